@@ -0,0 +1,399 @@
+use std::str;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use failure::Error;
+
+use packet::Packet;
+use types::{AlgorithmError, HashAlgorithm};
+
+const CRC24_INIT: u32 = 0x00B7_04CE;
+const CRC24_POLY: u32 = 0x0186_4CFB;
+
+// Armored data is wrapped at 64 characters per line, matching GnuPG.
+const LINE_LENGTH: usize = 64;
+
+/// Compute the CRC-24 checksum of some data, as specified in [RFC4880 &sect;6.1].
+///
+/// [RFC4880 &sect;6.1]: https://tools.ietf.org/html/rfc4880#section-6.1
+pub(crate) fn crc24(data: &[u8]) -> u32 {
+    let mut crc = CRC24_INIT;
+
+    for byte in data {
+        crc ^= u32::from(*byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+
+    crc & 0x00FF_FFFF
+}
+
+/// Split a single line off the front of some bytes, returning the line (without its line ending or
+/// any trailing whitespace) and the bytes following it.
+pub(crate) fn split_line(bytes: &[u8]) -> Result<(&str, &[u8]), Error> {
+    let (line, remaining) = match bytes.iter().position(|b| *b == b'\n') {
+        Some(idx) => (&bytes[..idx], &bytes[idx + 1..]),
+        None => (bytes, &bytes[bytes.len()..]),
+    };
+
+    Ok((str::from_utf8(line)?.trim_end(), remaining))
+}
+
+pub(crate) fn hash_algorithm_name(algo: HashAlgorithm) -> Result<&'static str, Error> {
+    let name = match algo {
+        HashAlgorithm::Md5 => "MD5",
+        HashAlgorithm::Sha1 => "SHA1",
+        HashAlgorithm::Ripemd160 => "RIPEMD160",
+        HashAlgorithm::Sha256 => "SHA256",
+        HashAlgorithm::Sha384 => "SHA384",
+        HashAlgorithm::Sha512 => "SHA512",
+        HashAlgorithm::Sha224 => "SHA224",
+        HashAlgorithm::Unknown => bail!(AlgorithmError::HashAlgorithmError),
+    };
+
+    Ok(name)
+}
+
+pub(crate) fn hash_algorithm_from_name(name: &str) -> HashAlgorithm {
+    match name.trim() {
+        "MD5" => HashAlgorithm::Md5,
+        "SHA1" => HashAlgorithm::Sha1,
+        "RIPEMD160" => HashAlgorithm::Ripemd160,
+        "SHA256" => HashAlgorithm::Sha256,
+        "SHA384" => HashAlgorithm::Sha384,
+        "SHA512" => HashAlgorithm::Sha512,
+        "SHA224" => HashAlgorithm::Sha224,
+        _ => HashAlgorithm::Unknown,
+    }
+}
+
+/// The kind of data contained in an armored block. See [RFC4880 &sect;6.2].
+///
+/// [RFC4880 &sect;6.2]: https://tools.ietf.org/html/rfc4880#section-6.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockType {
+    Message,
+    PublicKeyBlock,
+    PrivateKeyBlock,
+    Signature,
+}
+
+impl BlockType {
+    fn label(&self) -> &'static str {
+        match *self {
+            BlockType::Message => "MESSAGE",
+            BlockType::PublicKeyBlock => "PUBLIC KEY BLOCK",
+            BlockType::PrivateKeyBlock => "PRIVATE KEY BLOCK",
+            BlockType::Signature => "SIGNATURE",
+        }
+    }
+
+    fn from_label(label: &str) -> Option<BlockType> {
+        match label {
+            "MESSAGE" => Some(BlockType::Message),
+            "PUBLIC KEY BLOCK" => Some(BlockType::PublicKeyBlock),
+            "PRIVATE KEY BLOCK" => Some(BlockType::PrivateKeyBlock),
+            "SIGNATURE" => Some(BlockType::Signature),
+            _ => None,
+        }
+    }
+}
+
+/// A header line of an armored block. See [RFC4880 &sect;6.2].
+///
+/// [RFC4880 &sect;6.2]: https://tools.ietf.org/html/rfc4880#section-6.2
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArmorHeader {
+    Version(String),
+    Comment(String),
+    MessageId(String),
+    Hash(Vec<HashAlgorithm>),
+    Charset(String),
+    Unknown(String, String),
+}
+
+impl ArmorHeader {
+    pub(crate) fn from_line(line: &str) -> Result<ArmorHeader, Error> {
+        let (key, value) = match line.find(": ") {
+            Some(idx) => (&line[..idx], &line[idx + 2..]),
+            None => bail!(ArmorError::InvalidHeader {
+                reason: line.to_string(),
+            }),
+        };
+
+        let header = match key {
+            "Version" => ArmorHeader::Version(value.to_string()),
+            "Comment" => ArmorHeader::Comment(value.to_string()),
+            "MessageID" => ArmorHeader::MessageId(value.to_string()),
+            "Hash" => ArmorHeader::Hash(value.split(',').map(hash_algorithm_from_name).collect()),
+            "Charset" => ArmorHeader::Charset(value.to_string()),
+            _ => ArmorHeader::Unknown(key.to_string(), value.to_string()),
+        };
+
+        Ok(header)
+    }
+
+    pub(crate) fn to_line(&self) -> Result<String, Error> {
+        let line = match *self {
+            ArmorHeader::Version(ref value) => format!("Version: {}", value),
+            ArmorHeader::Comment(ref value) => format!("Comment: {}", value),
+            ArmorHeader::MessageId(ref value) => format!("MessageID: {}", value),
+            ArmorHeader::Hash(ref algos) => {
+                let names = algos
+                    .iter()
+                    .map(|algo| hash_algorithm_name(*algo))
+                    .collect::<Result<Vec<_>, _>>()?;
+                format!("Hash: {}", names.join(","))
+            }
+            ArmorHeader::Charset(ref value) => format!("Charset: {}", value),
+            ArmorHeader::Unknown(ref key, ref value) => format!("{}: {}", key, value),
+        };
+
+        Ok(line)
+    }
+}
+
+/// An ASCII-armored block of OpenPGP data, as specified in [RFC4880 &sect;6].
+///
+/// The `contents` of an armored block are the raw binary OpenPGP packets it encodes, which can be
+/// parsed with [`Armor::packets`].
+///
+/// [RFC4880 &sect;6]: https://tools.ietf.org/html/rfc4880#section-6
+/// [`Armor::packets`]: struct.Armor.html#method.packets
+#[derive(Clone, Debug)]
+pub struct Armor {
+    pub block_type: BlockType,
+    pub headers: Vec<ArmorHeader>,
+    pub contents: Vec<u8>,
+}
+
+impl Armor {
+    /// Create a new armored block with no headers.
+    pub fn new(block_type: BlockType, contents: Vec<u8>) -> Armor {
+        Armor {
+            block_type,
+            headers: Vec::new(),
+            contents,
+        }
+    }
+
+    /// Create a new armored block containing the serialized form of some packets.
+    pub fn from_packets(block_type: BlockType, packets: &[Packet]) -> Result<Armor, Error> {
+        let mut contents = Vec::new();
+        for packet in packets {
+            contents.extend(&packet.to_bytes()?);
+        }
+
+        Ok(Armor::new(block_type, contents))
+    }
+
+    /// Parse the packets contained in this armored block.
+    pub fn packets(&self) -> Result<Vec<Packet>, Error> {
+        Packet::all_from_bytes(&self.contents)
+    }
+
+    /// Read in an armored block from some bytes, returning the block and any bytes following it.
+    ///
+    /// Any text preceding the armor header line is ignored. If the block has a checksum, it must
+    /// match the decoded contents.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Armor, &[u8]), Error> {
+        let mut remaining = bytes;
+
+        let block_type = loop {
+            if remaining.is_empty() {
+                bail!(ArmorError::MissingHeaderLine);
+            }
+
+            let (line, rest) = split_line(remaining)?;
+            remaining = rest;
+
+            if line.starts_with("-----BEGIN PGP ") && line.ends_with("-----") && line.len() >= 20 {
+                let label = &line[15..line.len() - 5];
+                match BlockType::from_label(label) {
+                    Some(block_type) => break block_type,
+                    None => bail!(ArmorError::UnknownBlockType {
+                        label: label.to_string(),
+                    }),
+                }
+            }
+        };
+
+        let mut headers = Vec::new();
+        let mut body = String::new();
+        let mut checksum = None;
+        let mut in_headers = true;
+        let tail = format!("-----END PGP {}-----", block_type.label());
+
+        loop {
+            if remaining.is_empty() {
+                bail!(ArmorError::MissingTailLine);
+            }
+
+            let (line, rest) = split_line(remaining)?;
+            remaining = rest;
+
+            if line == tail {
+                break;
+            } else if line.starts_with("-----") {
+                bail!(ArmorError::MissingTailLine);
+            }
+
+            if in_headers {
+                if line.is_empty() {
+                    in_headers = false;
+                    continue;
+                } else if line.contains(':') {
+                    headers.push(ArmorHeader::from_line(line)?);
+                    continue;
+                }
+
+                // Some implementations omit the blank line when there are no headers.
+                in_headers = false;
+            }
+
+            if line.len() == 5 && line.starts_with('=') {
+                checksum = Some(line[1..].to_string());
+            } else if checksum.is_some() {
+                bail!(ArmorError::InvalidBase64 {
+                    reason: "data following checksum".to_string(),
+                });
+            } else {
+                body.push_str(line);
+            }
+        }
+
+        let contents = BASE64.decode(&body).map_err(|e| ArmorError::InvalidBase64 {
+            reason: format!("{}", e),
+        })?;
+
+        if let Some(checksum) = checksum {
+            let checksum = BASE64.decode(&checksum).map_err(|e| ArmorError::InvalidBase64 {
+                reason: format!("{}", e),
+            })?;
+
+            let crc = crc24(&contents);
+            if checksum != [(crc >> 16) as u8, (crc >> 8) as u8, crc as u8] {
+                bail!(ArmorError::BadChecksum);
+            }
+        }
+
+        let armor = Armor {
+            block_type,
+            headers,
+            contents,
+        };
+
+        Ok((armor, remaining))
+    }
+
+    /// Serialize this armored block to bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = String::new();
+
+        out.push_str(&format!("-----BEGIN PGP {}-----\n", self.block_type.label()));
+        for header in &self.headers {
+            out.push_str(&header.to_line()?);
+            out.push('\n');
+        }
+        out.push('\n');
+
+        let encoded = BASE64.encode(&self.contents);
+        for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+            out.push_str(str::from_utf8(line)?);
+            out.push('\n');
+        }
+
+        let crc = crc24(&self.contents);
+        out.push('=');
+        out.push_str(&BASE64.encode([(crc >> 16) as u8, (crc >> 8) as u8, crc as u8]));
+        out.push('\n');
+
+        out.push_str(&format!("-----END PGP {}-----\n", self.block_type.label()));
+
+        Ok(out.into_bytes())
+    }
+}
+
+/// Error type for [`Armor`]-level errors.
+///
+/// [`Armor`]: struct.Armor.html
+#[derive(Debug, Fail)]
+pub enum ArmorError {
+    #[fail(display = "No armor header line found")]
+    MissingHeaderLine,
+    #[fail(display = "Missing armor tail line")]
+    MissingTailLine,
+    #[fail(display = "Unknown armor block type: {}", label)]
+    UnknownBlockType { label: String },
+    #[fail(display = "Invalid armor header: {}", reason)]
+    InvalidHeader { reason: String },
+    #[fail(display = "Invalid base64 data: {}", reason)]
+    InvalidBase64 { reason: String },
+    #[fail(display = "Bad armor checksum")]
+    BadChecksum,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_KEY: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const ARMORED_PUBLIC_KEY: &[u8] = include_bytes!("../tests/data/ed.pub.asc");
+
+    #[test]
+    fn crc24_check_values() {
+        assert_eq!(crc24(b""), CRC24_INIT);
+        assert_eq!(crc24(b"123456789"), 0x0021_CF02);
+    }
+
+    #[test]
+    fn read_gnupg_armor() {
+        let (armor, remaining) = Armor::from_bytes(ARMORED_PUBLIC_KEY).unwrap();
+
+        assert_eq!(armor.block_type, BlockType::PublicKeyBlock);
+        assert!(armor.headers.is_empty());
+        assert_eq!(armor.contents, PUBLIC_KEY);
+        assert!(remaining.is_empty());
+        assert_eq!(armor.packets().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn write_matches_gnupg() {
+        let armor = Armor::new(BlockType::PublicKeyBlock, PUBLIC_KEY.to_vec());
+        assert_eq!(armor.to_bytes().unwrap(), ARMORED_PUBLIC_KEY);
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let mut armor = Armor::new(BlockType::Signature, b"signature".to_vec());
+        armor.headers.push(ArmorHeader::Comment("a comment".to_string()));
+        armor.headers.push(ArmorHeader::Hash(vec![HashAlgorithm::Sha256, HashAlgorithm::Sha512]));
+
+        let bytes = armor.to_bytes().unwrap();
+        let (read, _) = Armor::from_bytes(&bytes).unwrap();
+        assert_eq!(read.block_type, BlockType::Signature);
+        assert_eq!(read.headers, armor.headers);
+        assert_eq!(read.contents, armor.contents);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let armored = String::from_utf8(ARMORED_PUBLIC_KEY.to_vec()).unwrap();
+        let tampered = armored.replace("=QDNn", "=QDNo");
+
+        let err = Armor::from_bytes(tampered.as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), ArmorError::BadChecksum.to_string());
+    }
+
+    #[test]
+    fn missing_tail_line() {
+        let armored = &ARMORED_PUBLIC_KEY[..ARMORED_PUBLIC_KEY.len() - 36];
+
+        let err = Armor::from_bytes(armored).unwrap_err();
+        assert_eq!(err.to_string(), ArmorError::MissingTailLine.to_string());
+    }
+}
@@ -0,0 +1,600 @@
+use std::io::Read;
+
+use failure::Error;
+
+use key::{Key, SecretKeyStatus};
+use packet::Packet;
+use reader::{PacketReader, StreamedPacket};
+use signature::SignaturePacket;
+
+/// A user ID on a [`Certificate`], along with the signatures certifying it.
+///
+/// [`Certificate`]: struct.Certificate.html
+#[derive(Clone, Debug)]
+pub struct UserIdComponent {
+    pub user_id: String,
+    pub signatures: Vec<SignaturePacket>,
+}
+
+/// A user attribute on a [`Certificate`], such as a photo ID, along with the signatures
+/// certifying it. The attribute is kept as the raw contents of its packet.
+///
+/// [`Certificate`]: struct.Certificate.html
+#[derive(Clone, Debug)]
+pub struct UserAttributeComponent {
+    pub attribute: Vec<u8>,
+    pub signatures: Vec<SignaturePacket>,
+}
+
+/// A subkey on a [`Certificate`], along with its binding and revocation signatures.
+///
+/// [`Certificate`]: struct.Certificate.html
+#[derive(Clone, Debug)]
+pub struct SubkeyComponent {
+    pub key: Key,
+    pub signatures: Vec<SignaturePacket>,
+}
+
+/// An OpenPGP certificate, or transferable public key: a primary key together with its user IDs,
+/// user attributes and subkeys, and the signatures binding them all together. See
+/// [RFC4880 &sect;11.1].
+///
+/// Certificates are assembled from the packets of a key, such as those returned by
+/// [`Packet::all_from_bytes`] for the output of `gpg --export`. Trust packets, which are only
+/// meaningful to the keyring they came from, are dropped.
+///
+/// [RFC4880 &sect;11.1]: https://tools.ietf.org/html/rfc4880#section-11.1
+/// [`Packet::all_from_bytes`]: enum.Packet.html#method.all_from_bytes
+#[derive(Clone, Debug)]
+pub struct Certificate {
+    pub primary: Key,
+    /// Signatures directly over the primary key, such as direct-key signatures and key
+    /// revocations.
+    pub signatures: Vec<SignaturePacket>,
+    pub user_ids: Vec<UserIdComponent>,
+    pub user_attributes: Vec<UserAttributeComponent>,
+    pub subkeys: Vec<SubkeyComponent>,
+}
+
+// The component that signatures read while assembling a certificate belong to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Position {
+    Primary,
+    UserId,
+    UserAttribute,
+    Subkey,
+}
+
+impl Certificate {
+    /// Create a certificate holding only a primary key.
+    pub fn new(primary: Key) -> Certificate {
+        Certificate {
+            primary,
+            signatures: Vec::new(),
+            user_ids: Vec::new(),
+            user_attributes: Vec::new(),
+            subkeys: Vec::new(),
+        }
+    }
+
+    /// Assemble a single certificate from its packets.
+    pub fn from_packets<I: IntoIterator<Item = Packet>>(packets: I) -> Result<Certificate, Error> {
+        let mut certificates = Certificate::all_from_packets(packets)?;
+        if certificates.len() > 1 {
+            return Err(CertificateError::MultipleCertificates.into());
+        }
+
+        match certificates.pop() {
+            Some(certificate) => Ok(certificate),
+            None => Err(CertificateError::MissingPrimaryKey.into()),
+        }
+    }
+
+    /// Assemble all of the certificates in a keyring from its packets.
+    ///
+    /// Packets that are out of place, or don't belong in a certificate at all, are reported as a
+    /// [`CertificateError::UnexpectedPacket`] carrying their tag.
+    ///
+    /// [`CertificateError::UnexpectedPacket`]: enum.CertificateError.html#variant.UnexpectedPacket
+    pub fn all_from_packets<I: IntoIterator<Item = Packet>>(
+        packets: I,
+    ) -> Result<Vec<Certificate>, Error> {
+        assemble(packets.into_iter().map(Ok), false)
+    }
+
+    /// Parse a single certificate from binary data.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Certificate, Error> {
+        Certificate::from_packets(Packet::all_from_bytes(bytes)?)
+    }
+
+    /// Read all of the certificates in a keyring from a stream, one packet at a time.
+    pub fn all_from_reader<R: Read>(reader: R) -> Result<Vec<Certificate>, Error> {
+        assemble(read_packets(reader), false)
+    }
+
+    /// The packets making up this certificate, in the canonical order given by RFC4880: the
+    /// primary key and its signatures, then each user ID, user attribute and subkey followed by
+    /// its signatures.
+    ///
+    /// Any secret key material the keys hold is left out.
+    pub fn to_packets(&self) -> Vec<Packet> {
+        self.packets_with_keys(
+            |key| Packet::PublicKey(key.to_public()),
+            |key| Packet::PublicSubkey(key.to_public()),
+        )
+    }
+
+    /// Serialize this certificate, in the same order as [`to_packets`].
+    ///
+    /// [`to_packets`]: #method.to_packets
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        for packet in self.to_packets() {
+            out.extend(&packet.to_bytes()?);
+        }
+
+        Ok(out)
+    }
+
+    // The packets making up this certificate, with the primary key and subkeys wrapped in packets
+    // by `primary` and `subkey`.
+    fn packets_with_keys<P, S>(&self, primary: P, subkey: S) -> Vec<Packet>
+    where
+        P: Fn(&Key) -> Packet,
+        S: Fn(&Key) -> Packet,
+    {
+        let mut packets = vec![primary(&self.primary)];
+        packets.extend(self.signatures.iter().cloned().map(Packet::Signature));
+
+        for user_id in &self.user_ids {
+            packets.push(Packet::UserId(user_id.user_id.clone()));
+            packets.extend(user_id.signatures.iter().cloned().map(Packet::Signature));
+        }
+
+        for attribute in &self.user_attributes {
+            packets.push(Packet::UserAttribute(attribute.attribute.clone()));
+            packets.extend(attribute.signatures.iter().cloned().map(Packet::Signature));
+        }
+
+        for component in &self.subkeys {
+            packets.push(subkey(&component.key));
+            packets.extend(component.signatures.iter().cloned().map(Packet::Signature));
+        }
+
+        packets
+    }
+
+    fn signatures_at(&mut self, position: Position) -> &mut Vec<SignaturePacket> {
+        match position {
+            Position::UserId => self.user_ids.last_mut().map(|c| &mut c.signatures),
+            Position::UserAttribute => self.user_attributes.last_mut().map(|c| &mut c.signatures),
+            Position::Subkey => self.subkeys.last_mut().map(|c| &mut c.signatures),
+            Position::Primary => None,
+        }.unwrap_or(&mut self.signatures)
+    }
+}
+
+/// A transferable secret key: a [`Certificate`] whose primary key and subkeys were read from
+/// Secret-Key and Secret-Subkey packets, as output by `gpg --export-secret-keys`. See
+/// [RFC4880 &sect;11.2].
+///
+/// Secret key material may be missing from any of the keys, or replaced with a GnuPG stub; use
+/// [`secret_keys`] to find out which keys can actually be used.
+///
+/// [`Certificate`]: struct.Certificate.html
+/// [RFC4880 &sect;11.2]: https://tools.ietf.org/html/rfc4880#section-11.2
+/// [`secret_keys`]: #method.secret_keys
+#[derive(Clone, Debug)]
+pub struct SecretCertificate {
+    certificate: Certificate,
+}
+
+impl SecretCertificate {
+    /// Assemble a single secret certificate from its packets.
+    pub fn from_packets<I: IntoIterator<Item = Packet>>(
+        packets: I,
+    ) -> Result<SecretCertificate, Error> {
+        let mut certificates = SecretCertificate::all_from_packets(packets)?;
+        if certificates.len() > 1 {
+            return Err(CertificateError::MultipleCertificates.into());
+        }
+
+        match certificates.pop() {
+            Some(certificate) => Ok(certificate),
+            None => Err(CertificateError::MissingPrimaryKey.into()),
+        }
+    }
+
+    /// Assemble all of the secret certificates in a keyring from its packets.
+    pub fn all_from_packets<I: IntoIterator<Item = Packet>>(
+        packets: I,
+    ) -> Result<Vec<SecretCertificate>, Error> {
+        Ok(assemble(packets.into_iter().map(Ok), true)?
+            .into_iter()
+            .map(|certificate| SecretCertificate { certificate })
+            .collect())
+    }
+
+    /// Parse a single secret certificate from binary data.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SecretCertificate, Error> {
+        SecretCertificate::from_packets(Packet::all_from_bytes(bytes)?)
+    }
+
+    /// Read all of the secret certificates in a keyring from a stream, one packet at a time.
+    pub fn all_from_reader<R: Read>(reader: R) -> Result<Vec<SecretCertificate>, Error> {
+        Ok(assemble(read_packets(reader), true)?
+            .into_iter()
+            .map(|certificate| SecretCertificate { certificate })
+            .collect())
+    }
+
+    /// The status of the secret key material of the primary key, followed by each subkey.
+    pub fn secret_keys(&self) -> Vec<(&Key, SecretKeyStatus)> {
+        let primary = &self.certificate.primary;
+        let subkeys = self.certificate.subkeys.iter().map(|subkey| &subkey.key);
+
+        Some(primary)
+            .into_iter()
+            .chain(subkeys)
+            .map(|key| (key, key.secret_key_status()))
+            .collect()
+    }
+
+    /// The public certificate corresponding to this secret certificate, with all secret key
+    /// material removed. This is the only way to get at the certificate's user IDs, signatures
+    /// and subkey bindings, so that secret key material can't be exported by accident.
+    pub fn to_public(&self) -> Certificate {
+        let mut certificate = self.certificate.clone();
+        certificate.primary = certificate.primary.to_public();
+        for subkey in &mut certificate.subkeys {
+            subkey.key = subkey.key.to_public();
+        }
+
+        certificate
+    }
+
+    /// The packets making up this secret certificate, in the same order as
+    /// [`Certificate::to_packets`]. Subkeys without any secret key material are output as public
+    /// subkeys, as GnuPG does.
+    ///
+    /// [`Certificate::to_packets`]: struct.Certificate.html#method.to_packets
+    pub fn to_packets(&self) -> Vec<Packet> {
+        self.certificate.packets_with_keys(
+            |key| Packet::SecretKey(key.clone()),
+            |key| match key.secret_key_status() {
+                SecretKeyStatus::Missing => Packet::PublicSubkey(key.clone()),
+                _ => Packet::SecretSubkey(key.clone()),
+            },
+        )
+    }
+
+    /// Serialize this secret certificate, in the same order as [`to_packets`].
+    ///
+    /// [`to_packets`]: #method.to_packets
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        for packet in self.to_packets() {
+            out.extend(&packet.to_bytes()?);
+        }
+
+        Ok(out)
+    }
+}
+
+// Read the packets of a keyring from a stream, rejecting any packets holding streamed data.
+fn read_packets<R: Read>(reader: R) -> impl Iterator<Item = Result<Packet, Error>> {
+    PacketReader::new(reader).map(|packet| match packet? {
+        StreamedPacket::Packet(packet) => Ok(packet),
+        StreamedPacket::LiteralData(_, _) => {
+            Err(CertificateError::UnexpectedPacket { tag: 11 }.into())
+        }
+        StreamedPacket::CompressedData(_, _) => {
+            Err(CertificateError::UnexpectedPacket { tag: 8 }.into())
+        }
+        StreamedPacket::SymmetricEncryptedData(_) => {
+            Err(CertificateError::UnexpectedPacket { tag: 9 }.into())
+        }
+        StreamedPacket::SymmetricEncryptedIntegrityProtectedData(_) => {
+            Err(CertificateError::UnexpectedPacket { tag: 18 }.into())
+        }
+    })
+}
+
+// Group packets into certificates. With `secret` set, primary keys and subkeys are expected in
+// Secret-Key and Secret-Subkey packets instead, although public subkeys are still accepted.
+fn assemble<I: Iterator<Item = Result<Packet, Error>>>(
+    packets: I,
+    secret: bool,
+) -> Result<Vec<Certificate>, Error> {
+    let mut certificates: Vec<Certificate> = Vec::new();
+    let mut position = Position::Primary;
+
+    for packet in packets {
+        let packet = packet?;
+        let primary = match packet {
+            Packet::PublicKey(key) if !secret => Ok(key),
+            Packet::SecretKey(key) if secret => Ok(key),
+            packet => Err(packet),
+        };
+        let packet = match primary {
+            Ok(key) => {
+                certificates.push(Certificate::new(key));
+                position = Position::Primary;
+                continue;
+            }
+            Err(packet) => packet,
+        };
+
+        let certificate = match certificates.last_mut() {
+            Some(certificate) => certificate,
+            None => match packet {
+                Packet::Trust => continue,
+                packet => return Err(CertificateError::UnexpectedPacket {
+                    tag: packet.packet_tag(),
+                }.into()),
+            },
+        };
+
+        match packet {
+            Packet::Signature(signature) => certificate.signatures_at(position).push(signature),
+            Packet::UserId(user_id) => {
+                certificate.user_ids.push(UserIdComponent {
+                    user_id,
+                    signatures: Vec::new(),
+                });
+                position = Position::UserId;
+            }
+            Packet::UserAttribute(attribute) => {
+                certificate.user_attributes.push(UserAttributeComponent {
+                    attribute,
+                    signatures: Vec::new(),
+                });
+                position = Position::UserAttribute;
+            }
+            Packet::PublicSubkey(key) => {
+                certificate.subkeys.push(SubkeyComponent {
+                    key,
+                    signatures: Vec::new(),
+                });
+                position = Position::Subkey;
+            }
+            Packet::SecretSubkey(key) if secret => {
+                certificate.subkeys.push(SubkeyComponent {
+                    key,
+                    signatures: Vec::new(),
+                });
+                position = Position::Subkey;
+            }
+            Packet::Trust => {}
+            packet => return Err(CertificateError::UnexpectedPacket {
+                tag: packet.packet_tag(),
+            }.into()),
+        }
+    }
+
+    Ok(certificates)
+}
+
+/// Error type for [`Certificate`]-level errors.
+///
+/// [`Certificate`]: struct.Certificate.html
+#[derive(Debug, Fail)]
+pub enum CertificateError {
+    #[fail(display = "Certificate does not start with a primary key")]
+    MissingPrimaryKey,
+    #[fail(display = "Expected a single certificate, but found several")]
+    MultipleCertificates,
+    #[fail(display = "Unexpected packet with tag {} in certificate", tag)]
+    UnexpectedPacket { tag: u8 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use key::SecretKeyStatus;
+    use signature::SignatureType;
+
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const RSA_CERTIFICATE: &[u8] = include_bytes!("../tests/data/rsa.pub");
+    const DSA_CERTIFICATE: &[u8] = include_bytes!("../tests/data/dsa.pub");
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/ed.signed.gpg");
+    const ED_SECRET_KEY: &[u8] = include_bytes!("../tests/data/ed.sec");
+    const SUBKEY_CERTIFICATE: &[u8] = include_bytes!("../tests/data/sub.pub");
+    const SUBKEY_SECRET_KEY: &[u8] = include_bytes!("../tests/data/sub.sec");
+    const STUB_SECRET_KEY: &[u8] = include_bytes!("../tests/data/sub.stub.sec");
+    const CARD_SECRET_KEY: &[u8] = include_bytes!("../tests/data/sub.card.sec");
+
+    fn certificate_error(err: Error) -> CertificateError {
+        match err.downcast::<CertificateError>() {
+            Ok(err) => err,
+            Err(err) => panic!("expected a certificate error, found {}", err),
+        }
+    }
+
+    #[test]
+    fn read_gnupg_certificate() {
+        let certificate = Certificate::from_bytes(ED_CERTIFICATE).unwrap();
+
+        assert_eq!(certificate.primary.id().unwrap(), 0xFAB9_C2B7_EE84_CBA8);
+        assert!(certificate.signatures.is_empty());
+        assert_eq!(certificate.user_ids.len(), 1);
+        assert_eq!(certificate.user_ids[0].user_id, "Ed Test <ed@example.com>");
+        assert_eq!(certificate.user_ids[0].signatures.len(), 1);
+        assert!(certificate.user_attributes.is_empty());
+        assert_eq!(certificate.subkeys.len(), 1);
+        assert_eq!(certificate.subkeys[0].key.id().unwrap(), 0xD88B_EE62_C596_6048);
+        assert_eq!(
+            certificate.subkeys[0].signatures[0].sig_type,
+            SignatureType::SubkeyBinding
+        );
+    }
+
+    #[test]
+    fn gnupg_round_trip() {
+        for &bytes in &[ED_CERTIFICATE, RSA_CERTIFICATE, DSA_CERTIFICATE] {
+            let certificate = Certificate::from_bytes(bytes).unwrap();
+            assert_eq!(certificate.to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn canonical_order() {
+        let packets = Packet::all_from_bytes(ED_CERTIFICATE).unwrap();
+        let attribute = vec![0x01, 0x02, 0x03];
+
+        // Put the subkey first, and add a user attribute after it.
+        let mut reordered = vec![packets[0].clone()];
+        reordered.extend(packets[3..].iter().cloned());
+        reordered.push(Packet::UserAttribute(attribute.clone()));
+        reordered.extend(packets[1..3].iter().cloned());
+
+        let certificate = Certificate::from_packets(reordered).unwrap();
+        assert_eq!(certificate.user_attributes[0].attribute, attribute);
+        assert!(certificate.user_attributes[0].signatures.is_empty());
+        assert_eq!(certificate.user_ids[0].signatures.len(), 1);
+        assert_eq!(certificate.subkeys[0].signatures.len(), 1);
+
+        let mut expected = packets[..3].to_vec();
+        expected.push(Packet::UserAttribute(attribute));
+        expected.extend(packets[3..].iter().cloned());
+
+        let expected = expected.iter().flat_map(|p| p.to_bytes().unwrap()).collect::<Vec<_>>();
+        assert_eq!(certificate.to_bytes().unwrap(), expected);
+    }
+
+    #[test]
+    fn read_keyring() {
+        let mut keyring = ED_CERTIFICATE.to_vec();
+        keyring.push(0xCC); // A trust packet, as in GnuPG keyrings.
+        keyring.push(0x00);
+        keyring.extend(RSA_CERTIFICATE);
+
+        let certificates = Certificate::all_from_reader(&keyring[..]).unwrap();
+        assert_eq!(certificates.len(), 2);
+        assert_eq!(certificates[0].to_bytes().unwrap(), ED_CERTIFICATE);
+        assert_eq!(certificates[1].to_bytes().unwrap(), RSA_CERTIFICATE);
+
+        match certificate_error(Certificate::from_bytes(&keyring).unwrap_err()) {
+            CertificateError::MultipleCertificates => {}
+            err => panic!("expected multiple certificates, found {:?}", err),
+        }
+    }
+
+    #[test]
+    fn missing_primary_key() {
+        match certificate_error(Certificate::from_packets(Vec::new()).unwrap_err()) {
+            CertificateError::MissingPrimaryKey => {}
+            err => panic!("expected a missing primary key, found {:?}", err),
+        }
+
+        // A certificate can't start with its user ID.
+        let packets = Packet::all_from_bytes(ED_CERTIFICATE).unwrap();
+        let err = Certificate::from_packets(packets[1..].to_vec()).unwrap_err();
+        match certificate_error(err) {
+            CertificateError::UnexpectedPacket { tag } => assert_eq!(tag, 13),
+            err => panic!("expected an unexpected packet, found {:?}", err),
+        }
+    }
+
+    #[test]
+    fn reject_message() {
+        match certificate_error(Certificate::all_from_reader(MESSAGE).unwrap_err()) {
+            CertificateError::UnexpectedPacket { tag } => assert_eq!(tag, 4),
+            err => panic!("expected an unexpected packet, found {:?}", err),
+        }
+    }
+
+    fn statuses(certificate: &SecretCertificate) -> Vec<SecretKeyStatus> {
+        certificate
+            .secret_keys()
+            .into_iter()
+            .map(|(_, status)| status)
+            .collect()
+    }
+
+    #[test]
+    fn secret_round_trip() {
+        for &bytes in &[ED_SECRET_KEY, SUBKEY_SECRET_KEY, STUB_SECRET_KEY, CARD_SECRET_KEY] {
+            let certificate = SecretCertificate::from_bytes(bytes).unwrap();
+            assert_eq!(certificate.to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn secret_key_status() {
+        let certificate = SecretCertificate::from_bytes(ED_SECRET_KEY).unwrap();
+        assert_eq!(
+            statuses(&certificate),
+            vec![SecretKeyStatus::Unencrypted, SecretKeyStatus::Unencrypted]
+        );
+
+        let certificate = SecretCertificate::from_bytes(SUBKEY_SECRET_KEY).unwrap();
+        assert_eq!(statuses(&certificate), vec![SecretKeyStatus::Encrypted; 3]);
+
+        let certificate = SecretCertificate::from_bytes(STUB_SECRET_KEY).unwrap();
+        assert_eq!(
+            statuses(&certificate),
+            vec![
+                SecretKeyStatus::GnuDummy,
+                SecretKeyStatus::Encrypted,
+                SecretKeyStatus::Encrypted,
+            ]
+        );
+
+        let certificate = SecretCertificate::from_bytes(CARD_SECRET_KEY).unwrap();
+        assert_eq!(
+            statuses(&certificate)[0],
+            SecretKeyStatus::GnuDivertToCard(vec![0xD2, 0x76, 0x00, 0x01])
+        );
+    }
+
+    #[test]
+    fn secret_to_public() {
+        for &(secret, public) in &[
+            (ED_SECRET_KEY, ED_CERTIFICATE),
+            (SUBKEY_SECRET_KEY, SUBKEY_CERTIFICATE),
+            (STUB_SECRET_KEY, SUBKEY_CERTIFICATE),
+            (CARD_SECRET_KEY, SUBKEY_CERTIFICATE),
+        ] {
+            let certificate = SecretCertificate::from_bytes(secret).unwrap().to_public();
+            assert_eq!(certificate.to_bytes().unwrap(), public);
+            assert!(
+                certificate
+                    .subkeys
+                    .iter()
+                    .all(|subkey| subkey.key.secret_key_status() == SecretKeyStatus::Missing)
+            );
+        }
+    }
+
+    #[test]
+    fn public_export_leaves_out_secrets() {
+        let secret = SecretCertificate::from_bytes(ED_SECRET_KEY).unwrap();
+        assert_eq!(secret.certificate.to_bytes().unwrap(), ED_CERTIFICATE);
+    }
+
+    #[test]
+    fn secret_certificate_with_public_subkey() {
+        let mut packets = Packet::all_from_bytes(ED_SECRET_KEY).unwrap();
+        packets[3] = match packets[3] {
+            Packet::SecretSubkey(ref key) => Packet::PublicSubkey(key.to_public()),
+            ref p => panic!("expected a secret subkey, found {:?}", p),
+        };
+        let bytes = packets.iter().flat_map(|p| p.to_bytes().unwrap()).collect::<Vec<_>>();
+
+        let certificate = SecretCertificate::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            statuses(&certificate),
+            vec![SecretKeyStatus::Unencrypted, SecretKeyStatus::Missing]
+        );
+        assert_eq!(certificate.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn reject_public_certificate() {
+        match certificate_error(SecretCertificate::from_bytes(ED_CERTIFICATE).unwrap_err()) {
+            CertificateError::UnexpectedPacket { tag } => assert_eq!(tag, 6),
+            err => panic!("expected an unexpected packet, found {:?}", err),
+        }
+    }
+}
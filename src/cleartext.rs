@@ -0,0 +1,310 @@
+use std::str;
+
+use failure::Error;
+
+use armor::{split_line, Armor, ArmorHeader, BlockType};
+use packet::Packet;
+use signature::{SignaturePacket, SignatureType};
+use types::HashAlgorithm;
+
+const HEADER_LINE: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+const SIGNATURE_LINE: &str = "-----BEGIN PGP SIGNATURE-----";
+
+/// Convert some text to the canonical form used when signing cleartext messages: trailing
+/// whitespace is removed from each line, and lines are joined with `<CR><LF>`.
+pub(crate) fn canonicalize_cleartext(text: &str) -> Vec<u8> {
+    let lines = text.split('\n')
+        .map(|line| line.trim_end_matches(&[' ', '\t', '\r'][..]))
+        .collect::<Vec<_>>();
+
+    lines.join("\r\n").into_bytes()
+}
+
+/// A cleartext signed message, as specified in [RFC4880 &sect;7].
+///
+/// The `text` of a message is stored with dash-escaping removed and `\n` line endings. The data
+/// covered by its signatures is available from [`canonical_text`].
+///
+/// [RFC4880 &sect;7]: https://tools.ietf.org/html/rfc4880#section-7
+/// [`canonical_text`]: #method.canonical_text
+#[derive(Clone, Debug)]
+pub struct CleartextSignedMessage {
+    pub text: String,
+    pub signatures: Vec<SignaturePacket>,
+}
+
+impl CleartextSignedMessage {
+    /// Create a new cleartext signed message.
+    ///
+    /// Signatures for the message should be of type [`SignatureType::TextDocument`], and built by
+    /// passing [`canonical_text`] to [`SignaturePacket::signable_payload`].
+    ///
+    /// [`SignatureType::TextDocument`]: enum.SignatureType.html#variant.TextDocument
+    /// [`canonical_text`]: #method.canonical_text
+    /// [`SignaturePacket::signable_payload`]: struct.SignaturePacket.html#method.signable_payload
+    pub fn new<T: AsRef<str>>(text: T, signatures: Vec<SignaturePacket>) -> CleartextSignedMessage {
+        CleartextSignedMessage {
+            text: text.as_ref().replace("\r\n", "\n"),
+            signatures,
+        }
+    }
+
+    /// Retrieve the canonical form of this message's text, i.e. the data its signatures cover.
+    pub fn canonical_text(&self) -> Vec<u8> {
+        canonicalize_cleartext(&self.text)
+    }
+
+    /// Retrieve the hash algorithms used by this message's signatures, in the order they are first
+    /// used.
+    pub fn hash_algorithms(&self) -> Vec<HashAlgorithm> {
+        let mut algos = Vec::new();
+        for signature in &self.signatures {
+            if !algos.contains(&signature.hash_algo) {
+                algos.push(signature.hash_algo);
+            }
+        }
+
+        algos
+    }
+
+    /// Read in a cleartext signed message from some bytes, returning the message and any bytes
+    /// following it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(CleartextSignedMessage, &[u8]), Error> {
+        let mut remaining = bytes;
+
+        loop {
+            if remaining.is_empty() {
+                bail!(CleartextError::MissingHeaderLine);
+            }
+
+            let (line, rest) = split_line(remaining)?;
+            remaining = rest;
+
+            if line == HEADER_LINE {
+                break;
+            }
+        }
+
+        // Messages without a Hash header use MD5.
+        let mut declared_algos = Vec::new();
+        loop {
+            let (line, rest) = split_line(remaining)?;
+            remaining = rest;
+
+            if line.is_empty() {
+                break;
+            }
+
+            match ArmorHeader::from_line(line)? {
+                ArmorHeader::Hash(algos) => declared_algos.extend(algos),
+                _ => bail!(CleartextError::InvalidHeader {
+                    reason: line.to_string(),
+                }),
+            }
+        }
+        if declared_algos.is_empty() {
+            declared_algos.push(HashAlgorithm::Md5);
+        }
+
+        let mut lines = Vec::new();
+        loop {
+            if remaining.is_empty() {
+                bail!(CleartextError::MissingSignature);
+            }
+
+            let (line, rest) = match remaining.iter().position(|b| *b == b'\n') {
+                Some(idx) => (&remaining[..idx], &remaining[idx + 1..]),
+                None => (remaining, &remaining[remaining.len()..]),
+            };
+            let line = str::from_utf8(line)?.trim_end_matches('\r');
+
+            if line.trim_end() == SIGNATURE_LINE {
+                break;
+            }
+
+            match line.strip_prefix("- ") {
+                Some(unescaped) => lines.push(unescaped),
+                None => lines.push(line),
+            }
+
+            remaining = rest;
+        }
+
+        let (armor, remaining) = Armor::from_bytes(remaining)?;
+        if armor.block_type != BlockType::Signature {
+            bail!(CleartextError::MissingSignature);
+        }
+
+        let mut signatures = Vec::new();
+        for packet in armor.packets()? {
+            match packet {
+                Packet::Signature(signature) => {
+                    // RFC4880, Section 7 requires text signatures over the canonical text.
+                    if signature.sig_type != SignatureType::TextDocument {
+                        bail!(CleartextError::WrongSignatureType {
+                            sig_type: signature.sig_type,
+                        });
+                    }
+
+                    if !declared_algos.contains(&signature.hash_algo) {
+                        bail!(CleartextError::UndeclaredHash {
+                            hash_algo: signature.hash_algo,
+                        });
+                    }
+
+                    signatures.push(signature);
+                }
+                p => bail!(CleartextError::UnexpectedPacket {
+                    packet_type: format!("{:?}", p),
+                }),
+            }
+        }
+
+        let message = CleartextSignedMessage {
+            text: lines.join("\n"),
+            signatures,
+        };
+
+        Ok((message, remaining))
+    }
+
+    /// Serialize this message to bytes, dash-escaping its text as necessary.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+
+        out.extend(HEADER_LINE.as_bytes());
+        out.push(b'\n');
+
+        let algos = self.hash_algorithms();
+        if !algos.is_empty() {
+            out.extend(ArmorHeader::Hash(algos).to_line()?.as_bytes());
+            out.push(b'\n');
+        }
+        out.push(b'\n');
+
+        for line in self.text.split('\n') {
+            if line.starts_with('-') {
+                out.extend(b"- ");
+            }
+            out.extend(line.as_bytes());
+            out.push(b'\n');
+        }
+
+        let signatures = self.signatures
+            .iter()
+            .map(|signature| Packet::Signature(signature.clone()))
+            .collect::<Vec<_>>();
+        out.extend(&Armor::from_packets(BlockType::Signature, &signatures)?.to_bytes()?);
+
+        Ok(out)
+    }
+}
+
+/// Error type for [`CleartextSignedMessage`]-level errors.
+///
+/// [`CleartextSignedMessage`]: struct.CleartextSignedMessage.html
+#[derive(Debug, Fail)]
+pub enum CleartextError {
+    #[fail(display = "No cleartext header line found")]
+    MissingHeaderLine,
+    #[fail(display = "Invalid cleartext header: {}", reason)]
+    InvalidHeader { reason: String },
+    #[fail(display = "Missing cleartext signature block")]
+    MissingSignature,
+    #[fail(display = "Signature uses undeclared hash algorithm: {:?}", hash_algo)]
+    UndeclaredHash { hash_algo: HashAlgorithm },
+    #[fail(display = "Cleartext signature has type {:?} instead of a text signature", sig_type)]
+    WrongSignatureType { sig_type: SignatureType },
+    #[fail(display = "Unexpected packet in cleartext signature: {}", packet_type)]
+    UnexpectedPacket { packet_type: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use certificate::Certificate;
+
+    const CLEARTEXT: &[u8] = include_bytes!("../tests/data/ed.clear.asc");
+    const SHA512_CLEARTEXT: &[u8] = include_bytes!("../tests/data/rsa.clear.asc");
+    const BINARY_SIGNATURE: &[u8] = include_bytes!("../tests/data/ed.sig");
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const RSA_CERTIFICATE: &[u8] = include_bytes!("../tests/data/rsa.pub");
+
+    const TEXT: &str = "Dear sir,\n- dashed line\nFrom the other side \nRegards";
+
+    #[test]
+    fn read_gnupg_message() {
+        let (message, remaining) = CleartextSignedMessage::from_bytes(CLEARTEXT).unwrap();
+
+        assert_eq!(message.text, TEXT);
+        assert_eq!(
+            message.canonical_text(),
+            b"Dear sir,\r\n- dashed line\r\nFrom the other side\r\nRegards"
+        );
+        assert_eq!(message.hash_algorithms(), vec![HashAlgorithm::Sha256]);
+        assert!(remaining.is_empty());
+
+        let key = Certificate::from_bytes(ED_CERTIFICATE).unwrap().primary;
+        assert_eq!(message.signatures.len(), 1);
+        message.signatures[0].verify(message.canonical_text(), &key).unwrap();
+    }
+
+    #[test]
+    fn read_sha512_message() {
+        let (message, _) = CleartextSignedMessage::from_bytes(SHA512_CLEARTEXT).unwrap();
+        assert_eq!(message.hash_algorithms(), vec![HashAlgorithm::Sha512]);
+
+        let key = Certificate::from_bytes(RSA_CERTIFICATE).unwrap().primary;
+        message.signatures[0].verify(message.canonical_text(), &key).unwrap();
+    }
+
+    #[test]
+    fn modified_text_fails_verification() {
+        let (mut message, _) = CleartextSignedMessage::from_bytes(CLEARTEXT).unwrap();
+        message.text = message.text.replace("Regards", "Regrets");
+
+        let key = Certificate::from_bytes(ED_CERTIFICATE).unwrap().primary;
+        assert!(message.signatures[0].verify(message.canonical_text(), &key).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let (message, _) = CleartextSignedMessage::from_bytes(CLEARTEXT).unwrap();
+
+        let bytes = message.to_bytes().unwrap();
+        let (read, _) = CleartextSignedMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(read.text, message.text);
+        assert_eq!(read.signatures.len(), 1);
+        assert_eq!(
+            read.signatures[0].to_bytes().unwrap(),
+            message.signatures[0].to_bytes().unwrap()
+        );
+    }
+
+    #[test]
+    fn reject_undeclared_hash() {
+        let cleartext = String::from_utf8(CLEARTEXT.to_vec()).unwrap();
+        let cleartext = cleartext.replace("Hash: SHA256", "Hash: SHA512");
+
+        let err = CleartextSignedMessage::from_bytes(cleartext.as_bytes()).unwrap_err();
+        let expected = CleartextError::UndeclaredHash {
+            hash_algo: HashAlgorithm::Sha256,
+        };
+        assert_eq!(err.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn reject_binary_signature() {
+        let signature = match Packet::from_bytes(BINARY_SIGNATURE).unwrap().0 {
+            Packet::Signature(signature) => signature,
+            p => panic!("expected a signature, found {:?}", p),
+        };
+        let message = CleartextSignedMessage::new(TEXT, vec![signature]);
+
+        let err = CleartextSignedMessage::from_bytes(&message.to_bytes().unwrap()).unwrap_err();
+        let expected = CleartextError::WrongSignatureType {
+            sig_type: SignatureType::BinaryDocument,
+        };
+        assert_eq!(err.to_string(), expected.to_string());
+    }
+}
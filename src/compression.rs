@@ -1,13 +1,16 @@
+use std::io;
 use std::io::prelude::*;
 
 use bzip2::read::{BzDecoder, BzEncoder};
+use bzip2::write::BzEncoder as BzWriteEncoder;
 use failure::Error;
 use flate2::read::{DeflateDecoder, DeflateEncoder, ZlibDecoder, ZlibEncoder};
+use flate2::write;
 use nom::{be_u8, rest};
 use nom::{ErrorKind, IResult};
 use nom::Err as NomErr;
 
-use types::NomError;
+use types::{AlgorithmError, CompressionAlgorithm, NomError};
 
 named!(uncompressed_data<Result<CompressedDataPacket, Error>>,
     map!(rest, |x| Ok(CompressedDataPacket::Uncompressed(Vec::from(x))))
@@ -121,6 +124,110 @@ impl CompressedDataPacket {
     }
 }
 
+/// A streaming decompressor for the body of a Compressed Data packet, yielding the packets it
+/// contains. See [`PacketReader`].
+///
+/// [`PacketReader`]: struct.PacketReader.html
+pub enum Decompressor<R: Read> {
+    Uncompressed(R),
+    Zip(DeflateDecoder<R>),
+    Zlib(ZlibDecoder<R>),
+    Bzip2(BzDecoder<R>),
+}
+
+impl<R: Read> Decompressor<R> {
+    /// Create a decompressor reading compressed data of type `algo` from `inner`.
+    pub fn new(algo: CompressionAlgorithm, inner: R) -> Result<Decompressor<R>, Error> {
+        let decompressor = match algo {
+            CompressionAlgorithm::Uncompressed => Decompressor::Uncompressed(inner),
+            CompressionAlgorithm::Zip => Decompressor::Zip(DeflateDecoder::new(inner)),
+            CompressionAlgorithm::Zlib => Decompressor::Zlib(ZlibDecoder::new(inner)),
+            CompressionAlgorithm::Bzip2 => Decompressor::Bzip2(BzDecoder::new(inner)),
+            CompressionAlgorithm::Unknown => bail!(AlgorithmError::CompressionAlgorithmError),
+        };
+
+        Ok(decompressor)
+    }
+}
+
+impl<R: Read> Read for Decompressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Decompressor::Uncompressed(ref mut r) => r.read(buf),
+            Decompressor::Zip(ref mut r) => r.read(buf),
+            Decompressor::Zlib(ref mut r) => r.read(buf),
+            Decompressor::Bzip2(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+/// A streaming compressor for the body of a Compressed Data packet.
+///
+/// [`finish`] must be called to write out any remaining compressed data.
+///
+/// [`finish`]: #method.finish
+pub enum Compressor<W: Write> {
+    Uncompressed(W),
+    Zip(write::DeflateEncoder<W>),
+    Zlib(write::ZlibEncoder<W>),
+    Bzip2(BzWriteEncoder<W>),
+}
+
+impl<W: Write> Compressor<W> {
+    /// Create a compressor writing data compressed with `algo` to `inner`.
+    pub fn new(algo: CompressionAlgorithm, inner: W) -> Result<Compressor<W>, Error> {
+        let compressor = match algo {
+            CompressionAlgorithm::Uncompressed => Compressor::Uncompressed(inner),
+            CompressionAlgorithm::Zip => Compressor::Zip(write::DeflateEncoder::new(
+                inner,
+                ::flate2::Compression::default(),
+            )),
+            CompressionAlgorithm::Zlib => Compressor::Zlib(write::ZlibEncoder::new(
+                inner,
+                ::flate2::Compression::default(),
+            )),
+            CompressionAlgorithm::Bzip2 => {
+                Compressor::Bzip2(BzWriteEncoder::new(inner, ::bzip2::Compression::Default))
+            }
+            CompressionAlgorithm::Unknown => bail!(AlgorithmError::CompressionAlgorithmError),
+        };
+
+        Ok(compressor)
+    }
+
+    /// Finish compressing, returning the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        let inner = match self {
+            Compressor::Uncompressed(w) => w,
+            Compressor::Zip(w) => w.finish()?,
+            Compressor::Zlib(w) => w.finish()?,
+            Compressor::Bzip2(w) => w.finish()?,
+        };
+
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Compressor::Uncompressed(ref mut w) => w.write(buf),
+            Compressor::Zip(ref mut w) => w.write(buf),
+            Compressor::Zlib(ref mut w) => w.write(buf),
+            Compressor::Bzip2(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Compressor::Uncompressed(ref mut w) => w.flush(),
+            Compressor::Zip(ref mut w) => w.flush(),
+            Compressor::Zlib(ref mut w) => w.flush(),
+            Compressor::Bzip2(ref mut w) => w.flush(),
+        }
+    }
+}
+
 #[derive(Debug, Fail)]
 pub enum CompressionError {
     #[fail(display = "Invalid compressed data: {}", reason)]
@@ -0,0 +1,554 @@
+use std::io::Read;
+
+use failure::Error;
+use num::BigUint;
+use p256;
+use p384;
+use p521;
+use x25519_dalek;
+
+use key::{native_point, EcdhPrivateKey, EcdhPublicKey, ElgamalPrivateKey, ElgamalPublicKey, Key,
+          KeyError, KeyMaterial, RsaPrivateKey, RsaPublicKey};
+use literal::LiteralPacket;
+use message::MessageError;
+use packet::Packet;
+use pkesk::{ecdh_kdf, EncryptedSessionKey};
+use reader::{PacketReader, StreamedPacket};
+use seipd::{decrypt_integrity_protected, DecryptionError};
+use signature::SignaturePacket;
+use skesk::{SessionKey, SymmetricKeySessionKeyPacket};
+use symmetric::key_unwrap;
+use types::*;
+use util::fixed_bytes;
+
+/// A holder of secret keys that can decrypt session keys, such as a hardware security module or
+/// an agent.
+///
+/// Implementors only need to perform the public key decryption of a session key; finding the
+/// session key packets to try and decrypting the message itself is handled by
+/// [`decrypt_message`].
+///
+/// [`decrypt_message`]: fn.decrypt_message.html
+pub trait Decryptor {
+    /// The key ID of the decryption key.
+    fn key_id(&self) -> Result<u64, Error>;
+
+    /// Decrypt a session key encrypted to this key, returning the decoded message: the symmetric
+    /// algorithm, the session key and its checksum, as read by
+    /// [`SessionKey::from_checksummed_bytes`].
+    ///
+    /// RSA and Elgamal decryptors must strip the EME-PKCS1-v1_5 encoding. ECDH decryptors must
+    /// unwrap the key with the key-encryption key derived from the shared secret, and strip its
+    /// padding.
+    ///
+    /// [`SessionKey::from_checksummed_bytes`]: struct.SessionKey.html#method.from_checksummed_bytes
+    fn decrypt(&self, session_key: &EncryptedSessionKey) -> Result<Vec<u8>, Error>;
+}
+
+/// A message decrypted by [`decrypt_message`].
+///
+/// The signatures found alongside the literal data aren't verified; they can be checked against
+/// the literal data with [`verify_detached`].
+///
+/// [`decrypt_message`]: fn.decrypt_message.html
+/// [`verify_detached`]: fn.verify_detached.html
+#[derive(Clone, Debug)]
+pub struct DecryptedMessage {
+    pub session_key: SessionKey,
+    pub literal: LiteralPacket,
+    pub signatures: Vec<SignaturePacket>,
+}
+
+/// Decrypt a message encrypted to public keys or passphrases, as produced by `gpg --encrypt` or
+/// `gpg --symmetric`.
+///
+/// The session key is first looked for in the message's [`PublicKeySessionKeyPacket`]s, using
+/// the decryptors whose key ID matches the recipient; every decryptor is tried against wildcard
+/// recipients. Failing that, `passphrase` is called once for each
+/// [`SymmetricKeySessionKeyPacket`], and may return `None` to skip it.
+///
+/// Only integrity protected data is decrypted, and nothing is returned unless its Modification
+/// Detection Code matches.
+///
+/// [`PublicKeySessionKeyPacket`]: struct.PublicKeySessionKeyPacket.html
+/// [`SymmetricKeySessionKeyPacket`]: struct.SymmetricKeySessionKeyPacket.html
+pub fn decrypt_message<R, F>(
+    input: R,
+    decryptors: &[&dyn Decryptor],
+    mut passphrase: F,
+) -> Result<DecryptedMessage, Error>
+where
+    R: Read,
+    F: FnMut(&SymmetricKeySessionKeyPacket) -> Option<Vec<u8>>,
+{
+    let mut pkesks = Vec::new();
+    let mut skesks = Vec::new();
+    let mut data = None;
+
+    for packet in PacketReader::new(input) {
+        match packet? {
+            StreamedPacket::Packet(Packet::Marker) => {}
+            StreamedPacket::Packet(Packet::PublicKeySessionKey(pkesk)) => pkesks.push(pkesk),
+            StreamedPacket::Packet(Packet::SymmetricKeySessionKey(skesk)) => skesks.push(skesk),
+            StreamedPacket::SymmetricEncryptedIntegrityProtectedData(mut body) => {
+                let mut body_data = Vec::new();
+                body.read_to_end(&mut body_data)?;
+                data = Some(body_data);
+                break;
+            }
+            StreamedPacket::SymmetricEncryptedData(_) => bail!(DecryptionError::UnprotectedData),
+            StreamedPacket::Packet(p) => bail!(MessageError::UnexpectedPacket {
+                packet_type: format!("{:?}", p),
+            }),
+            StreamedPacket::LiteralData(..) | StreamedPacket::CompressedData(..) => {
+                bail!(DecryptionError::MissingEncryptedData)
+            }
+        }
+    }
+
+    let data = match data {
+        Some(data) => data,
+        None => bail!(DecryptionError::MissingEncryptedData),
+    };
+
+    // Session keys are only derived as they are needed, so that no passphrase is asked for once
+    // a key has decrypted the message.
+    let from_pkesks = pkesks.iter().flat_map(|pkesk| {
+        decryptors
+            .iter()
+            .filter(move |decryptor| {
+                pkesk.is_wildcard() || decryptor.key_id().ok() == Some(pkesk.recipient)
+            })
+            .filter_map(move |decryptor| {
+                decryptor
+                    .decrypt(&pkesk.session_key)
+                    .and_then(|message| SessionKey::from_checksummed_bytes(&message))
+                    .ok()
+            })
+    });
+    let from_skesks = skesks.iter().filter_map(|skesk| {
+        passphrase(skesk).and_then(|passphrase| skesk.session_key(passphrase).ok())
+    });
+
+    // A wrong session key can pass the quick check by chance, so a mismatched MDC only counts once
+    // every other session key has been tried.
+    let mut modified = false;
+    for session_key in from_pkesks.chain(from_skesks) {
+        let e = match decrypt_data(&data, session_key) {
+            Ok(message) => return Ok(message),
+            Err(e) => e,
+        };
+
+        match e.downcast::<DecryptionError>() {
+            Ok(DecryptionError::QuickCheckFailed) => {}
+            Ok(DecryptionError::ModificationDetected) => modified = true,
+            Ok(e) => return Err(e.into()),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if modified {
+        bail!(DecryptionError::ModificationDetected);
+    }
+
+    bail!(DecryptionError::NoSessionKey)
+}
+
+/// Decrypt the body of the integrity protected data packet with a candidate session key, and read
+/// the message inside it.
+fn decrypt_data(data: &[u8], session_key: SessionKey) -> Result<DecryptedMessage, Error> {
+    let plaintext = decrypt_integrity_protected(session_key.algo, &session_key.key, data)?;
+
+    let mut literal = None;
+    let mut signatures = Vec::new();
+    for packet in Packet::all_from_bytes(&plaintext)? {
+        match packet {
+            Packet::Marker | Packet::OnePassSignature(_) => {}
+            Packet::Signature(signature) => signatures.push(signature),
+            Packet::LiteralData(data) => {
+                if literal.is_some() {
+                    bail!(MessageError::UnexpectedPacket {
+                        packet_type: "LiteralData".to_string(),
+                    });
+                }
+
+                literal = Some(data);
+            }
+            p => bail!(MessageError::UnexpectedPacket {
+                packet_type: format!("{:?}", p),
+            }),
+        }
+    }
+
+    match literal {
+        Some(literal) => Ok(DecryptedMessage {
+            session_key,
+            literal,
+            signatures,
+        }),
+        None => bail!(MessageError::MissingLiteral),
+    }
+}
+
+/// A [`Decryptor`] backed by an unlocked secret [`Key`] held in memory.
+///
+/// RSA and Elgamal keys are supported, as are ECDH keys over Curve25519 and the NIST curves.
+///
+/// [`Decryptor`]: trait.Decryptor.html
+/// [`Key`]: struct.Key.html
+#[derive(Clone, Debug)]
+pub struct KeyDecryptor {
+    key: Key,
+}
+
+impl KeyDecryptor {
+    /// Create a decryptor from a key with unencrypted secret key material.
+    pub fn new(key: Key) -> Result<KeyDecryptor, Error> {
+        if key.is_locked() {
+            bail!(KeyError::Locked);
+        }
+
+        if key.key_material.private_to_bytes()?.is_empty() {
+            bail!(KeyError::MissingSecretKey);
+        }
+
+        Ok(KeyDecryptor { key })
+    }
+
+    /// Retrieve the key used by this decryptor.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+}
+
+impl Decryptor for KeyDecryptor {
+    fn key_id(&self) -> Result<u64, Error> {
+        self.key.id()
+    }
+
+    fn decrypt(&self, session_key: &EncryptedSessionKey) -> Result<Vec<u8>, Error> {
+        match (&self.key.key_material, session_key) {
+            (
+                &KeyMaterial::Rsa(ref public, Some(ref private)),
+                &EncryptedSessionKey::Rsa(ref m_e),
+            ) => decrypt_rsa(m_e, public, private),
+            (
+                &KeyMaterial::Elgamal(ref public, Some(ref private)),
+                &EncryptedSessionKey::Elgamal(ref g_k, ref m_y_k),
+            ) => decrypt_elgamal(g_k, m_y_k, public, private),
+            (
+                &KeyMaterial::Ecdh(ref public, Some(ref private)),
+                &EncryptedSessionKey::Ecdh {
+                    ref point,
+                    ref wrapped_key,
+                },
+            ) => decrypt_ecdh(point, wrapped_key, public, private, &self.key.fingerprint()?),
+            (&KeyMaterial::Rsa(_, None), _)
+            | (&KeyMaterial::Elgamal(_, None), _)
+            | (&KeyMaterial::Ecdh(_, None), _) => bail!(KeyError::MissingSecretKey),
+            _ => bail!(DecryptionError::UnsupportedAlgorithm {
+                algorithm: self.key.pubkey_algorithm,
+            }),
+        }
+    }
+}
+
+/// Decode a message encoded with EME-PKCS1-v1_5, as specified in [RFC4880 &sect;13.1.2], for a
+/// modulus of `len` octets.
+///
+/// [RFC4880 &sect;13.1.2]: https://tools.ietf.org/html/rfc4880#section-13.1.2
+fn eme_pkcs1_v1_5_decode(encoded: &BigUint, len: usize) -> Result<Vec<u8>, Error> {
+    let encoded = match fixed_bytes(encoded, len) {
+        Some(encoded) => encoded,
+        None => bail!(DecryptionError::InvalidSessionKey),
+    };
+
+    // The encoding is 00 02, at least eight nonzero padding octets, 00 and the message.
+    let separator = encoded.iter().skip(2).position(|&octet| octet == 0);
+    match separator {
+        Some(padding) if encoded[..2] == [0x00, 0x02] && padding >= 8 => {
+            Ok(Vec::from(&encoded[padding + 3..]))
+        }
+        _ => bail!(DecryptionError::InvalidSessionKey),
+    }
+}
+
+fn decrypt_rsa(
+    m_e: &BigUint,
+    public: &RsaPublicKey,
+    private: &RsaPrivateKey,
+) -> Result<Vec<u8>, Error> {
+    if m_e >= &public.n {
+        bail!(DecryptionError::InvalidSessionKey);
+    }
+
+    let m = m_e.modpow(&private.d, &public.n);
+    eme_pkcs1_v1_5_decode(&m, public.n.bits().div_ceil(8))
+}
+
+fn decrypt_elgamal(
+    g_k: &BigUint,
+    m_y_k: &BigUint,
+    public: &ElgamalPublicKey,
+    private: &ElgamalPrivateKey,
+) -> Result<Vec<u8>, Error> {
+    let p = &public.p;
+    if g_k >= p || m_y_k >= p {
+        bail!(DecryptionError::InvalidSessionKey);
+    }
+
+    // The shared value y^k = (g^k)^x is inverted with Fermat's little theorem, as p is prime.
+    let y_k = g_k.modpow(&private.0, p);
+    let m = m_y_k * y_k.modpow(&(p - BigUint::from(2u32)), p) % p;
+    eme_pkcs1_v1_5_decode(&m, p.bits().div_ceil(8))
+}
+
+macro_rules! ecdh_shared_secret {
+    ($curve:ident, $secret:expr, $point:expr) => {{
+        let secret = $curve::SecretKey::from_slice($secret).map_err(|_| KeyError::MalformedMpi)?;
+        let point = $curve::PublicKey::from_sec1_bytes($point)
+            .map_err(|_| KeyError::InvalidPoint)?;
+
+        $curve::ecdh::diffie_hellman(secret.to_nonzero_scalar(), point.as_affine())
+            .raw_secret_bytes()
+            .to_vec()
+    }};
+}
+
+fn decrypt_ecdh(
+    point: &[u8],
+    wrapped_key: &[u8],
+    public: &EcdhPublicKey,
+    private: &EcdhPrivateKey,
+    fingerprint: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let field_bytes = match public.curve.field_bytes() {
+        Some(field_bytes) => field_bytes,
+        None => bail!(KeyError::UnsupportedCurve {
+            curve: format!("{:?}", public.curve),
+        }),
+    };
+
+    let point = native_point(&public.curve, point)?;
+    let secret = match fixed_bytes(&private.0, field_bytes) {
+        Some(secret) => secret,
+        None => bail!(KeyError::MalformedMpi),
+    };
+
+    let shared_secret = match public.curve {
+        Curve::Curve25519 => {
+            // The secret scalar is stored as a big-endian MPI, the reverse of its native
+            // encoding.
+            let mut native = [0u8; 32];
+            for (native, secret) in native.iter_mut().zip(secret.iter().rev()) {
+                *native = *secret;
+            }
+            let mut ephemeral = [0u8; 32];
+            ephemeral.copy_from_slice(point);
+
+            let secret = x25519_dalek::StaticSecret::from(native);
+            let shared_secret = secret.diffie_hellman(&x25519_dalek::PublicKey::from(ephemeral));
+            shared_secret.as_bytes().to_vec()
+        }
+        Curve::NistP256 => ecdh_shared_secret!(p256, &secret, point),
+        Curve::NistP384 => ecdh_shared_secret!(p384, &secret, point),
+        Curve::NistP521 => ecdh_shared_secret!(p521, &secret, point),
+        ref curve => bail!(KeyError::UnsupportedCurve {
+            curve: format!("{:?}", curve),
+        }),
+    };
+
+    let kek = ecdh_kdf(public, fingerprint, &shared_secret)?;
+    let mut message = key_unwrap(public.kdf_cipher, &kek, wrapped_key)?;
+
+    // Strip the PKCS#5 padding.
+    let padding = match message.last() {
+        Some(&padding) => usize::from(padding),
+        None => bail!(DecryptionError::InvalidSessionKey),
+    };
+    if padding == 0
+        || padding > message.len()
+        || message[message.len() - padding..].iter().any(|&octet| usize::from(octet) != padding)
+    {
+        bail!(DecryptionError::InvalidSessionKey);
+    }
+    let len = message.len() - padding;
+    message.truncate(len);
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use certificate::{Certificate, SecretCertificate};
+    use literal::LiteralData;
+    use message::verify_detached;
+
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/message.txt");
+    const RSA_MESSAGE: &[u8] = include_bytes!("../tests/data/rsa.enc.gpg");
+    const ELGAMAL_MESSAGE: &[u8] = include_bytes!("../tests/data/elg.enc.gpg");
+    const P256_MESSAGE: &[u8] = include_bytes!("../tests/data/enistp256.enc.gpg");
+    const P384_MESSAGE: &[u8] = include_bytes!("../tests/data/nistp384.enc.gpg");
+    const P521_MESSAGE: &[u8] = include_bytes!("../tests/data/nistp521.enc.gpg");
+    const SIGNED_MESSAGE: &[u8] = include_bytes!("../tests/data/ed.signed.enc.gpg");
+    const HIDDEN_MESSAGE: &[u8] = include_bytes!("../tests/data/hidden.enc.gpg");
+    const SYMMETRIC_MESSAGE: &[u8] = include_bytes!("../tests/data/symmetric.gpg");
+    const MIXED_MESSAGE: &[u8] = include_bytes!("../tests/data/rsa.symmetric.gpg");
+    const RSA_SECRET_KEY: &[u8] = include_bytes!("../tests/data/rsa.sec");
+    const ELGAMAL_SECRET_KEY: &[u8] = include_bytes!("../tests/data/elg.sec");
+    const P256_SECRET_KEY: &[u8] = include_bytes!("../tests/data/enistp256.sec");
+    const P384_SECRET_KEY: &[u8] = include_bytes!("../tests/data/nistp384.sec");
+    const P521_SECRET_KEY: &[u8] = include_bytes!("../tests/data/nistp521.sec");
+    const ED_SECRET_KEY: &[u8] = include_bytes!("../tests/data/ed.sec");
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const PROTECTED_SECRET_KEY: &[u8] = include_bytes!("../tests/data/protected.sec");
+
+    // A decryptor for the encryption subkey of a secret certificate.
+    fn subkey_decryptor(bytes: &[u8]) -> KeyDecryptor {
+        let certificate = SecretCertificate::from_bytes(bytes).unwrap();
+        KeyDecryptor::new(certificate.secret_keys()[1].0.clone()).unwrap()
+    }
+
+    fn no_passphrase(_: &SymmetricKeySessionKeyPacket) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn password(_: &SymmetricKeySessionKeyPacket) -> Option<Vec<u8>> {
+        Some(b"password".to_vec())
+    }
+
+    fn assert_message(decrypted: &DecryptedMessage) {
+        assert_eq!(decrypted.literal.filename, "message.txt");
+        match decrypted.literal.contents {
+            LiteralData::Binary(ref contents) => assert_eq!(contents, MESSAGE),
+            ref c => panic!("expected binary data, found {:?}", c),
+        }
+    }
+
+    fn assert_error(result: Result<DecryptedMessage, Error>, expected: DecryptionError) {
+        assert_eq!(result.unwrap_err().to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn decrypt_gnupg_messages() {
+        for &(message, secret, algo) in &[
+            (RSA_MESSAGE, RSA_SECRET_KEY, PublicKeyAlgorithm::Rsa),
+            (ELGAMAL_MESSAGE, ELGAMAL_SECRET_KEY, PublicKeyAlgorithm::ElgamalEncryptOnly),
+            (P256_MESSAGE, P256_SECRET_KEY, PublicKeyAlgorithm::EllipticCurve),
+            (P384_MESSAGE, P384_SECRET_KEY, PublicKeyAlgorithm::EllipticCurve),
+            (P521_MESSAGE, P521_SECRET_KEY, PublicKeyAlgorithm::EllipticCurve),
+        ] {
+            let decryptor = subkey_decryptor(secret);
+            assert_eq!(decryptor.key().pubkey_algorithm, algo);
+
+            let decrypted = decrypt_message(message, &[&decryptor], no_passphrase).unwrap();
+            assert_message(&decrypted);
+            assert!(decrypted.signatures.is_empty());
+        }
+    }
+
+    #[test]
+    fn decrypt_signed_message() {
+        // Encrypted to Curve25519, and signed and compressed before that.
+        let decryptor = subkey_decryptor(ED_SECRET_KEY);
+        let decrypted = decrypt_message(SIGNED_MESSAGE, &[&decryptor], no_passphrase).unwrap();
+        assert_message(&decrypted);
+        assert_eq!(decrypted.signatures.len(), 1);
+
+        let key = Certificate::from_bytes(ED_CERTIFICATE).unwrap().primary;
+        let verifications = verify_detached(MESSAGE, &decrypted.signatures, &[key]).unwrap();
+        assert!(verifications[0].is_valid());
+    }
+
+    #[test]
+    fn decrypt_hidden_recipient() {
+        // Every decryptor is tried against a wildcard recipient.
+        let decryptors = [
+            subkey_decryptor(ED_SECRET_KEY),
+            subkey_decryptor(ELGAMAL_SECRET_KEY),
+            subkey_decryptor(RSA_SECRET_KEY),
+        ];
+        let decryptors = decryptors.iter().map(|d| d as &dyn Decryptor).collect::<Vec<_>>();
+
+        let decrypted = decrypt_message(HIDDEN_MESSAGE, &decryptors, no_passphrase).unwrap();
+        assert_message(&decrypted);
+    }
+
+    #[test]
+    fn decrypt_with_passphrase() {
+        let decrypted = decrypt_message(SYMMETRIC_MESSAGE, &[], password).unwrap();
+        assert_message(&decrypted);
+        assert_eq!(decrypted.session_key.algo, SymmetricKeyAlgorithm::Aes256);
+
+        // The passphrase isn't asked for if a key decrypts the message first.
+        let decryptor = subkey_decryptor(RSA_SECRET_KEY);
+        let decrypted = decrypt_message(MIXED_MESSAGE, &[&decryptor], |_| -> Option<Vec<u8>> {
+            panic!("asked for a passphrase")
+        }).unwrap();
+        assert_message(&decrypted);
+    }
+
+    #[test]
+    fn no_session_key() {
+        assert_error(
+            decrypt_message(SYMMETRIC_MESSAGE, &[], |_| Some(b"wrong".to_vec())),
+            DecryptionError::NoSessionKey,
+        );
+        assert_error(
+            decrypt_message(SYMMETRIC_MESSAGE, &[], no_passphrase),
+            DecryptionError::NoSessionKey,
+        );
+
+        // Keys that the message isn't encrypted to are never tried.
+        let decryptor = subkey_decryptor(ED_SECRET_KEY);
+        assert_error(
+            decrypt_message(RSA_MESSAGE, &[&decryptor], no_passphrase),
+            DecryptionError::NoSessionKey,
+        );
+    }
+
+    #[test]
+    fn modification_detected() {
+        let mut message = RSA_MESSAGE.to_vec();
+        *message.last_mut().unwrap() ^= 0x01;
+
+        // A wrong key can't hide the modification from the right one.
+        let decryptors = [subkey_decryptor(ED_SECRET_KEY), subkey_decryptor(RSA_SECRET_KEY)];
+        let decryptors = decryptors.iter().map(|d| d as &dyn Decryptor).collect::<Vec<_>>();
+        let mut hidden = HIDDEN_MESSAGE.to_vec();
+        *hidden.last_mut().unwrap() ^= 0x01;
+
+        assert_error(
+            decrypt_message(&message[..], &decryptors, no_passphrase),
+            DecryptionError::ModificationDetected,
+        );
+        assert_error(
+            decrypt_message(&hidden[..], &decryptors, no_passphrase),
+            DecryptionError::ModificationDetected,
+        );
+    }
+
+    #[test]
+    fn missing_encrypted_data() {
+        let packet = Packet::from_bytes(RSA_MESSAGE).unwrap().0;
+        let message = packet.to_bytes().unwrap();
+
+        let decryptor = subkey_decryptor(RSA_SECRET_KEY);
+        assert_error(
+            decrypt_message(&message[..], &[&decryptor], no_passphrase),
+            DecryptionError::MissingEncryptedData,
+        );
+    }
+
+    #[test]
+    fn decryptor_needs_secret_key() {
+        let certificate = SecretCertificate::from_bytes(PROTECTED_SECRET_KEY).unwrap();
+        let err = KeyDecryptor::new(certificate.secret_keys()[0].0.clone()).unwrap_err();
+        assert_eq!(err.to_string(), KeyError::Locked.to_string());
+
+        let key = subkey_decryptor(RSA_SECRET_KEY).key().to_public();
+        let err = KeyDecryptor::new(key).unwrap_err();
+        assert_eq!(err.to_string(), KeyError::MissingSecretKey.to_string());
+    }
+}
@@ -0,0 +1,332 @@
+use std::time::Duration;
+
+use failure::Error;
+
+use certificate::Certificate;
+use compression::CompressedDataPacket;
+use key::Key;
+use literal::LiteralPacket;
+use packet::Packet;
+use pkesk::PublicKeySessionKeyPacket;
+use s2k::StringToKey;
+use seipd::IntegrityProtectedDataPacket;
+use skesk::{SessionKey, SymmetricKeySessionKeyPacket};
+use types::*;
+
+// The iteration count used to derive keys from passphrases.
+const S2K_COUNT: u32 = 65_011_712;
+
+/// A builder for messages encrypted to public keys and passphrases, as made by `gpg --encrypt`
+/// and `gpg --symmetric`.
+///
+/// Each message is encrypted with a fresh random session key. The session key is encrypted to
+/// every recipient key in a [`PublicKeySessionKeyPacket`] and with every passphrase in a
+/// [`SymmetricKeySessionKeyPacket`], followed by the message itself in an
+/// [`IntegrityProtectedDataPacket`].
+///
+/// Unless it is set explicitly, the symmetric algorithm is the first algorithm in the preferences
+/// of the first recipient that every other recipient with preferences also supports. Triple-DES is
+/// implicitly supported by everyone, as required by RFC4880. Without any preferences, AES-256 is
+/// used.
+///
+/// [`PublicKeySessionKeyPacket`]: struct.PublicKeySessionKeyPacket.html
+/// [`SymmetricKeySessionKeyPacket`]: struct.SymmetricKeySessionKeyPacket.html
+/// [`IntegrityProtectedDataPacket`]: struct.IntegrityProtectedDataPacket.html
+#[derive(Clone, Debug, Default)]
+pub struct Encryptor {
+    recipients: Vec<Key>,
+    preferences: Vec<Vec<SymmetricKeyAlgorithm>>,
+    passphrases: Vec<Vec<u8>>,
+    sym_algo: Option<SymmetricKeyAlgorithm>,
+    compression: Option<CompressionAlgorithm>,
+}
+
+impl Encryptor {
+    /// Create an encryptor with no recipients or passphrases.
+    pub fn new() -> Encryptor {
+        Encryptor::default()
+    }
+
+    /// Encrypt the message to a key. The key's algorithm preferences aren't known, so they don't
+    /// restrict the choice of symmetric algorithm.
+    pub fn add_recipient(&mut self, key: Key) {
+        self.recipients.push(key);
+    }
+
+    /// Encrypt the message to a key, choosing a symmetric algorithm from its preferences.
+    pub fn add_recipient_with_preferences<T: AsRef<[SymmetricKeyAlgorithm]>>(
+        &mut self,
+        key: Key,
+        preferences: T,
+    ) {
+        self.recipients.push(key);
+        self.preferences.push(Vec::from(preferences.as_ref()));
+    }
+
+    /// Encrypt the message to the newest key of a certificate that is usable for encryption at
+    /// `time`, following the preferences of the primary user ID's self-signature.
+    pub fn add_certificate(
+        &mut self,
+        certificate: &Certificate,
+        time: Duration,
+    ) -> Result<(), Error> {
+        let key = match certificate
+            .encryption_keys(time)
+            .into_iter()
+            .max_by_key(|key| key.creation_time)
+        {
+            Some(key) => key.clone(),
+            None => bail!(EncryptionError::NoEncryptionKey),
+        };
+
+        let validity = certificate.validate(time);
+        let bindings = validity
+            .user_ids
+            .iter()
+            .filter(|user_id| user_id.is_valid())
+            .filter_map(|user_id| user_id.binding.as_ref());
+        let mut primary = None;
+        for binding in bindings {
+            if primary.is_none() || binding.is_primary_user_id() {
+                primary = Some(binding);
+            }
+        }
+
+        match primary.and_then(|binding| binding.preferred_symmetric_algorithms()) {
+            Some(preferences) => self.add_recipient_with_preferences(key, preferences),
+            None => self.add_recipient(key),
+        }
+
+        Ok(())
+    }
+
+    /// Also allow the message to be decrypted with a passphrase.
+    pub fn add_passphrase<T: AsRef<[u8]>>(&mut self, passphrase: T) {
+        self.passphrases.push(Vec::from(passphrase.as_ref()));
+    }
+
+    /// Use a specific symmetric algorithm, regardless of the recipients' preferences.
+    pub fn set_symmetric_algorithm(&mut self, algo: SymmetricKeyAlgorithm) {
+        self.sym_algo = Some(algo);
+    }
+
+    /// Compress the message before encrypting it.
+    pub fn set_compression(&mut self, algo: CompressionAlgorithm) {
+        self.compression = Some(algo);
+    }
+
+    /// The symmetric algorithm messages will be encrypted with.
+    pub fn symmetric_algorithm(&self) -> SymmetricKeyAlgorithm {
+        if let Some(algo) = self.sym_algo {
+            return algo;
+        }
+
+        let (first, rest) = match self.preferences.split_first() {
+            Some(preferences) => preferences,
+            None => return SymmetricKeyAlgorithm::Aes256,
+        };
+
+        first
+            .iter()
+            .cloned()
+            .filter(|algo| algo.key_bytes() > 0)
+            .find(|algo| rest.iter().all(|preferences| preferences.contains(algo)))
+            .unwrap_or(SymmetricKeyAlgorithm::TripleDes)
+    }
+
+    /// Encrypt a literal data packet, returning the encoded packets of the encrypted message.
+    pub fn encrypt(&self, literal: &LiteralPacket) -> Result<Vec<u8>, Error> {
+        if self.recipients.is_empty() && self.passphrases.is_empty() {
+            bail!(EncryptionError::NoRecipients);
+        }
+
+        let session_key = SessionKey::random(self.symmetric_algorithm());
+        let mut packets = Vec::new();
+        for recipient in &self.recipients {
+            packets.push(Packet::PublicKeySessionKey(
+                PublicKeySessionKeyPacket::new(&session_key, recipient)?,
+            ));
+        }
+        for passphrase in &self.passphrases {
+            let s2k = StringToKey::iterated(HashAlgorithm::Sha256, S2K_COUNT);
+            packets.push(Packet::SymmetricKeySessionKey(
+                SymmetricKeySessionKeyPacket::new(&session_key, passphrase, s2k)?,
+            ));
+        }
+
+        let literal = Packet::LiteralData(literal.clone()).to_bytes()?;
+        let plaintext = match self.compression {
+            Some(algo) => Packet::CompressedData(compressed(algo, literal)?).to_bytes()?,
+            None => literal,
+        };
+
+        packets.push(Packet::SymmetricEncryptedIntegrityProtectedData(
+            IntegrityProtectedDataPacket::encrypt(session_key.algo, &session_key.key, &plaintext)?,
+        ));
+
+        let mut out = Vec::new();
+        for packet in packets {
+            out.extend(&packet.to_bytes()?);
+        }
+
+        Ok(out)
+    }
+}
+
+fn compressed(algo: CompressionAlgorithm, data: Vec<u8>) -> Result<CompressedDataPacket, Error> {
+    let packet = match algo {
+        CompressionAlgorithm::Uncompressed => CompressedDataPacket::Uncompressed(data),
+        CompressionAlgorithm::Zip => CompressedDataPacket::Zip(data),
+        CompressionAlgorithm::Zlib => CompressedDataPacket::Zlib(data),
+        CompressionAlgorithm::Bzip2 => CompressedDataPacket::Bzip2(data),
+        CompressionAlgorithm::Unknown => bail!(AlgorithmError::CompressionAlgorithmError),
+    };
+
+    Ok(packet)
+}
+
+/// Error type for [`Encryptor`]-level errors.
+///
+/// [`Encryptor`]: struct.Encryptor.html
+#[derive(Debug, Fail)]
+pub enum EncryptionError {
+    #[fail(display = "Certificate has no key usable for encryption")]
+    NoEncryptionKey,
+    #[fail(display = "Message has no recipients or passphrases to encrypt to")]
+    NoRecipients,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use certificate::SecretCertificate;
+    use decryptor::{decrypt_message, Decryptor, KeyDecryptor};
+    use literal::LiteralData;
+
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const DSA_CERTIFICATE: &[u8] = include_bytes!("../tests/data/dsa.pub");
+    const RSA_SECRET_KEY: &[u8] = include_bytes!("../tests/data/rsa.sec");
+    const ELGAMAL_SECRET_KEY: &[u8] = include_bytes!("../tests/data/elg.sec");
+    const ED_SECRET_KEY: &[u8] = include_bytes!("../tests/data/ed.sec");
+
+    fn literal() -> LiteralPacket {
+        LiteralPacket {
+            filename: "message.txt".to_string(),
+            timestamp: Duration::from_secs(1_792_190_177),
+            contents: LiteralData::Binary(b"Hello, world!\n".to_vec()),
+        }
+    }
+
+    // A decryptor for the encryption subkey of a secret certificate.
+    fn subkey_decryptor(bytes: &[u8]) -> KeyDecryptor {
+        let certificate = SecretCertificate::from_bytes(bytes).unwrap();
+        KeyDecryptor::new(certificate.secret_keys()[1].0.clone()).unwrap()
+    }
+
+    fn assert_decrypts_to_literal(
+        message: &[u8],
+        decryptors: &[&dyn Decryptor],
+        passphrase: &str,
+    ) {
+        let decrypted = decrypt_message(message, decryptors, |_| {
+            Some(passphrase.as_bytes().to_vec())
+        }).unwrap();
+
+        assert_eq!(
+            Packet::LiteralData(decrypted.literal).to_bytes().unwrap(),
+            Packet::LiteralData(literal()).to_bytes().unwrap()
+        );
+    }
+
+    #[test]
+    fn encrypt_to_keys() {
+        let decryptors = [
+            subkey_decryptor(RSA_SECRET_KEY),
+            subkey_decryptor(ELGAMAL_SECRET_KEY),
+            subkey_decryptor(ED_SECRET_KEY),
+        ];
+
+        let mut encryptor = Encryptor::new();
+        for decryptor in &decryptors {
+            encryptor.add_recipient(decryptor.key().to_public());
+        }
+        let message = encryptor.encrypt(&literal()).unwrap();
+
+        let packets = Packet::all_from_bytes(&message).unwrap();
+        assert_eq!(packets.len(), 4);
+        for (packet, decryptor) in packets.iter().zip(&decryptors) {
+            match *packet {
+                Packet::PublicKeySessionKey(ref pkesk) => {
+                    assert_eq!(pkesk.recipient, decryptor.key_id().unwrap())
+                }
+                ref p => panic!("expected a session key, found {:?}", p),
+            }
+        }
+
+        // Each recipient can decrypt the message on its own.
+        for decryptor in &decryptors {
+            assert_decrypts_to_literal(&message, &[decryptor], "");
+        }
+    }
+
+    #[test]
+    fn encrypt_with_passphrase() {
+        let mut encryptor = Encryptor::new();
+        encryptor.add_passphrase("password");
+        encryptor.set_compression(CompressionAlgorithm::Zlib);
+        let message = encryptor.encrypt(&literal()).unwrap();
+
+        assert_decrypts_to_literal(&message, &[], "password");
+    }
+
+    #[test]
+    fn encrypt_to_certificate() {
+        let certificate = Certificate::from_bytes(ED_CERTIFICATE).unwrap();
+        let time = certificate.primary.creation_time + Duration::from_secs(24 * 60 * 60);
+
+        let mut encryptor = Encryptor::new();
+        encryptor.add_certificate(&certificate, time).unwrap();
+        assert_eq!(encryptor.symmetric_algorithm(), SymmetricKeyAlgorithm::Aes256);
+
+        let message = encryptor.encrypt(&literal()).unwrap();
+        match Packet::from_bytes(&message).unwrap().0 {
+            Packet::PublicKeySessionKey(ref pkesk) => {
+                assert_eq!(pkesk.recipient, 0xD88B_EE62_C596_6048)
+            }
+            ref p => panic!("expected a session key, found {:?}", p),
+        }
+        assert_decrypts_to_literal(&message, &[&subkey_decryptor(ED_SECRET_KEY)], "");
+
+        let certificate = Certificate::from_bytes(DSA_CERTIFICATE).unwrap();
+        let err = encryptor.add_certificate(&certificate, time).unwrap_err();
+        assert_eq!(err.to_string(), EncryptionError::NoEncryptionKey.to_string());
+    }
+
+    #[test]
+    fn symmetric_algorithm_preferences() {
+        let key = subkey_decryptor(ED_SECRET_KEY).key().to_public();
+
+        let mut encryptor = Encryptor::new();
+        assert_eq!(encryptor.symmetric_algorithm(), SymmetricKeyAlgorithm::Aes256);
+
+        encryptor.add_recipient_with_preferences(
+            key.clone(),
+            [SymmetricKeyAlgorithm::Aes256, SymmetricKeyAlgorithm::Aes128],
+        );
+        encryptor.add_recipient(key.clone());
+        encryptor.add_recipient_with_preferences(key.clone(), [SymmetricKeyAlgorithm::Aes128]);
+        assert_eq!(encryptor.symmetric_algorithm(), SymmetricKeyAlgorithm::Aes128);
+
+        encryptor.add_recipient_with_preferences(key, [SymmetricKeyAlgorithm::Twofish]);
+        assert_eq!(encryptor.symmetric_algorithm(), SymmetricKeyAlgorithm::TripleDes);
+
+        encryptor.set_symmetric_algorithm(SymmetricKeyAlgorithm::Aes192);
+        assert_eq!(encryptor.symmetric_algorithm(), SymmetricKeyAlgorithm::Aes192);
+    }
+
+    #[test]
+    fn no_recipients() {
+        let err = Encryptor::new().encrypt(&literal()).unwrap_err();
+        assert_eq!(err.to_string(), EncryptionError::NoRecipients.to_string());
+    }
+}
@@ -7,9 +7,11 @@ use md5::Md5;
 use nom::{be_u16, be_u8, ErrorKind, IResult};
 use nom::Err as NomErr;
 use num::BigUint;
+use rand;
 use sha1::Sha1;
 
 use s2k::{StringToKey, s2k};
+use symmetric::Cfb;
 use types::*;
 use util::{pgp_mpi, pgp_time};
 
@@ -58,6 +60,47 @@ named!(
 
 named!(elgamal_privkey<ElgamalPrivateKey>, map!(pgp_mpi, ElgamalPrivateKey));
 
+named!(curve_oid<Curve>, map!(length_data!(be_u8), Curve::from));
+
+named!(ec_point<Vec<u8>>, map!(pgp_mpi, |point| point.to_bytes_be()));
+
+named!(
+    ecdsa_pubkey<EcdsaPublicKey>,
+    do_parse!(
+        curve: curve_oid >>
+        point: ec_point >>
+        (EcdsaPublicKey { curve, point })
+    )
+);
+
+named!(ecdsa_privkey<EcdsaPrivateKey>, map!(pgp_mpi, EcdsaPrivateKey));
+
+named!(
+    eddsa_pubkey<EdDsaPublicKey>,
+    do_parse!(
+        curve: curve_oid >>
+        point: ec_point >>
+        (EdDsaPublicKey { curve, point })
+    )
+);
+
+named!(eddsa_privkey<EdDsaPrivateKey>, map!(pgp_mpi, EdDsaPrivateKey));
+
+named!(
+    ecdh_pubkey<EcdhPublicKey>,
+    do_parse!(
+        curve: curve_oid >>
+        point: ec_point >>
+        // The KDF parameters are preceded by their length (always 3) and a reserved octet.
+        tag!(&[3u8, 1u8]) >>
+        kdf_hash: map!(be_u8, HashAlgorithm::from) >>
+        kdf_cipher: map!(be_u8, SymmetricKeyAlgorithm::from) >>
+        (EcdhPublicKey { curve, point, kdf_hash, kdf_cipher })
+    )
+);
+
+named!(ecdh_privkey<EcdhPrivateKey>, map!(pgp_mpi, EcdhPrivateKey));
+
 named!(
     v3_pubkey<Key>,
     do_parse!(
@@ -75,6 +118,7 @@ named!(
             key_material: KeyMaterial::Rsa(RsaPublicKey { n: rsa_n, e: rsa_e }, None),
             encryption_method: None,
             privkey_checksum: None,
+            encrypted_privkey: None,
         })
     )
 );
@@ -103,6 +147,12 @@ named!(
             PublicKeyAlgorithm::ElgamalEncryptOnly => map!(
                 call!(elgamal_pubkey),
                 |k| KeyMaterial::Elgamal(k, None)
+            ) |
+            PublicKeyAlgorithm::Ecdsa => map!(call!(ecdsa_pubkey), |k| KeyMaterial::Ecdsa(k, None)) |
+            PublicKeyAlgorithm::EdDsa => map!(call!(eddsa_pubkey), |k| KeyMaterial::EdDsa(k, None)) |
+            PublicKeyAlgorithm::EllipticCurve => map!(
+                call!(ecdh_pubkey),
+                |k| KeyMaterial::Ecdh(k, None)
             )) >>
         (Key {
             version: KeyVersion::V4,
@@ -112,6 +162,7 @@ named!(
             key_material: pubkey_material,
             encryption_method: None,
             privkey_checksum: None,
+            encrypted_privkey: None,
         })
     )
 );
@@ -138,7 +189,8 @@ named!(privkey_prefix_s2k<KeyEncryptionMethod>,
         tag!(&[255u8]) >>
         enc_type: map!(be_u8, SymmetricKeyAlgorithm::from) >>
         s2k_specifier: s2k >>
-        iv: take!(enc_type.block_bytes()) >>
+        // GnuPG stubs have no IV, since there is no secret key material to encrypt.
+        iv: take!(if s2k_specifier.is_gnu_stub() { 0 } else { enc_type.block_bytes() }) >>
         (KeyEncryptionMethod::StringToKey(enc_type, Vec::from(iv), s2k_specifier))
     )
 );
@@ -148,7 +200,7 @@ named!(privkey_prefix_s2k_sha1<KeyEncryptionMethod>,
         tag!(&[254u8]) >>
         enc_type: map!(be_u8, SymmetricKeyAlgorithm::from) >>
         s2k_specifier: s2k >>
-        iv: take!(enc_type.block_bytes()) >>
+        iv: take!(if s2k_specifier.is_gnu_stub() { 0 } else { enc_type.block_bytes() }) >>
         (KeyEncryptionMethod::StringToKeySha1(enc_type, Vec::from(iv), s2k_specifier))
     )
 );
@@ -161,6 +213,44 @@ named!(privkey_prefix<KeyEncryptionMethod>,
     )
 );
 
+/// Parse the secret key material matching some public key material.
+fn private_material<'a>(material: &KeyMaterial, inp: &'a [u8]) -> IResult<&'a [u8], KeyMaterial> {
+    let remaining = inp;
+
+    match *material {
+        KeyMaterial::Rsa(ref pub_material, _) => match rsa_privkey(remaining) {
+            IResult::Done(remaining, privkey) => IResult::Done(remaining, KeyMaterial::Rsa(pub_material.clone(), Some(privkey))),
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(i) => IResult::Incomplete(i),
+        },
+        KeyMaterial::Dsa(ref pub_material, _) => match dsa_privkey(remaining) {
+            IResult::Done(remaining, privkey) => IResult::Done(remaining, KeyMaterial::Dsa(pub_material.clone(), Some(privkey))),
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(i) => IResult::Incomplete(i),
+        },
+        KeyMaterial::Elgamal(ref pub_material, _) => match elgamal_privkey(remaining) {
+            IResult::Done(remaining, privkey) => IResult::Done(remaining, KeyMaterial::Elgamal(pub_material.clone(), Some(privkey))),
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(i) => IResult::Incomplete(i),
+        },
+        KeyMaterial::Ecdsa(ref pub_material, _) => match ecdsa_privkey(remaining) {
+            IResult::Done(remaining, privkey) => IResult::Done(remaining, KeyMaterial::Ecdsa(pub_material.clone(), Some(privkey))),
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(i) => IResult::Incomplete(i),
+        },
+        KeyMaterial::EdDsa(ref pub_material, _) => match eddsa_privkey(remaining) {
+            IResult::Done(remaining, privkey) => IResult::Done(remaining, KeyMaterial::EdDsa(pub_material.clone(), Some(privkey))),
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(i) => IResult::Incomplete(i),
+        },
+        KeyMaterial::Ecdh(ref pub_material, _) => match ecdh_privkey(remaining) {
+            IResult::Done(remaining, privkey) => IResult::Done(remaining, KeyMaterial::Ecdh(pub_material.clone(), Some(privkey))),
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(i) => IResult::Incomplete(i),
+        }
+    }
+}
+
 fn parse_key(inp: &[u8]) -> IResult<&[u8], Key> {
     let (remaining, mut key) = match pubkey(inp) {
         IResult::Done(remaining, key) => (remaining, key),
@@ -174,42 +264,34 @@ fn parse_key(inp: &[u8]) -> IResult<&[u8], Key> {
         IResult::Incomplete(_) => return IResult::Done(remaining, key),
     };
 
-    let (remaining, privkey_material) = match key.key_material {
-        KeyMaterial::Rsa(ref pub_material, _) => match rsa_privkey(remaining) {
-            IResult::Done(remaining, privkey) => (remaining, KeyMaterial::Rsa(pub_material.clone(), Some(privkey))),
-            IResult::Error(e) => return IResult::Error(e),
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
-        },
-        KeyMaterial::Dsa(ref pub_material, _) => match dsa_privkey(remaining) {
-            IResult::Done(remaining, privkey) => (remaining, KeyMaterial::Dsa(pub_material.clone(), Some(privkey))),
-            IResult::Error(e) => return IResult::Error(e),
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
-        },
-        KeyMaterial::Elgamal(ref pub_material, _) => match elgamal_privkey(remaining) {
-            IResult::Done(remaining, privkey) => (remaining, KeyMaterial::Elgamal(pub_material.clone(), Some(privkey))),
-            IResult::Error(e) => return IResult::Error(e),
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
-        }
-    };
+    let remaining = match privkey_prefix {
+        KeyEncryptionMethod::Unencrypted => {
+            let (remaining, privkey_material) = match private_material(&key.key_material, remaining) {
+                IResult::Done(remaining, privkey_material) => (remaining, privkey_material),
+                IResult::Error(e) => return IResult::Error(e),
+                IResult::Incomplete(i) => return IResult::Incomplete(i),
+            };
+
+            let (remaining, checksum) = match take!(remaining, 2) {
+                IResult::Done(remaining, checksum) => (remaining, checksum),
+                IResult::Error(e) => return IResult::Error(e),
+                IResult::Incomplete(i) => return IResult::Incomplete(i),
+            };
 
-    let (remaining, checksum) = match privkey_prefix {
-        KeyEncryptionMethod::Unencrypted
-        | KeyEncryptionMethod::SymmetricKey(_, _)
-        | KeyEncryptionMethod::StringToKey(_, _, _) => match take!(remaining, 2) {
-            IResult::Done(remaining, checksum) => (remaining, checksum),
-            IResult::Error(e) => return IResult::Error(e),
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
+            key.key_material = privkey_material;
+            key.privkey_checksum = Some(Vec::from(checksum));
+
+            remaining
         }
-        KeyEncryptionMethod::StringToKeySha1(_, _, _) => match take!(remaining, 20) {
-            IResult::Done(remaining, checksum) => (remaining, checksum),
-            IResult::Error(e) => return IResult::Error(e),
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
+        // Encrypted secret key material, along with its checksum, can't be parsed until it has
+        // been decrypted with Key::unlock.
+        _ => {
+            key.encrypted_privkey = Some(Vec::from(remaining));
+            &remaining[remaining.len()..]
         }
     };
 
-    key.key_material = privkey_material;
     key.encryption_method = Some(privkey_prefix);
-    key.privkey_checksum = Some(Vec::from(checksum));
 
     IResult::Done(remaining, key)
 }
@@ -223,6 +305,7 @@ pub struct Key {
     pub key_material: KeyMaterial,
     pub encryption_method: Option<KeyEncryptionMethod>,
     pub privkey_checksum: Option<Vec<u8>>,
+    encrypted_privkey: Option<Vec<u8>>,
 }
 
 impl Key {
@@ -248,15 +331,18 @@ impl Key {
     }
 
     pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
-        let mut out = Vec::new();
-
-        out.push(4u8);
-        out.write_u32::<BigEndian>(self.creation_time.as_secs() as u32)?;
-        out.push(self.pubkey_algorithm as u8);
-        out.extend(&self.key_material.public_to_bytes()?);
+        let mut out = self.public_to_bytes()?;
 
         let private_bytes = self.key_material.private_to_bytes()?;
-        if !private_bytes.is_empty() {
+        if let Some(ref encrypted) = self.encrypted_privkey {
+            match self.encryption_method {
+                None | Some(KeyEncryptionMethod::Unencrypted) => {
+                    bail!(KeyError::InvalidEncryption)
+                }
+                Some(ref method) => out.extend(&method.to_bytes()),
+            }
+            out.extend(encrypted);
+        } else if !private_bytes.is_empty() {
             match self.encryption_method {
                 None => bail!(KeyError::InvalidEncryption),
                 Some(KeyEncryptionMethod::Unencrypted) => {
@@ -278,10 +364,186 @@ impl Key {
         Ok(out)
     }
 
+    /// Serialize only the public parts of this key, as in a public key packet, whether or not it
+    /// holds secret key material.
+    pub fn public_to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+
+        out.push(4u8);
+        out.write_u32::<BigEndian>(self.creation_time.as_secs() as u32)?;
+        out.push(self.pubkey_algorithm as u8);
+        out.extend(&self.key_material.public_to_bytes()?);
+
+        Ok(out)
+    }
+
+    /// Describe the secret key material this key holds, if any.
+    pub fn secret_key_status(&self) -> SecretKeyStatus {
+        let s2k = match self.encryption_method {
+            None => return SecretKeyStatus::Missing,
+            Some(KeyEncryptionMethod::Unencrypted) => {
+                return match self.key_material.private_to_bytes() {
+                    Ok(ref material) if !material.is_empty() => SecretKeyStatus::Unencrypted,
+                    _ => SecretKeyStatus::Missing,
+                };
+            }
+            Some(KeyEncryptionMethod::SymmetricKey(_, _)) => return SecretKeyStatus::Encrypted,
+            Some(KeyEncryptionMethod::StringToKey(_, _, ref s2k))
+            | Some(KeyEncryptionMethod::StringToKeySha1(_, _, ref s2k)) => s2k,
+        };
+
+        match *s2k {
+            StringToKey::GnuDummy(_) => SecretKeyStatus::GnuDummy,
+            StringToKey::GnuDivertToCard(_, ref serial) => {
+                SecretKeyStatus::GnuDivertToCard(serial.clone())
+            }
+            _ => SecretKeyStatus::Encrypted,
+        }
+    }
+
+    /// Return a copy of this key with all secret key material removed, as it would appear in a
+    /// public key packet.
+    pub fn to_public(&self) -> Key {
+        let mut key = self.clone();
+        key.key_material = self.key_material.public();
+        key.encryption_method = None;
+        key.privkey_checksum = None;
+        key.encrypted_privkey = None;
+
+        key
+    }
+
+    /// Whether this key's secret key material is encrypted, and must be decrypted with
+    /// [`unlock`] before it can be used.
+    ///
+    /// [`unlock`]: #method.unlock
+    pub fn is_locked(&self) -> bool {
+        self.encrypted_privkey.is_some()
+    }
+
+    /// Decrypt this key's secret key material with a passphrase, returning a copy of the key
+    /// holding the unencrypted secret key material.
+    ///
+    /// Keys that aren't locked are returned unchanged. A passphrase that fails the integrity check
+    /// on the decrypted material results in a [`KeyError::WrongPassphrase`].
+    ///
+    /// [`KeyError::WrongPassphrase`]: enum.KeyError.html#variant.WrongPassphrase
+    pub fn unlock<T: AsRef<[u8]>>(&self, passphrase: T) -> Result<Key, Error> {
+        let encrypted = match self.encrypted_privkey {
+            Some(ref encrypted) => encrypted,
+            None => return Ok(self.clone()),
+        };
+
+        // v3 keys encrypt each MPI separately, leaving their lengths in the clear.
+        if self.version == KeyVersion::V3 {
+            bail!(KeyError::UnimplementedEncryption {
+                method: "v3 key encryption".to_string(),
+            });
+        }
+
+        // Legacy keys that only give a cipher derive its key with a simple MD5 S2K.
+        let (algo, iv, s2k, sha1_check) = match self.encryption_method {
+            Some(KeyEncryptionMethod::SymmetricKey(algo, ref iv)) => {
+                (algo, iv, StringToKey::Simple(HashAlgorithm::Md5), false)
+            }
+            Some(KeyEncryptionMethod::StringToKey(algo, ref iv, ref s2k)) => {
+                (algo, iv, s2k.clone(), false)
+            }
+            Some(KeyEncryptionMethod::StringToKeySha1(algo, ref iv, ref s2k)) => {
+                (algo, iv, s2k.clone(), true)
+            }
+            _ => bail!(KeyError::InvalidEncryption),
+        };
+
+        if s2k.is_gnu_stub() {
+            bail!(KeyError::MissingSecretKey);
+        }
+
+        let session_key = s2k.derive_key(passphrase, algo.key_bytes())?;
+        let mut plaintext = encrypted.clone();
+        Cfb::new(algo, &session_key, iv)?.decrypt(&mut plaintext);
+
+        let check_len = if sha1_check { 20 } else { 2 };
+        if plaintext.len() < check_len {
+            return Err(KeyError::WrongPassphrase.into());
+        }
+
+        let (material, check) = plaintext.split_at(plaintext.len() - check_len);
+        let valid = if sha1_check {
+            Sha1::digest(material).as_ref() == check
+        } else {
+            privkey_checksum(material) == check
+        };
+        if !valid {
+            return Err(KeyError::WrongPassphrase.into());
+        }
+
+        let key_material = match private_material(&self.key_material, material) {
+            IResult::Done(_, key_material) => key_material,
+            _ => bail!(KeyError::MalformedMpi),
+        };
+
+        let mut key = self.clone();
+        key.key_material = key_material;
+        key.encryption_method = Some(KeyEncryptionMethod::Unencrypted);
+        key.privkey_checksum = Some(privkey_checksum(material));
+        key.encrypted_privkey = None;
+
+        Ok(key)
+    }
+
+    /// Encrypt this key's secret key material with a passphrase, returning a copy of the key
+    /// holding the encrypted secret key material.
+    ///
+    /// The key is protected as GnuPG does: an iterated and salted SHA-256 S2K hashing `s2k_count`
+    /// octets with a random salt, AES-256 in CFB mode with a random IV, and a SHA-1 integrity
+    /// check. Locked keys must be [`unlock`]ed first.
+    ///
+    /// [`unlock`]: #method.unlock
+    pub fn protect<T: AsRef<[u8]>>(&self, passphrase: T, s2k_count: u32) -> Result<Key, Error> {
+        if self.is_locked() {
+            bail!(KeyError::Locked);
+        }
+
+        let mut plaintext = self.key_material.private_to_bytes()?;
+        if plaintext.is_empty() {
+            bail!(KeyError::MissingSecretKey);
+        }
+        let check = Sha1::digest(&plaintext);
+        plaintext.extend(check.as_ref());
+
+        let algo = SymmetricKeyAlgorithm::Aes256;
+        let s2k = StringToKey::iterated(HashAlgorithm::Sha256, s2k_count);
+        let iv = rand::random::<[u8; 16]>();
+
+        let session_key = s2k.derive_key(passphrase, algo.key_bytes())?;
+        Cfb::new(algo, &session_key, &iv)?.encrypt(&mut plaintext);
+
+        let mut key = self.clone();
+        key.key_material = self.key_material.public();
+        key.encryption_method = Some(KeyEncryptionMethod::StringToKeySha1(
+            algo,
+            Vec::from(&iv[..]),
+            s2k,
+        ));
+        key.privkey_checksum = None;
+        key.encrypted_privkey = Some(plaintext);
+
+        Ok(key)
+    }
+
     pub fn expiration_time(&self) -> Option<Duration> {
         self.expiration_time
     }
 
+    /// The version of this key's packet: 3 or 4.
+    pub fn version(&self) -> u8 {
+        match self.version {
+            KeyVersion::V3 => 3,
+            KeyVersion::V4 => 4,
+        }
+    }
+
     pub fn fingerprint(&self) -> Result<Vec<u8>, Error> {
         match self.version {
             KeyVersion::V3 => {
@@ -292,12 +554,7 @@ impl Key {
                 let mut hash_payload = Vec::new();
                 hash_payload.push(0x99);
 
-                let mut key_data = Vec::new();
-                key_data.push(0x4);
-                key_data.write_u32::<BigEndian>(self.creation_time.as_secs() as u32)?;
-                key_data.push(self.pubkey_algorithm as u8);
-                key_data.extend(self.key_material.public_to_bytes()?);
-
+                let key_data = self.public_to_bytes()?;
                 hash_payload.write_u16::<BigEndian>(key_data.len() as u16)?;
                 hash_payload.extend(&key_data);
 
@@ -315,6 +572,9 @@ impl Key {
                 }
                 KeyMaterial::Dsa(_, _) => bail!("v3 DSA keys are unsupported"),
                 KeyMaterial::Elgamal(_, _) => bail!("v3 Elgamal keys are unsupported"),
+                KeyMaterial::Ecdsa(_, _) | KeyMaterial::EdDsa(_, _) | KeyMaterial::Ecdh(_, _) => {
+                    bail!("v3 elliptic curve keys are unsupported")
+                }
             },
             KeyVersion::V4 => self.fingerprint().map(|mut f| {
                 let len = f.len();
@@ -337,9 +597,24 @@ pub enum KeyMaterial {
     Rsa(RsaPublicKey, Option<RsaPrivateKey>),
     Dsa(DsaPublicKey, Option<DsaPrivateKey>),
     Elgamal(ElgamalPublicKey, Option<ElgamalPrivateKey>),
+    Ecdsa(EcdsaPublicKey, Option<EcdsaPrivateKey>),
+    EdDsa(EdDsaPublicKey, Option<EdDsaPrivateKey>),
+    Ecdh(EcdhPublicKey, Option<EcdhPrivateKey>),
 }
 
 impl KeyMaterial {
+    /// Retrieve a copy of this key material without any secret key material.
+    pub fn public(&self) -> KeyMaterial {
+        match *self {
+            KeyMaterial::Rsa(ref public, _) => KeyMaterial::Rsa(public.clone(), None),
+            KeyMaterial::Dsa(ref public, _) => KeyMaterial::Dsa(public.clone(), None),
+            KeyMaterial::Elgamal(ref public, _) => KeyMaterial::Elgamal(public.clone(), None),
+            KeyMaterial::Ecdsa(ref public, _) => KeyMaterial::Ecdsa(public.clone(), None),
+            KeyMaterial::EdDsa(ref public, _) => KeyMaterial::EdDsa(public.clone(), None),
+            KeyMaterial::Ecdh(ref public, _) => KeyMaterial::Ecdh(public.clone(), None),
+        }
+    }
+
     pub fn public_to_bytes(&self) -> Result<Vec<u8>, Error> {
         let mut out = Vec::new();
 
@@ -368,6 +643,19 @@ impl KeyMaterial {
                 out.write_u16::<BigEndian>(public.y.bits() as u16)?;
                 out.extend(&public.y.to_bytes_be());
             }
+            &KeyMaterial::Ecdsa(ref public, _) => {
+                write_curve_point(&mut out, &public.curve, &public.point)?;
+            }
+            &KeyMaterial::EdDsa(ref public, _) => {
+                write_curve_point(&mut out, &public.curve, &public.point)?;
+            }
+            &KeyMaterial::Ecdh(ref public, _) => {
+                write_curve_point(&mut out, &public.curve, &public.point)?;
+                out.push(3);
+                out.push(1);
+                out.push(public.kdf_hash.into());
+                out.push(public.kdf_cipher.into());
+            }
         }
 
         Ok(out)
@@ -391,19 +679,87 @@ impl KeyMaterial {
                 out.write_u16::<BigEndian>(private.bits() as u16)?;
                 out.extend(&private.to_bytes_be());
             }
-            &KeyMaterial::Elgamal(_, Some(ElgamalPrivateKey(ref private))) => {
+            &KeyMaterial::Elgamal(_, Some(ElgamalPrivateKey(ref private)))
+            | &KeyMaterial::Ecdsa(_, Some(EcdsaPrivateKey(ref private)))
+            | &KeyMaterial::EdDsa(_, Some(EdDsaPrivateKey(ref private)))
+            | &KeyMaterial::Ecdh(_, Some(EcdhPrivateKey(ref private))) => {
                 out.write_u16::<BigEndian>(private.bits() as u16)?;
                 out.extend(&private.to_bytes_be());
             }
             &KeyMaterial::Rsa(_, None)
             | &KeyMaterial::Dsa(_, None)
-            | &KeyMaterial::Elgamal(_, None) => {}
+            | &KeyMaterial::Elgamal(_, None)
+            | &KeyMaterial::Ecdsa(_, None)
+            | &KeyMaterial::EdDsa(_, None)
+            | &KeyMaterial::Ecdh(_, None) => {}
         }
 
         Ok(out)
     }
 }
 
+fn write_curve_point(out: &mut Vec<u8>, curve: &Curve, point: &[u8]) -> Result<(), Error> {
+    let oid = curve.oid();
+    out.push(oid.len() as u8);
+    out.extend(oid);
+
+    let point = BigUint::from_bytes_be(point);
+    out.write_u16::<BigEndian>(point.bits() as u16)?;
+    out.extend(&point.to_bytes_be());
+
+    Ok(())
+}
+
+/// Decode an elliptic curve point from its OpenPGP MPI representation into the curve's native
+/// encoding: the SEC1 uncompressed encoding for Weierstrass curves, or the 32-octet encoding for
+/// Ed25519 and Curve25519.
+pub(crate) fn native_point<'a>(curve: &Curve, point: &'a [u8]) -> Result<&'a [u8], Error> {
+    let field_bytes = match curve.field_bytes() {
+        Some(field_bytes) => field_bytes,
+        None => bail!(KeyError::UnsupportedCurve {
+            curve: format!("{:?}", curve),
+        }),
+    };
+
+    match *curve {
+        // Points on these curves are prefixed with 0x40 to mark them as native encodings.
+        Curve::Ed25519 | Curve::Curve25519 => {
+            if point.len() != field_bytes + 1 || point[0] != 0x40 {
+                bail!(KeyError::InvalidPoint);
+            }
+
+            Ok(&point[1..])
+        }
+        _ => {
+            if point.len() != 2 * field_bytes + 1 || point[0] != 0x04 {
+                bail!(KeyError::InvalidPoint);
+            }
+
+            Ok(point)
+        }
+    }
+}
+
+/// The secret key material held by a [`Key`], as returned by [`Key::secret_key_status`].
+///
+/// [`Key`]: struct.Key.html
+/// [`Key::secret_key_status`]: struct.Key.html#method.secret_key_status
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretKeyStatus {
+    /// The key holds no secret key material, as in a public key packet.
+    Missing,
+    /// The secret key material is available as-is.
+    Unencrypted,
+    /// The secret key material is protected with a passphrase, and must be [`unlock`]ed.
+    ///
+    /// [`unlock`]: struct.Key.html#method.unlock
+    Encrypted,
+    /// The secret key material was removed by GnuPG, leaving a stub ("gnu-dummy").
+    GnuDummy,
+    /// The secret key material lives on the smartcard with the given serial number.
+    GnuDivertToCard(Vec<u8>),
+}
+
 #[derive(Clone, Debug)]
 pub enum KeyEncryptionMethod {
     Unencrypted,
@@ -412,6 +768,46 @@ pub enum KeyEncryptionMethod {
     StringToKeySha1(SymmetricKeyAlgorithm, Vec<u8>, StringToKey)
 }
 
+impl KeyEncryptionMethod {
+    /// Serialize the string-to-key usage octet and the fields following it, up to and including
+    /// the IV.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        match *self {
+            KeyEncryptionMethod::Unencrypted => out.push(0),
+            KeyEncryptionMethod::SymmetricKey(algo, ref iv) => {
+                out.push(algo.into());
+                out.extend(iv);
+            }
+            KeyEncryptionMethod::StringToKey(algo, ref iv, ref s2k) => {
+                out.push(255);
+                out.push(algo.into());
+                out.extend(&s2k.to_bytes());
+                out.extend(iv);
+            }
+            KeyEncryptionMethod::StringToKeySha1(algo, ref iv, ref s2k) => {
+                out.push(254);
+                out.push(algo.into());
+                out.extend(&s2k.to_bytes());
+                out.extend(iv);
+            }
+        }
+
+        out
+    }
+}
+
+/// Compute the two-octet checksum of some secret key material: the sum of its octets, modulo
+/// 65536.
+fn privkey_checksum(material: &[u8]) -> Vec<u8> {
+    let sum = material
+        .iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(u16::from(*byte)));
+
+    vec![(sum >> 8) as u8, sum as u8]
+}
+
 #[derive(Clone, Debug)]
 pub struct RsaPublicKey {
     pub n: BigUint,
@@ -447,6 +843,60 @@ pub struct ElgamalPublicKey {
 #[derive(Clone, Debug)]
 pub struct ElgamalPrivateKey(pub BigUint);
 
+#[derive(Clone, Debug)]
+pub struct EcdsaPublicKey {
+    pub curve: Curve,
+    pub point: Vec<u8>,
+}
+
+impl EcdsaPublicKey {
+    /// Retrieve this key's public point in the SEC1 uncompressed encoding.
+    pub fn native_point(&self) -> Result<&[u8], Error> {
+        native_point(&self.curve, &self.point)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EcdsaPrivateKey(pub BigUint);
+
+#[derive(Clone, Debug)]
+pub struct EdDsaPublicKey {
+    pub curve: Curve,
+    pub point: Vec<u8>,
+}
+
+impl EdDsaPublicKey {
+    /// Retrieve this key's public point in the curve's native encoding.
+    pub fn native_point(&self) -> Result<&[u8], Error> {
+        native_point(&self.curve, &self.point)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EdDsaPrivateKey(pub BigUint);
+
+/// Public key material for ECDH keys, including the parameters of the key derivation function
+/// described in [RFC6637 &sect;7].
+///
+/// [RFC6637 &sect;7]: https://tools.ietf.org/html/rfc6637#section-7
+#[derive(Clone, Debug)]
+pub struct EcdhPublicKey {
+    pub curve: Curve,
+    pub point: Vec<u8>,
+    pub kdf_hash: HashAlgorithm,
+    pub kdf_cipher: SymmetricKeyAlgorithm,
+}
+
+impl EcdhPublicKey {
+    /// Retrieve this key's public point in the curve's native encoding.
+    pub fn native_point(&self) -> Result<&[u8], Error> {
+        native_point(&self.curve, &self.point)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EcdhPrivateKey(pub BigUint);
+
 #[derive(Debug, Fail)]
 pub enum KeyError {
     #[fail(display = "Invalid key format: {}", reason)]
@@ -459,4 +909,224 @@ pub enum KeyError {
     UnimplementedEncryption { method: String },
     #[fail(display = "Malformed MPI payload")]
     MalformedMpi,
+    #[fail(display = "Unsupported elliptic curve: {}", curve)]
+    UnsupportedCurve { curve: String },
+    #[fail(display = "Invalid elliptic curve point")]
+    InvalidPoint,
+    #[fail(display = "Wrong passphrase")]
+    WrongPassphrase,
+    #[fail(display = "Secret key material is encrypted")]
+    Locked,
+    #[fail(display = "Key has no secret key material")]
+    MissingSecretKey,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use certificate::Certificate;
+    use ed25519_dalek;
+    use packet::Packet;
+    use util::fixed_bytes;
+
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const P256_CERTIFICATE: &[u8] = include_bytes!("../tests/data/enistp256.pub");
+    const P384_CERTIFICATE: &[u8] = include_bytes!("../tests/data/nistp384.pub");
+    const P521_CERTIFICATE: &[u8] = include_bytes!("../tests/data/nistp521.pub");
+    const BRAINPOOL_CERTIFICATE: &[u8] = include_bytes!("../tests/data/brainpool.pub");
+    const PROTECTED_SECRET_KEY: &[u8] = include_bytes!("../tests/data/protected.sec");
+
+    fn protected_key() -> Key {
+        match Packet::from_bytes(PROTECTED_SECRET_KEY).unwrap().0 {
+            Packet::SecretKey(key) => key,
+            p => panic!("expected a secret key, found {:?}", p),
+        }
+    }
+
+    // Check the primary ECDSA key and ECDH subkey of a certificate made by GnuPG.
+    fn check_nist_certificate(
+        certificate: &[u8],
+        ids: (u64, u64),
+        curve: Curve,
+        kdf: (HashAlgorithm, SymmetricKeyAlgorithm),
+    ) {
+        let certificate = Certificate::from_bytes(certificate).unwrap();
+        let point_len = 2 * curve.field_bytes().unwrap() + 1;
+
+        let primary = &certificate.primary;
+        assert_eq!(primary.id().unwrap(), ids.0);
+        match primary.key_material {
+            KeyMaterial::Ecdsa(ref public, None) => {
+                assert_eq!(public.curve, curve);
+                assert_eq!(public.native_point().unwrap().len(), point_len);
+            }
+            ref material => panic!("expected an ECDSA key, found {:?}", material),
+        }
+
+        let subkey = &certificate.subkeys[0].key;
+        assert_eq!(subkey.id().unwrap(), ids.1);
+        match subkey.key_material {
+            KeyMaterial::Ecdh(ref public, None) => {
+                assert_eq!(public.curve, curve);
+                assert_eq!(public.native_point().unwrap().len(), point_len);
+                assert_eq!((public.kdf_hash, public.kdf_cipher), kdf);
+            }
+            ref material => panic!("expected an ECDH key, found {:?}", material),
+        }
+    }
+
+    #[test]
+    fn ed25519_and_curve25519_keys() {
+        let certificate = Certificate::from_bytes(ED_CERTIFICATE).unwrap();
+
+        let primary = &certificate.primary;
+        assert_eq!(primary.id().unwrap(), 0xFAB9_C2B7_EE84_CBA8);
+        match primary.key_material {
+            KeyMaterial::EdDsa(ref public, None) => {
+                assert_eq!(public.curve, Curve::Ed25519);
+                assert_eq!(public.native_point().unwrap().len(), 32);
+            }
+            ref material => panic!("expected an EdDSA key, found {:?}", material),
+        }
+
+        let subkey = &certificate.subkeys[0].key;
+        assert_eq!(subkey.id().unwrap(), 0xD88B_EE62_C596_6048);
+        match subkey.key_material {
+            KeyMaterial::Ecdh(ref public, None) => {
+                assert_eq!(public.curve, Curve::Curve25519);
+                assert_eq!(public.native_point().unwrap().len(), 32);
+                assert_eq!(public.kdf_hash, HashAlgorithm::Sha256);
+                assert_eq!(public.kdf_cipher, SymmetricKeyAlgorithm::Aes128);
+            }
+            ref material => panic!("expected an ECDH key, found {:?}", material),
+        }
+    }
+
+    #[test]
+    fn nist_keys() {
+        check_nist_certificate(
+            P256_CERTIFICATE,
+            (0x90D7_3F69_D1F8_BD9D, 0xB5B0_DF7B_B4E4_FCD3),
+            Curve::NistP256,
+            (HashAlgorithm::Sha256, SymmetricKeyAlgorithm::Aes128),
+        );
+        check_nist_certificate(
+            P384_CERTIFICATE,
+            (0x3197_3020_1BA9_0F7C, 0x1966_0526_930F_D575),
+            Curve::NistP384,
+            (HashAlgorithm::Sha384, SymmetricKeyAlgorithm::Aes192),
+        );
+        check_nist_certificate(
+            P521_CERTIFICATE,
+            (0x1A72_DCE7_8496_ABF0, 0x8A5F_816E_CD8A_D6C4),
+            Curve::NistP521,
+            (HashAlgorithm::Sha512, SymmetricKeyAlgorithm::Aes256),
+        );
+    }
+
+    #[test]
+    fn brainpool_key() {
+        let certificate = Certificate::from_bytes(BRAINPOOL_CERTIFICATE).unwrap();
+
+        assert_eq!(certificate.primary.id().unwrap(), 0x2420_44D0_B056_4BA3);
+        match certificate.primary.key_material {
+            KeyMaterial::Ecdsa(ref public, None) => {
+                assert_eq!(public.curve, Curve::BrainpoolP256r1)
+            }
+            ref material => panic!("expected an ECDSA key, found {:?}", material),
+        }
+    }
+
+    #[test]
+    fn reject_invalid_point() {
+        let mut key = Certificate::from_bytes(P256_CERTIFICATE).unwrap().primary;
+        match key.key_material {
+            KeyMaterial::Ecdsa(ref mut public, _) => {
+                // Compressed points aren't used by OpenPGP.
+                public.point[0] = 0x02;
+
+                let err = public.native_point().unwrap_err();
+                assert_eq!(err.to_string(), KeyError::InvalidPoint.to_string());
+            }
+            ref material => panic!("expected an ECDSA key, found {:?}", material),
+        }
+    }
+
+    #[test]
+    fn unlock_gnupg_key() {
+        let key = protected_key();
+        assert!(key.is_locked());
+        match key.encryption_method {
+            Some(KeyEncryptionMethod::StringToKeySha1(_, _, StringToKey::IteratedSalted(..))) => {}
+            ref method => panic!("unexpected encryption method {:?}", method),
+        }
+
+        let unlocked = key.unlock("password").unwrap();
+        assert!(!unlocked.is_locked());
+        assert_eq!(unlocked.fingerprint().unwrap(), key.fingerprint().unwrap());
+
+        // The decrypted scalar must be the one behind the public point.
+        match unlocked.key_material {
+            KeyMaterial::EdDsa(ref public, Some(ref private)) => {
+                let mut secret = [0u8; 32];
+                secret.copy_from_slice(&fixed_bytes(&private.0, 32).unwrap());
+                let signing_key = ed25519_dalek::SigningKey::from_bytes(&secret);
+                assert_eq!(
+                    &signing_key.verifying_key().to_bytes()[..],
+                    public.native_point().unwrap()
+                );
+            }
+            ref material => panic!("expected an EdDSA secret key, found {:?}", material),
+        }
+    }
+
+    #[test]
+    fn unlock_wrong_passphrase() {
+        match protected_key().unlock("drowssap").unwrap_err().downcast::<KeyError>() {
+            Ok(KeyError::WrongPassphrase) => {}
+            err => panic!("expected a wrong passphrase, found {:?}", err),
+        }
+    }
+
+    #[test]
+    fn protect_round_trip() {
+        let unlocked = protected_key().unlock("password").unwrap();
+        let protected = unlocked.protect("correct horse", 65536).unwrap();
+        assert!(protected.is_locked());
+        match protected.encryption_method {
+            Some(KeyEncryptionMethod::StringToKeySha1(
+                SymmetricKeyAlgorithm::Aes256,
+                _,
+                StringToKey::IteratedSalted(HashAlgorithm::Sha256, _, 65536),
+            )) => {}
+            ref method => panic!("unexpected encryption method {:?}", method),
+        }
+
+        let read = Key::from_bytes(&protected.to_bytes().unwrap()).unwrap();
+        assert!(read.is_locked());
+        match read.unlock("password").unwrap_err().downcast::<KeyError>() {
+            Ok(KeyError::WrongPassphrase) => {}
+            err => panic!("expected a wrong passphrase, found {:?}", err),
+        }
+
+        let relocked = read.unlock("correct horse").unwrap();
+        assert_eq!(
+            relocked.key_material.private_to_bytes().unwrap(),
+            unlocked.key_material.private_to_bytes().unwrap()
+        );
+        assert_eq!(relocked.to_bytes().unwrap(), unlocked.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn protect_locked_key() {
+        let err = protected_key().protect("correct horse", 65536).unwrap_err();
+        assert_eq!(err.to_string(), KeyError::Locked.to_string());
+    }
+
+    #[test]
+    fn protect_public_key() {
+        let key = Certificate::from_bytes(ED_CERTIFICATE).unwrap().primary;
+        let err = key.protect("correct horse", 65536).unwrap_err();
+        assert_eq!(err.to_string(), KeyError::MissingSecretKey.to_string());
+    }
 }
@@ -11,44 +11,91 @@
 //! signatures.
 //!
 //! [`Packet`]s are read by calling [`Packet::from_bytes`], and can be serialized by calling
-//! [`Packet::to_bytes`].
+//! [`Packet::to_bytes`]. ASCII-armored data can be read and written with [`Armor`].
 //!
+//! [`Armor`]: struct.Armor.html
 //! [`Packet`]: enum.Packet.html
 //! [`Packet::to_bytes`]: enum.Packet.html#method.to_bytes
 //! [`Packet::from_bytes`]: enum.Packet.html#method.from_bytes
 //! [`SignaturePacket`]: struct.SignaturePacket.html
+extern crate aes;
+extern crate base64;
+extern crate blowfish;
 extern crate byteorder;
 extern crate bzip2;
+extern crate cast5;
+extern crate cipher;
+extern crate des;
 extern crate digest;
+extern crate ed25519_dalek;
 #[macro_use]
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
 extern crate flate2;
+extern crate idea;
 extern crate md5;
 #[macro_use]
 extern crate nom;
 extern crate num;
+extern crate p256;
+extern crate p384;
+extern crate p521;
+extern crate rand;
 extern crate ripemd160;
 extern crate sha1;
 extern crate sha2;
+extern crate twofish;
+extern crate x25519_dalek;
 extern crate yasna;
 
+mod armor;
+mod certificate;
+mod cleartext;
 mod compression;
+mod decryptor;
+mod encryptor;
 mod key;
 mod literal;
 mod marker;
+mod message;
+mod onepass;
 mod packet;
+mod pkesk;
+mod reader;
 mod s2k;
+mod seipd;
 mod signature;
+mod signer;
+mod skesk;
+mod symmetric;
 mod types;
 mod userid;
 mod util;
+mod validity;
+mod verify;
+mod writer;
 
+pub use armor::*;
+pub use certificate::*;
+pub use cleartext::*;
 pub use compression::*;
+pub use decryptor::*;
+pub use encryptor::*;
 pub use key::*;
 pub use literal::*;
+pub use message::*;
+pub use onepass::*;
 pub use packet::*;
+pub use pkesk::*;
+pub use reader::*;
 pub use s2k::*;
+pub use seipd::*;
 pub use signature::*;
+pub use signer::*;
+pub use skesk::*;
+pub use symmetric::SymmetricError;
 pub use types::*;
+pub use validity::*;
+pub use verify::*;
+pub use writer::*;
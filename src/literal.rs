@@ -1,7 +1,8 @@
+use std::io::Read;
 use std::str;
 use std::time::Duration;
 
-use byteorder::{BigEndian, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use failure::Error;
 use nom::{rest, be_u32, be_u8};
 use nom::{ErrorKind, IResult};
@@ -112,6 +113,83 @@ impl LiteralData {
     }
 }
 
+/// The format of the contents of a Literal Data packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiteralFormat {
+    Binary,
+    Text,
+    Utf8,
+}
+
+impl LiteralFormat {
+    fn from_tag(tag: u8) -> Result<LiteralFormat, Error> {
+        match tag {
+            b'b' => Ok(LiteralFormat::Binary),
+            b't' => Ok(LiteralFormat::Text),
+            b'u' => Ok(LiteralFormat::Utf8),
+            _ => bail!(LiteralError::InvalidTag {
+                tag: String::from_utf8_lossy(&[tag]).into_owned(),
+            }),
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match *self {
+            LiteralFormat::Binary => b'b',
+            LiteralFormat::Text => b't',
+            LiteralFormat::Utf8 => b'u',
+        }
+    }
+}
+
+/// The fields of a Literal Data packet preceding its contents, for use when the contents are
+/// streamed rather than held in a [`LiteralPacket`].
+///
+/// [`LiteralPacket`]: struct.LiteralPacket.html
+#[derive(Clone, Debug)]
+pub struct LiteralHeader {
+    pub format: LiteralFormat,
+    pub filename: String,
+    pub timestamp: Duration,
+}
+
+impl LiteralHeader {
+    /// Read a literal data header from the start of a packet body, leaving the body positioned at
+    /// the literal contents.
+    pub(crate) fn from_reader<R: Read>(reader: &mut R) -> Result<LiteralHeader, Error> {
+        let format = LiteralFormat::from_tag(reader.read_u8()?)?;
+
+        let mut filename = vec![0u8; usize::from(reader.read_u8()?)];
+        reader.read_exact(&mut filename)?;
+
+        let timestamp = Duration::from_secs(u64::from(reader.read_u32::<BigEndian>()?));
+
+        Ok(LiteralHeader {
+            format,
+            filename: String::from_utf8(filename)?,
+            timestamp,
+        })
+    }
+
+    /// Serialize this header to bytes, to be followed by the literal contents.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+
+        out.push(self.format.tag());
+
+        let filename = self.filename.as_bytes();
+        if filename.len() > 255 {
+            bail!(LiteralError::FilenameTooLong);
+        }
+        out.push(filename.len() as u8);
+        out.extend(filename);
+
+        out.write_u32::<BigEndian>(self.timestamp.as_secs() as u32)?;
+
+        Ok(out)
+    }
+}
+
 /// Error type for [`LiteralPacket`]-level errors.
 ///
 /// [`LiteralPacket`]: struct.LiteralPacket.html
@@ -0,0 +1,582 @@
+use std::io;
+use std::io::{Read, Write};
+
+use failure::Error;
+
+use compression::Decompressor;
+use key::Key;
+use literal::LiteralHeader;
+use onepass::OnePassSignaturePacket;
+use packet::Packet;
+use reader::{PacketReader, StreamedPacket};
+use signature::{SignaturePacket, SignatureType, SignedDataHasher};
+use verify::VerificationError;
+
+// Size of the buffer used to stream literal data through the signature hashers.
+const BUFFER_SIZE: usize = 8192;
+
+// How many levels of compressed data may be nested. GnuPG never nests them, so this only stops
+// crafted messages from exhausting the stack.
+const MAX_NESTING_DEPTH: usize = 8;
+
+/// The outcome of checking a single signature over a message.
+#[derive(Clone, Debug)]
+pub struct SignatureVerification {
+    pub signature: SignaturePacket,
+    /// The key that made the signature, if it was among the keys supplied.
+    pub key: Option<Key>,
+    pub result: Result<(), VerificationError>,
+}
+
+impl SignatureVerification {
+    /// Whether the signature verified successfully.
+    pub fn is_valid(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// The key ID of the signature's issuer, if it names one.
+    pub fn issuer(&self) -> Option<u64> {
+        self.signature.signer()
+    }
+}
+
+/// The signatures found in a message by [`verify_message`], along with the header of its literal
+/// data.
+///
+/// [`verify_message`]: fn.verify_message.html
+#[derive(Clone, Debug)]
+pub struct VerifiedMessage {
+    pub literal: LiteralHeader,
+    pub signatures: Vec<SignatureVerification>,
+}
+
+impl VerifiedMessage {
+    /// Whether the message has at least one signature, and all of its signatures verified
+    /// successfully.
+    pub fn all_valid(&self) -> bool {
+        !self.signatures.is_empty() && self.signatures.iter().all(|s| s.is_valid())
+    }
+}
+
+/// Look up the signer of a signature and check it against the digest of the signed data.
+pub(crate) fn check_signature(
+    signature: SignaturePacket,
+    hasher: Result<SignedDataHasher, Error>,
+    keys: &[Key],
+) -> SignatureVerification {
+    let key = signature
+        .signer()
+        .and_then(|signer| keys.iter().find(|key| key.id().ok() == Some(signer)))
+        .cloned();
+
+    let result = match (hasher, key.as_ref()) {
+        (Err(_), _) => Err(VerificationError::UnsupportedAlgorithm {
+            algorithm: format!("{:?}", signature.hash_algo),
+        }),
+        (_, None) => Err(VerificationError::MissingKey),
+        (Ok(hasher), Some(key)) => signature
+            .finish_digest(hasher.finish())
+            .and_then(|digest| signature.verify_digest(&digest, key))
+            .map_err(|e| match e.downcast::<VerificationError>() {
+                Ok(e) => e,
+                Err(e) => VerificationError::Malformed {
+                    reason: format!("{}", e),
+                },
+            }),
+    };
+
+    SignatureVerification {
+        signature,
+        key,
+        result,
+    }
+}
+
+#[derive(Default)]
+struct MessageState {
+    one_pass: Vec<(OnePassSignaturePacket, Result<SignedDataHasher, Error>)>,
+    leading: Vec<(SignaturePacket, Result<SignedDataHasher, Error>)>,
+    literal: Option<LiteralHeader>,
+    signatures: Vec<SignatureVerification>,
+}
+
+fn process_packets<'a>(
+    reader: PacketReader<Box<dyn Read + 'a>>,
+    state: &mut MessageState,
+    keys: &[Key],
+    output: &mut dyn Write,
+    depth: usize,
+) -> Result<(), Error> {
+    for packet in reader {
+        match packet? {
+            StreamedPacket::Packet(Packet::Marker) => {}
+            StreamedPacket::Packet(Packet::OnePassSignature(ops)) => {
+                if state.literal.is_some() {
+                    bail!(MessageError::UnexpectedPacket {
+                        packet_type: "OnePassSignature".to_string(),
+                    });
+                }
+
+                let hasher = SignedDataHasher::new(ops.hash_algo, ops.sig_type);
+                state.one_pass.push((ops, hasher));
+            }
+            StreamedPacket::Packet(Packet::Signature(signature)) => {
+                if state.literal.is_none() {
+                    // Signatures may also precede the data they cover, without one-pass packets.
+                    let hasher = SignedDataHasher::new(signature.hash_algo, signature.sig_type);
+                    state.leading.push((signature, hasher));
+                    continue;
+                }
+
+                // Trailing signatures match the one-pass signatures in reverse order.
+                let (ops, hasher) = match state.one_pass.pop() {
+                    Some(one_pass) => one_pass,
+                    None => bail!(MessageError::UnexpectedPacket {
+                        packet_type: "Signature".to_string(),
+                    }),
+                };
+
+                let verification = if ops.signer != signature.signer().unwrap_or(ops.signer)
+                    || ops.hash_algo != signature.hash_algo
+                    || ops.sig_type != signature.sig_type
+                {
+                    SignatureVerification {
+                        signature,
+                        key: None,
+                        result: Err(VerificationError::Malformed {
+                            reason: "signature does not match its one-pass signature".to_string(),
+                        }),
+                    }
+                } else {
+                    check_signature(signature, hasher, keys)
+                };
+
+                state.signatures.push(verification);
+            }
+            StreamedPacket::LiteralData(header, mut body) => {
+                if state.literal.is_some() {
+                    bail!(MessageError::UnexpectedPacket {
+                        packet_type: "LiteralData".to_string(),
+                    });
+                }
+
+                let mut buf = vec![0u8; BUFFER_SIZE];
+                loop {
+                    let read = match body.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    let hashers = state
+                        .one_pass
+                        .iter_mut()
+                        .map(|&mut (_, ref mut hasher)| hasher)
+                        .chain(state.leading.iter_mut().map(|&mut (_, ref mut hasher)| hasher));
+                    for hasher in hashers {
+                        if let Ok(ref mut hasher) = *hasher {
+                            hasher.update(&buf[..read]);
+                        }
+                    }
+
+                    output.write_all(&buf[..read])?;
+                }
+
+                for (signature, hasher) in state.leading.drain(..) {
+                    state.signatures.push(check_signature(signature, hasher, keys));
+                }
+
+                state.literal = Some(header);
+            }
+            StreamedPacket::CompressedData(algo, body) => {
+                if depth >= MAX_NESTING_DEPTH {
+                    bail!(MessageError::NestingTooDeep);
+                }
+
+                let decompressor: Box<dyn Read + 'a> = Box::new(Decompressor::new(algo, body)?);
+                let reader = PacketReader::new(decompressor);
+                process_packets(reader, state, keys, output, depth + 1)?;
+            }
+            StreamedPacket::Packet(p) => bail!(MessageError::UnexpectedPacket {
+                packet_type: format!("{:?}", p),
+            }),
+            StreamedPacket::SymmetricEncryptedData(_)
+            | StreamedPacket::SymmetricEncryptedIntegrityProtectedData(_) => {
+                bail!(MessageError::UnexpectedPacket {
+                    packet_type: "encrypted data".to_string(),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify an inline-signed message, as produced by `gpg --sign`, against a set of keys.
+///
+/// The message is read as a stream: its literal data is hashed once for each signature and
+/// written to `output`, so messages of any size can be checked. Compressed data is descended into
+/// as needed, up to eight levels deep. Signatures may either follow the data with matching
+/// one-pass signatures, or precede it.
+///
+/// An error is only returned if the message itself is malformed; the outcome of each signature
+/// is reported in the returned [`VerifiedMessage`].
+///
+/// [`VerifiedMessage`]: struct.VerifiedMessage.html
+pub fn verify_message<R: Read, W: Write>(
+    input: R,
+    keys: &[Key],
+    mut output: W,
+) -> Result<VerifiedMessage, Error> {
+    let mut state = MessageState::default();
+
+    let input: Box<dyn Read> = Box::new(input);
+    process_packets(PacketReader::new(input), &mut state, keys, &mut output, 0)?;
+
+    if !state.one_pass.is_empty() {
+        bail!(MessageError::MissingSignature);
+    }
+
+    match state.literal {
+        Some(literal) => Ok(VerifiedMessage {
+            literal,
+            signatures: state.signatures,
+        }),
+        None => bail!(MessageError::MissingLiteral),
+    }
+}
+
+/// Verify detached signatures, such as those in `.sig` files, over data read from a stream.
+///
+/// The data is read once and hashed incrementally for every signature. Text document signatures
+/// are checked against the data with its line endings converted to `<CR><LF>`. A result is
+/// returned for each signature, in order; signatures that don't cover a document are reported as
+/// malformed.
+pub fn verify_detached<R: Read>(
+    mut data: R,
+    signatures: &[SignaturePacket],
+    keys: &[Key],
+) -> Result<Vec<SignatureVerification>, Error> {
+    let mut hashers = signatures
+        .iter()
+        .map(|signature| match signature.sig_type {
+            SignatureType::BinaryDocument | SignatureType::TextDocument => Some(
+                SignedDataHasher::new(signature.hash_algo, signature.sig_type),
+            ),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = match data.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        for hasher in &mut hashers {
+            if let Some(Ok(ref mut hasher)) = *hasher {
+                hasher.update(&buf[..read]);
+            }
+        }
+    }
+
+    let verifications = signatures
+        .iter()
+        .zip(hashers)
+        .map(|(signature, hasher)| match hasher {
+            Some(hasher) => check_signature(signature.clone(), hasher, keys),
+            None => SignatureVerification {
+                signature: signature.clone(),
+                key: None,
+                result: Err(VerificationError::Malformed {
+                    reason: format!("{:?} signature is not over a document", signature.sig_type),
+                }),
+            },
+        })
+        .collect();
+
+    Ok(verifications)
+}
+
+/// Error type for message structure errors.
+#[derive(Debug, Fail)]
+pub enum MessageError {
+    #[fail(display = "Message has no literal data")]
+    MissingLiteral,
+    #[fail(display = "One-pass signature has no matching signature")]
+    MissingSignature,
+    #[fail(display = "Unexpected packet in message: {}", packet_type)]
+    UnexpectedPacket { packet_type: String },
+    #[fail(display = "Compressed data is nested too deeply")]
+    NestingTooDeep,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use armor::Armor;
+    use certificate::Certificate;
+    use compression::CompressedDataPacket;
+
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/message.txt");
+    const TEXT: &[u8] = include_bytes!("../tests/data/text.txt");
+    const BRAINPOOL_CERTIFICATE: &[u8] = include_bytes!("../tests/data/brainpool.pub");
+    const BRAINPOOL_SIGNATURE: &[u8] = include_bytes!("../tests/data/brainpool.sig");
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const ED_SIGNATURE: &[u8] = include_bytes!("../tests/data/ed.sig");
+    const ED_ARMORED_SIGNATURE: &[u8] = include_bytes!("../tests/data/ed.sig.asc");
+    const ED_TEXT_SIGNATURE: &[u8] = include_bytes!("../tests/data/ed.text.sig");
+    const ED_SIGNED_MESSAGE: &[u8] = include_bytes!("../tests/data/ed.signed.gpg");
+    const RSA_CERTIFICATE: &[u8] = include_bytes!("../tests/data/rsa.pub");
+    const RSA_SIGNED_MESSAGE: &[u8] = include_bytes!("../tests/data/rsa.signed.gpg");
+    const RSA_SIGNATURE: &[u8] = include_bytes!("../tests/data/rsa.sig");
+
+    fn keys() -> Vec<Key> {
+        vec![
+            Certificate::from_bytes(ED_CERTIFICATE).unwrap().primary,
+            Certificate::from_bytes(RSA_CERTIFICATE).unwrap().primary,
+        ]
+    }
+
+    fn read_signatures(bytes: &[u8]) -> Vec<SignaturePacket> {
+        Packet::all_from_bytes(bytes)
+            .unwrap()
+            .into_iter()
+            .map(|packet| match packet {
+                Packet::Signature(signature) => signature,
+                p => panic!("expected a signature, found {:?}", p),
+            })
+            .collect()
+    }
+
+    // Wrap a message in a Compressed Data packet that stores it uncompressed.
+    fn wrap(message: &[u8]) -> Vec<u8> {
+        let packet = Packet::CompressedData(CompressedDataPacket::Uncompressed(message.to_vec()));
+        packet.to_bytes().unwrap()
+    }
+
+    // A reader that is interrupted before every successful read.
+    struct InterruptingReader<R> {
+        inner: R,
+        interrupt: bool,
+    }
+
+    impl<R: Read> Read for InterruptingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+            }
+
+            self.inner.read(&mut buf[..1])
+        }
+    }
+
+    #[test]
+    fn verify_gnupg_message() {
+        let mut output = Vec::new();
+        let verified = verify_message(ED_SIGNED_MESSAGE, &keys(), &mut output).unwrap();
+
+        assert_eq!(output, MESSAGE);
+        assert_eq!(verified.literal.filename, "message.txt");
+        assert_eq!(verified.signatures.len(), 1);
+        assert_eq!(verified.signatures[0].issuer(), Some(0xFAB9_C2B7_EE84_CBA8));
+        assert!(verified.all_valid());
+    }
+
+    #[test]
+    fn verify_compressed_message() {
+        let mut output = Vec::new();
+        let verified = verify_message(RSA_SIGNED_MESSAGE, &keys(), &mut output).unwrap();
+
+        assert_eq!(output, MESSAGE);
+        assert!(verified.all_valid());
+    }
+
+    #[test]
+    fn verify_leading_signature() {
+        let packets = Packet::all_from_bytes(ED_SIGNED_MESSAGE).unwrap();
+        let mut message = packets[2].to_bytes().unwrap();
+        message.extend(packets[1].to_bytes().unwrap());
+
+        let mut output = Vec::new();
+        let verified = verify_message(&message[..], &keys(), &mut output).unwrap();
+
+        assert_eq!(output, MESSAGE);
+        assert!(verified.all_valid());
+    }
+
+    #[test]
+    fn verify_interrupted_reads() {
+        let input = InterruptingReader {
+            inner: ED_SIGNED_MESSAGE,
+            interrupt: false,
+        };
+
+        let mut output = Vec::new();
+        let verified = verify_message(input, &keys(), &mut output).unwrap();
+
+        assert_eq!(output, MESSAGE);
+        assert!(verified.all_valid());
+    }
+
+    #[test]
+    fn reject_flipped_signature_bit() {
+        let mut message = ED_SIGNED_MESSAGE.to_vec();
+        *message.last_mut().unwrap() ^= 0x01;
+
+        let mut output = Vec::new();
+        let verified = verify_message(&message[..], &keys(), &mut output).unwrap();
+
+        assert!(!verified.all_valid());
+        match verified.signatures[0].result {
+            Err(VerificationError::BadSignature) => {}
+            ref r => panic!("expected a bad signature, found {:?}", r),
+        }
+    }
+
+    #[test]
+    fn nested_compressed_data() {
+        let mut message = ED_SIGNED_MESSAGE.to_vec();
+        for _ in 0..MAX_NESTING_DEPTH {
+            message = wrap(&message);
+        }
+
+        let verified = verify_message(&message[..], &keys(), io::sink()).unwrap();
+        assert!(verified.all_valid());
+
+        // One more level is rejected before it is descended into.
+        let message = wrap(&message);
+        let err = verify_message(&message[..], &keys(), io::sink()).unwrap_err();
+        assert_eq!(err.to_string(), MessageError::NestingTooDeep.to_string());
+    }
+
+    #[test]
+    fn missing_key() {
+        let keys = &keys()[1..];
+        let verified = verify_message(ED_SIGNED_MESSAGE, keys, io::sink()).unwrap();
+
+        assert!(verified.signatures[0].key.is_none());
+        match verified.signatures[0].result {
+            Err(VerificationError::MissingKey) => {}
+            ref r => panic!("expected a missing key, found {:?}", r),
+        }
+    }
+
+    #[test]
+    fn missing_signature() {
+        let packets = Packet::all_from_bytes(ED_SIGNED_MESSAGE).unwrap();
+        let mut message = packets[0].to_bytes().unwrap();
+        message.extend(packets[1].to_bytes().unwrap());
+
+        let err = verify_message(&message[..], &keys(), io::sink()).unwrap_err();
+        assert_eq!(err.to_string(), MessageError::MissingSignature.to_string());
+    }
+
+    #[test]
+    fn missing_literal() {
+        let packets = Packet::all_from_bytes(ED_SIGNED_MESSAGE).unwrap();
+        let message = packets[2].to_bytes().unwrap();
+
+        let err = verify_message(&message[..], &keys(), io::sink()).unwrap_err();
+        assert_eq!(err.to_string(), MessageError::MissingLiteral.to_string());
+    }
+
+    #[test]
+    fn verify_detached_signatures() {
+        let mut signatures = read_signatures(ED_SIGNATURE);
+        signatures.extend(read_signatures(RSA_SIGNATURE));
+
+        let verifications = verify_detached(MESSAGE, &signatures, &keys()).unwrap();
+        assert_eq!(verifications.len(), 2);
+        assert_eq!(verifications[0].issuer(), Some(0xFAB9_C2B7_EE84_CBA8));
+        assert_eq!(verifications[1].issuer(), Some(0x9263_AE56_9FA4_1175));
+        assert!(verifications.iter().all(|v| v.is_valid()));
+    }
+
+    #[test]
+    fn verify_detached_armored_signature() {
+        let (armor, _) = Armor::from_bytes(ED_ARMORED_SIGNATURE).unwrap();
+        let signatures = read_signatures(&armor.contents);
+
+        let verifications = verify_detached(MESSAGE, &signatures, &keys()).unwrap();
+        assert!(verifications[0].is_valid());
+    }
+
+    #[test]
+    fn verify_detached_text_signature() {
+        let signatures = read_signatures(ED_TEXT_SIGNATURE);
+        assert_eq!(signatures[0].sig_type, SignatureType::TextDocument);
+
+        let verifications = verify_detached(TEXT, &signatures, &keys()).unwrap();
+        assert!(verifications[0].is_valid());
+
+        // Converting the line endings doesn't change the signed text.
+        let crlf = String::from_utf8(TEXT.to_vec()).unwrap().replace("\n", "\r\n");
+        let verifications = verify_detached(crlf.as_bytes(), &signatures, &keys()).unwrap();
+        assert!(verifications[0].is_valid());
+    }
+
+    #[test]
+    fn verify_detached_in_pieces() {
+        let input = InterruptingReader {
+            inner: MESSAGE,
+            interrupt: false,
+        };
+
+        let signatures = read_signatures(ED_SIGNATURE);
+        let verifications = verify_detached(input, &signatures, &keys()).unwrap();
+        assert!(verifications[0].is_valid());
+    }
+
+    #[test]
+    fn reject_modified_detached_data() {
+        let mut signatures = read_signatures(ED_SIGNATURE);
+        signatures.extend(read_signatures(RSA_SIGNATURE));
+
+        let verifications = verify_detached(&b"Hello, world?\n"[..], &signatures, &keys()).unwrap();
+        for verification in verifications {
+            match verification.result {
+                Err(VerificationError::BadSignature) => {}
+                r => panic!("expected a bad signature, found {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_detached_signature() {
+        let signatures = read_signatures(BRAINPOOL_SIGNATURE);
+        let keys = [Certificate::from_bytes(BRAINPOOL_CERTIFICATE).unwrap().primary];
+
+        let verifications = verify_detached(MESSAGE, &signatures, &keys).unwrap();
+        match verifications[0].result {
+            Err(VerificationError::UnsupportedAlgorithm { ref algorithm }) => {
+                assert_eq!(algorithm, "BrainpoolP256r1")
+            }
+            ref r => panic!("expected an unsupported algorithm, found {:?}", r),
+        }
+    }
+
+    #[test]
+    fn reject_detached_certification() {
+        let signature = Packet::all_from_bytes(ED_CERTIFICATE)
+            .unwrap()
+            .into_iter()
+            .filter_map(|packet| match packet {
+                Packet::Signature(signature) => Some(signature),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+
+        let verifications = verify_detached(MESSAGE, &[signature], &keys()).unwrap();
+        match verifications[0].result {
+            Err(VerificationError::Malformed { .. }) => {}
+            ref r => panic!("expected a malformed signature, found {:?}", r),
+        }
+    }
+}
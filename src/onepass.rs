@@ -0,0 +1,176 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use failure::Error;
+use nom::{be_u64, be_u8};
+use nom::{ErrorKind, IResult};
+use nom::Err as NomErr;
+
+use signature::{SignaturePacket, SignatureType};
+use types::*;
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+named!(
+    one_pass_signature<OnePassSignaturePacket>,
+    do_parse!(
+        tag!(b"\x03") >>
+        sig_type: be_u8 >>
+        hash_algo: be_u8 >>
+        pubkey_algo: be_u8 >>
+        signer: be_u64 >>
+        last: be_u8 >>
+        (OnePassSignaturePacket {
+            sig_type: SignatureType::from(sig_type),
+            hash_algo: HashAlgorithm::from(hash_algo),
+            pubkey_algo: PublicKeyAlgorithm::from(pubkey_algo),
+            signer,
+            nested: last == 0,
+        })
+    )
+);
+
+/// A One-Pass Signature packet as specified in [RFC4880 &sect;5.4].
+///
+/// One-pass signatures precede the data they cover, so that it can be hashed while it is read; the
+/// matching [`SignaturePacket`]s follow the data. When a message has several signatures, all but
+/// the last one-pass signature are `nested`, and they are matched to the trailing signatures in
+/// reverse order.
+///
+/// [RFC4880 &sect;5.4]: https://tools.ietf.org/html/rfc4880#section-5.4
+/// [`SignaturePacket`]: struct.SignaturePacket.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OnePassSignaturePacket {
+    pub sig_type: SignatureType,
+    pub hash_algo: HashAlgorithm,
+    pub pubkey_algo: PublicKeyAlgorithm,
+    pub signer: u64,
+    /// Whether the next packet is another one-pass signature over the same data.
+    pub nested: bool,
+}
+
+impl OnePassSignaturePacket {
+    /// Create the one-pass signature matching a signature. The signature must have its signer
+    /// set.
+    pub fn from_signature(
+        signature: &SignaturePacket,
+        nested: bool,
+    ) -> Result<OnePassSignaturePacket, Error> {
+        let signer = match signature.signer() {
+            Some(signer) => signer,
+            None => bail!(OnePassSignatureError::MissingSigner),
+        };
+
+        Ok(OnePassSignaturePacket {
+            sig_type: signature.sig_type,
+            hash_algo: signature.hash_algo,
+            pubkey_algo: signature.pubkey_algo,
+            signer,
+            nested,
+        })
+    }
+
+    /// Read in a one-pass signature from some bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<OnePassSignaturePacket, Error> {
+        match one_pass_signature(bytes) {
+            IResult::Done(_, ops) => Ok(ops),
+            IResult::Error(NomErr::Code(ErrorKind::Custom(e))) => {
+                let e = NomError::from(e);
+
+                bail!(OnePassSignatureError::InvalidFormat {
+                    reason: format!("{:?}", e),
+                })
+            }
+            IResult::Error(e) => bail!(OnePassSignatureError::InvalidFormat {
+                reason: format!("{}", e),
+            }),
+            IResult::Incomplete(i) => bail!(OnePassSignatureError::InvalidFormat {
+                reason: format!("{:?}", i),
+            }),
+        }
+    }
+
+    /// Serialize this one-pass signature to bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+
+        // One-pass signature version 3
+        out.push(3);
+        out.push(self.sig_type.into());
+        out.push(self.hash_algo.into());
+        out.push(self.pubkey_algo.into());
+        out.write_u64::<BigEndian>(self.signer)?;
+        out.push(if self.nested { 0 } else { 1 });
+
+        Ok(out)
+    }
+}
+
+/// Error type for [`OnePassSignaturePacket`]-level errors.
+///
+/// [`OnePassSignaturePacket`]: struct.OnePassSignaturePacket.html
+#[derive(Debug, Fail)]
+pub enum OnePassSignatureError {
+    #[fail(display = "Invalid one-pass signature format: {}", reason)]
+    InvalidFormat { reason: String },
+    #[fail(display = "Signature has no signer")]
+    MissingSigner,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet::Packet;
+
+    const SIGNED_MESSAGE: &[u8] = include_bytes!("../tests/data/ed.signed.gpg");
+
+    #[test]
+    fn matches_gnupg_signature() {
+        let packets = Packet::all_from_bytes(SIGNED_MESSAGE).unwrap();
+        let (one_pass, signature) = match (&packets[0], &packets[2]) {
+            (&Packet::OnePassSignature(ref one_pass), &Packet::Signature(ref signature)) => {
+                (one_pass, signature)
+            }
+            _ => panic!("unexpected packets {:?}", packets),
+        };
+
+        assert_eq!(one_pass.signer, 0xFAB9_C2B7_EE84_CBA8);
+        assert!(!one_pass.nested);
+        assert_eq!(
+            &OnePassSignaturePacket::from_signature(signature, false).unwrap(),
+            one_pass
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        // The body of GnuPG's one-pass signature follows its two octet header.
+        let body = &SIGNED_MESSAGE[2..2 + usize::from(SIGNED_MESSAGE[1])];
+        let one_pass = OnePassSignaturePacket::from_bytes(body).unwrap();
+        assert_eq!(one_pass.to_bytes().unwrap(), body);
+
+        let nested = OnePassSignaturePacket {
+            nested: true,
+            ..one_pass
+        };
+        let bytes = nested.to_bytes().unwrap();
+        assert_eq!(bytes[12], 0);
+        assert_eq!(OnePassSignaturePacket::from_bytes(&bytes).unwrap(), nested);
+    }
+
+    #[test]
+    fn reject_unknown_version() {
+        let mut body = SIGNED_MESSAGE[2..2 + usize::from(SIGNED_MESSAGE[1])].to_vec();
+        body[0] = 4;
+        assert!(OnePassSignaturePacket::from_bytes(&body).is_err());
+    }
+
+    #[test]
+    fn missing_signer() {
+        let signature = SignaturePacket::new(
+            SignatureType::BinaryDocument,
+            PublicKeyAlgorithm::EdDsa,
+            HashAlgorithm::Sha256,
+        ).unwrap();
+
+        let err = OnePassSignaturePacket::from_signature(&signature, false).unwrap_err();
+        assert_eq!(err.to_string(), OnePassSignatureError::MissingSigner.to_string());
+    }
+}
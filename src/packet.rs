@@ -1,4 +1,6 @@
-use byteorder::{BigEndian, WriteBytesExt};
+use std::borrow::Cow;
+use std::io::Write;
+
 use failure::Error;
 use nom::{ErrorKind, be_u16, be_u32, be_u8, rest};
 use nom::Err as NomErr;
@@ -8,11 +10,16 @@ use compression::*;
 use key::*;
 use literal::*;
 use marker;
+use onepass::OnePassSignaturePacket;
+use pkesk::PublicKeySessionKeyPacket;
+use seipd::IntegrityProtectedDataPacket;
+use skesk::SymmetricKeySessionKeyPacket;
 use signature::*;
 use types::NomError;
 use userid;
+use writer::{write_header, HeaderFormat, PartialBodyWriter};
 
-named!(old_tag_format<(&[u8], usize), (u8, &[u8])>,
+named!(old_tag_format<(&[u8], usize), (u8, Cow<'_, [u8]>)>,
     do_parse!(
         tag: take_bits!(u8, 4) >>
         data: switch!(
@@ -25,65 +32,141 @@ named!(old_tag_format<(&[u8], usize), (u8, &[u8])>,
             0 => bytes!(call!(rest)) |
             l => bytes!(take!(l))
         ) >>
-        ((tag, data))
+        ((tag, Cow::Borrowed(data)))
     )
 );
 
-fn new_tag_format(inp: (&[u8], usize)) -> IResult<(&[u8], usize), (u8, &[u8])> {
-    let (remaining, tag) = match take_bits!(inp, u8, 6) {
-        IResult::Done(remaining, tag) => (remaining, tag),
-        IResult::Error(e) => return IResult::Error(e),
-        IResult::Incomplete(i) => return IResult::Incomplete(i),
-    };
+/// The length of a packet body, as given by its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BodyLength {
+    /// The body, or the rest of it, is this many octets long.
+    Fixed(u32),
+    /// The next chunk of the body is this many octets long, and is followed by another length.
+    Partial(u32),
+    /// The body extends to the end of the input; only used by old-format headers.
+    Indeterminate,
+}
+
+impl BodyLength {
+    /// Decode a new-format body length, given its first octet and a way to read the rest.
+    pub(crate) fn decode<E, F: FnMut() -> Result<u8, E>>(
+        first_octet: u8,
+        mut next_octet: F,
+    ) -> Result<BodyLength, E> {
+        let length = if first_octet < 192 {
+            BodyLength::Fixed(u32::from(first_octet))
+        } else if first_octet < 224 {
+            let second_octet = next_octet()?;
+            BodyLength::Fixed(((u32::from(first_octet) - 192) << 8) + u32::from(second_octet) + 192)
+        } else if first_octet < 255 {
+            BodyLength::Partial(1 << (first_octet & 0x1F))
+        } else {
+            let mut length = 0u32;
+            for _ in 0..4 {
+                length = (length << 8) | u32::from(next_octet()?);
+            }
+            BodyLength::Fixed(length)
+        };
+
+        Ok(length)
+    }
+}
 
-    let (remaining, first_octet) = match bytes!(remaining, be_u8) {
+fn new_format_length(inp: &[u8]) -> IResult<&[u8], BodyLength> {
+    let (mut remaining, first_octet) = match be_u8(inp) {
         IResult::Done(remaining, first_octet) => (remaining, first_octet),
         IResult::Error(e) => return IResult::Error(e),
         IResult::Incomplete(i) => return IResult::Incomplete(i),
     };
 
-    if first_octet < 192 {
-        match bytes!(remaining, take!(first_octet)) {
-            IResult::Done(remaining, contents) => return IResult::Done(remaining, (tag, contents)),
-            IResult::Error(e) => return IResult::Error(e),
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
+    let length = BodyLength::decode(first_octet, || match be_u8(remaining) {
+        IResult::Done(rest, octet) => {
+            remaining = rest;
+            Ok(octet)
         }
-    } else if first_octet < 224 {
-        let (remaining, second_octet) = match bytes!(remaining, be_u8) {
-            IResult::Done(remaining, second_octet) => (remaining, second_octet),
-            IResult::Error(e) => return IResult::Error(e),
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
-        };
+        IResult::Error(e) => Err(IResult::Error(e)),
+        IResult::Incomplete(i) => Err(IResult::Incomplete(i)),
+    });
 
-        let length = ((first_octet as u16 - 192) << 8) + second_octet as u16 + 192;
+    match length {
+        Ok(length) => IResult::Done(remaining, length),
+        Err(e) => e,
+    }
+}
 
-        match bytes!(remaining, take!(length)) {
-            IResult::Done(remaining, contents) => return IResult::Done(remaining, (tag, contents)),
-            IResult::Error(e) => return IResult::Error(e),
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
-        }
-    } else if first_octet == 255 {
-        let (remaining, length) = match bytes!(remaining, be_u32) {
-            IResult::Done(remaining, length) => (remaining, length),
+/// Read a body split into partial chunks, joining the chunks together. `first_length` is the
+/// length of the first chunk.
+fn partial_body(inp: &[u8], first_length: u32) -> IResult<&[u8], Vec<u8>> {
+    let mut body = Vec::new();
+    let mut remaining = inp;
+    let mut length = BodyLength::Partial(first_length);
+
+    loop {
+        let chunk_length = match length {
+            BodyLength::Fixed(l) | BodyLength::Partial(l) => l,
+            BodyLength::Indeterminate => unreachable!(),
+        };
+
+        let (rest, chunk) = match take!(remaining, chunk_length) {
+            IResult::Done(rest, chunk) => (rest, chunk),
             IResult::Error(e) => return IResult::Error(e),
             IResult::Incomplete(i) => return IResult::Incomplete(i),
         };
+        body.extend(chunk);
+        remaining = rest;
 
-        match bytes!(remaining, take!(length)) {
-            IResult::Done(remaining, contents) => return IResult::Done(remaining, (tag, contents)),
+        // The last chunk of a body is the one with a fixed length.
+        if let BodyLength::Fixed(_) = length {
+            return IResult::Done(remaining, body);
+        }
+
+        length = match new_format_length(remaining) {
+            IResult::Done(rest, length) => {
+                remaining = rest;
+                length
+            }
             IResult::Error(e) => return IResult::Error(e),
             IResult::Incomplete(i) => return IResult::Incomplete(i),
-        }
+        };
     }
+}
+
+fn new_tag_format(inp: (&[u8], usize)) -> IResult<(&[u8], usize), (u8, Cow<'_, [u8]>)> {
+    let (remaining, tag) = match take_bits!(inp, u8, 6) {
+        IResult::Done(remaining, tag) => (remaining, tag),
+        IResult::Error(e) => return IResult::Error(e),
+        IResult::Incomplete(i) => return IResult::Incomplete(i),
+    };
 
-    // If we've gotten here, it's a partial-length packet, which we don't support (yet?).
-    IResult::Error(NomErr::Code(ErrorKind::Custom(
-        NomError::Unimplemented as u32,
-    )))
+    let (remaining, length) = match bytes!(remaining, new_format_length) {
+        IResult::Done(remaining, length) => (remaining, length),
+        IResult::Error(e) => return IResult::Error(e),
+        IResult::Incomplete(i) => return IResult::Incomplete(i),
+    };
+
+    match length {
+        BodyLength::Fixed(length) => match bytes!(remaining, take!(length)) {
+            IResult::Done(remaining, contents) => {
+                IResult::Done(remaining, (tag, Cow::Borrowed(contents)))
+            }
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(i) => IResult::Incomplete(i),
+        },
+        BodyLength::Partial(length) => match bytes!(remaining, call!(partial_body, length)) {
+            IResult::Done(remaining, contents) => {
+                IResult::Done(remaining, (tag, Cow::Owned(contents)))
+            }
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(i) => IResult::Incomplete(i),
+        },
+        BodyLength::Indeterminate => IResult::Error(NomErr::Code(ErrorKind::Custom(
+            NomError::Unimplemented as u32,
+        ))),
+    }
 }
 
 named!(
-    pgp_packet_header<(u8, &[u8])>,
+    pgp_packet_header<(u8, Cow<'_, [u8]>)>,
     bits!(preceded!(
         tag_bits!(u8, 1, 0b1),
         switch!(take_bits!(u8, 1),
@@ -99,10 +182,10 @@ named!(
 /// that packet type.
 #[derive(Clone, Debug)]
 pub enum Packet {
-    PublicKeySessionKey,
+    PublicKeySessionKey(PublicKeySessionKeyPacket),
     Signature(SignaturePacket),
-    SymmetricKeySessionKey,
-    OnePassSignature,
+    SymmetricKeySessionKey(SymmetricKeySessionKeyPacket),
+    OnePassSignature(OnePassSignaturePacket),
     SecretKey(Key),
     PublicKey(Key),
     SecretSubkey(Key),
@@ -113,18 +196,18 @@ pub enum Packet {
     Trust,
     UserId(String),
     PublicSubkey(Key),
-    UserAttribute,
-    SymmetricEncryptedIntegrityProtectedData,
-    ModificationDetectionCode,
+    UserAttribute(Vec<u8>),
+    SymmetricEncryptedIntegrityProtectedData(IntegrityProtectedDataPacket),
+    ModificationDetectionCode(Vec<u8>),
 }
 
 impl Packet {
-    fn packet_tag(&self) -> u8 {
+    pub(crate) fn packet_tag(&self) -> u8 {
         match *self {
-            Packet::PublicKeySessionKey => 1,
+            Packet::PublicKeySessionKey(_) => 1,
             Packet::Signature(_) => 2,
-            Packet::SymmetricKeySessionKey => 3,
-            Packet::OnePassSignature => 4,
+            Packet::SymmetricKeySessionKey(_) => 3,
+            Packet::OnePassSignature(_) => 4,
             Packet::SecretKey(_) => 5,
             Packet::PublicKey(_) => 6,
             Packet::SecretSubkey(_) => 7,
@@ -135,17 +218,18 @@ impl Packet {
             Packet::Trust => 12,
             Packet::UserId(_) => 13,
             Packet::PublicSubkey(_) => 14,
-            Packet::UserAttribute => 17,
-            Packet::SymmetricEncryptedIntegrityProtectedData => 18,
-            Packet::ModificationDetectionCode => 19,
+            Packet::UserAttribute(_) => 17,
+            Packet::SymmetricEncryptedIntegrityProtectedData(_) => 18,
+            Packet::ModificationDetectionCode(_) => 19,
         }
     }
 
-    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
-        let mut out = Vec::new();
-
+    pub(crate) fn body_to_bytes(&self) -> Result<Vec<u8>, Error> {
         let body = match self {
+            &Packet::PublicKeySessionKey(ref pkesk) => pkesk.to_bytes()?,
             &Packet::Signature(ref signature) => signature.to_bytes()?,
+            &Packet::SymmetricKeySessionKey(ref skesk) => skesk.to_bytes()?,
+            &Packet::OnePassSignature(ref ops) => ops.to_bytes()?,
             &Packet::SecretKey(ref key) => key.to_bytes()?,
             &Packet::PublicKey(ref key) => key.to_bytes()?,
             &Packet::SecretSubkey(ref key) => key.to_bytes()?,
@@ -154,32 +238,44 @@ impl Packet {
             &Packet::LiteralData(ref data) => data.to_bytes()?,
             &Packet::UserId(ref id) => Vec::from(id.as_bytes()),
             &Packet::PublicSubkey(ref key) => key.to_bytes()?,
+            &Packet::UserAttribute(ref attribute) => attribute.clone(),
+            &Packet::SymmetricEncryptedIntegrityProtectedData(ref data) => data.to_bytes()?,
+            &Packet::ModificationDetectionCode(ref hash) => hash.clone(),
             p => bail!(PacketError::UnimplementedType { packet_type: format!("{:?}", p) }),
         };
 
-        let mut packet_tag = 0b1000_0000;
-        let packet_type = self.packet_tag() << 2;
-        packet_tag |= packet_type;
-
-        if body.len() < ::std::u8::MAX as usize {
-            // Header is 2 octets long. packet_tag is unchanged.
-            out.push(packet_tag);
-            out.push(body.len() as u8);
-        } else if body.len() < ::std::u16::MAX as usize {
-            // Header is 3 octets long.
-            out.push(packet_tag | 0x1);
-            out.write_u16::<BigEndian>(body.len() as u16)?;
-        } else {
-            // Header is 5 octets long.
-            out.push(packet_tag | 0x2);
-            out.write_u32::<BigEndian>(body.len() as u32)?;
+        Ok(body)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        let body = self.body_to_bytes()?;
+
+        if body.len() > u32::MAX as usize {
+            bail!(PacketError::BodyTooLong);
         }
 
+        // Old-format headers can't hold tags above 15.
+        let tag = self.packet_tag();
+        let format = if tag > 15 { HeaderFormat::New } else { HeaderFormat::Old };
+        write_header(&mut out, format, tag, Some(body.len() as u32))?;
+
         out.extend(&body);
 
         Ok(out)
     }
 
+    /// Serialize this packet with its body split into partial chunks of `chunk_size` octets, as
+    /// when streaming data of unknown size. See [`PartialBodyWriter`].
+    ///
+    /// [`PartialBodyWriter`]: struct.PartialBodyWriter.html
+    pub fn to_partial_bytes(&self, chunk_size: usize) -> Result<Vec<u8>, Error> {
+        let mut writer = PartialBodyWriter::new(Vec::new(), self.packet_tag(), chunk_size)?;
+        writer.write_all(&self.body_to_bytes()?)?;
+
+        writer.finish()
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<(Packet, &[u8]), Error> {
         let (remaining, packet_tag, packet_data) = match pgp_packet_header(bytes) {
             IResult::Done(remaining, (tag, data)) => (remaining, tag, data),
@@ -198,14 +294,21 @@ impl Packet {
             }),
         };
 
+        Ok((Packet::from_body(packet_tag, &packet_data)?, remaining))
+    }
+
+    /// Parse a packet given its tag and the contents of its body.
+    pub(crate) fn from_body(packet_tag: u8, packet_data: &[u8]) -> Result<Packet, Error> {
         let packet = match packet_tag {
             0 => bail!(PacketError::InvalidHeader {
                 reason: format!("packet has reserved tag"),
             }),
-            1 => Packet::PublicKeySessionKey,
+            1 => Packet::PublicKeySessionKey(PublicKeySessionKeyPacket::from_bytes(packet_data)?),
             2 => Packet::Signature(SignaturePacket::from_bytes(packet_data)?),
-            3 => Packet::SymmetricKeySessionKey,
-            4 => Packet::OnePassSignature,
+            3 => Packet::SymmetricKeySessionKey(SymmetricKeySessionKeyPacket::from_bytes(
+                packet_data,
+            )?),
+            4 => Packet::OnePassSignature(OnePassSignaturePacket::from_bytes(packet_data)?),
             5 => Packet::SecretKey(Key::from_bytes(packet_data)?),
             6 => Packet::PublicKey(Key::from_bytes(packet_data)?),
             7 => Packet::SecretSubkey(Key::from_bytes(packet_data)?),
@@ -219,15 +322,17 @@ impl Packet {
             12 => Packet::Trust,
             13 => Packet::UserId(userid::parse_userid(packet_data)?),
             14 => Packet::PublicSubkey(Key::from_bytes(packet_data)?),
-            17 => Packet::UserAttribute,
-            18 => Packet::SymmetricEncryptedIntegrityProtectedData,
-            19 => Packet::ModificationDetectionCode,
+            17 => Packet::UserAttribute(Vec::from(packet_data)),
+            18 => Packet::SymmetricEncryptedIntegrityProtectedData(
+                IntegrityProtectedDataPacket::from_bytes(packet_data)?,
+            ),
+            19 => Packet::ModificationDetectionCode(Vec::from(packet_data)),
             _ => bail!(PacketError::InvalidHeader {
                 reason: format!("unknown tag"),
             }),
         };
 
-        Ok((packet, remaining))
+        Ok(packet)
     }
 
     pub fn all_from_bytes(mut bytes: &[u8]) -> Result<Vec<Packet>, Error> {
@@ -261,4 +366,16 @@ pub enum PacketError {
     UnsupportedHeader { reason: String },
     #[fail(display = "Unimplemented packet type: {}", packet_type)]
     UnimplementedType { packet_type: String },
+    #[fail(display = "Invalid partial body chunk size: {}", size)]
+    InvalidChunkSize { size: usize },
+    #[fail(display = "Packets with tag {} can't use partial body lengths", tag)]
+    PartialLengthNotAllowed { tag: u8 },
+    #[fail(display = "Packets with tag {} can't use old-format headers", tag)]
+    OldFormatTag { tag: u8 },
+    #[fail(display = "New-format headers can't use an indeterminate length")]
+    IndeterminateLength,
+    #[fail(display = "Packet body is too long")]
+    BodyTooLong,
+    #[fail(display = "Packet body ended {} octets short of its length", remaining)]
+    BodyLengthMismatch { remaining: u32 },
 }
@@ -0,0 +1,426 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use failure::Error;
+use nom::{be_u64, be_u8, rest};
+use nom::{ErrorKind, IResult};
+use nom::Err as NomErr;
+use num::BigUint;
+use rand;
+use rand::Rng;
+use x25519_dalek;
+
+use key::{EcdhPublicKey, Key, KeyError, KeyMaterial};
+use skesk::SessionKey;
+use symmetric::key_wrap;
+use types::*;
+use util::{pgp_mpi, random_below};
+
+named!(rsa_session_key<EncryptedSessionKey>, map!(pgp_mpi, EncryptedSessionKey::Rsa));
+
+named!(
+    elgamal_session_key<EncryptedSessionKey>,
+    do_parse!(
+        g_k: pgp_mpi >>
+        m_y_k: pgp_mpi >>
+        (EncryptedSessionKey::Elgamal(g_k, m_y_k))
+    )
+);
+
+named!(
+    ecdh_session_key<EncryptedSessionKey>,
+    do_parse!(
+        point: map!(pgp_mpi, |point| point.to_bytes_be()) >>
+        wrapped_key: length_bytes!(be_u8) >>
+        (EncryptedSessionKey::Ecdh {
+            point,
+            wrapped_key: Vec::from(wrapped_key),
+        })
+    )
+);
+
+named!(
+    pkesk<PublicKeySessionKeyPacket>,
+    do_parse!(
+        version: verify!(be_u8, |version| version == 3) >>
+        recipient: be_u64 >>
+        pubkey_algo: map!(be_u8, PublicKeyAlgorithm::from) >>
+        session_key: switch!(value!(pubkey_algo),
+            PublicKeyAlgorithm::Rsa => call!(rsa_session_key) |
+            PublicKeyAlgorithm::RsaEncryptOnly => call!(rsa_session_key) |
+            PublicKeyAlgorithm::Elgamal => call!(elgamal_session_key) |
+            PublicKeyAlgorithm::ElgamalEncryptOnly => call!(elgamal_session_key) |
+            PublicKeyAlgorithm::EllipticCurve => call!(ecdh_session_key) |
+            _ => map!(rest, |data| EncryptedSessionKey::Unknown(Vec::from(data)))
+        ) >>
+        (PublicKeySessionKeyPacket {
+            version,
+            recipient,
+            pubkey_algo,
+            session_key,
+        })
+    )
+);
+
+/// A Public-Key Encrypted Session Key packet as specified in [RFC4880 &sect;5.1].
+///
+/// Each of these packets holds the session key of an encrypted message, encrypted to one
+/// recipient's key. The recipient is identified by key ID; a key ID of 0 is a wildcard, hiding
+/// the recipient, and any key may be tried against it.
+///
+/// [RFC4880 &sect;5.1]: https://tools.ietf.org/html/rfc4880#section-5.1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicKeySessionKeyPacket {
+    pub version: u8,
+    pub recipient: u64,
+    pub pubkey_algo: PublicKeyAlgorithm,
+    pub session_key: EncryptedSessionKey,
+}
+
+impl PublicKeySessionKeyPacket {
+    /// Read in a public-key encrypted session key from some bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<PublicKeySessionKeyPacket, Error> {
+        match pkesk(bytes) {
+            IResult::Done(_, pkesk) => Ok(pkesk),
+            IResult::Error(NomErr::Code(ErrorKind::Custom(e))) => {
+                let e = NomError::from(e);
+
+                bail!(PublicKeySessionKeyError::InvalidFormat {
+                    reason: format!("{:?}", e),
+                })
+            }
+            IResult::Error(e) => bail!(PublicKeySessionKeyError::InvalidFormat {
+                reason: format!("{}", e),
+            }),
+            IResult::Incomplete(i) => bail!(PublicKeySessionKeyError::InvalidFormat {
+                reason: format!("{:?}", i),
+            }),
+        }
+    }
+
+    /// Encrypt a session key to a recipient's public key. RSA, Elgamal and ECDH keys over
+    /// Curve25519 are supported.
+    pub fn new(
+        session_key: &SessionKey,
+        recipient: &Key,
+    ) -> Result<PublicKeySessionKeyPacket, Error> {
+        let message = session_key.to_checksummed_bytes();
+        let encrypted_key = match recipient.key_material {
+            KeyMaterial::Rsa(ref public, _) => {
+                let m = eme_pkcs1_v1_5(&message, public.n.bits().div_ceil(8))?;
+                EncryptedSessionKey::Rsa(m.modpow(&public.e, &public.n))
+            }
+            KeyMaterial::Elgamal(ref public, _) => {
+                let m = eme_pkcs1_v1_5(&message, public.p.bits().div_ceil(8))?;
+                let k = random_below(&public.p);
+                EncryptedSessionKey::Elgamal(
+                    public.g.modpow(&k, &public.p),
+                    m * public.y.modpow(&k, &public.p) % &public.p,
+                )
+            }
+            KeyMaterial::Ecdh(ref public, _) => {
+                ecdh_encrypt(&message, public, &recipient.fingerprint()?)?
+            }
+            _ => bail!(PublicKeySessionKeyError::UnsupportedAlgorithm {
+                algorithm: recipient.pubkey_algorithm,
+            }),
+        };
+
+        Ok(PublicKeySessionKeyPacket {
+            version: 3,
+            recipient: recipient.id()?,
+            pubkey_algo: recipient.pubkey_algorithm,
+            session_key: encrypted_key,
+        })
+    }
+
+    /// Whether the recipient's key ID is hidden, as with `gpg --throw-keyids`.
+    pub fn is_wildcard(&self) -> bool {
+        self.recipient == 0
+    }
+
+    /// Serialize this public-key encrypted session key to bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+
+        out.push(self.version);
+        out.write_u64::<BigEndian>(self.recipient)?;
+        out.push(self.pubkey_algo.into());
+
+        match self.session_key {
+            EncryptedSessionKey::Rsa(ref m_e) => {
+                out.write_u16::<BigEndian>(m_e.bits() as u16)?;
+                out.extend(&m_e.to_bytes_be());
+            }
+            EncryptedSessionKey::Elgamal(ref g_k, ref m_y_k) => {
+                out.write_u16::<BigEndian>(g_k.bits() as u16)?;
+                out.extend(&g_k.to_bytes_be());
+                out.write_u16::<BigEndian>(m_y_k.bits() as u16)?;
+                out.extend(&m_y_k.to_bytes_be());
+            }
+            EncryptedSessionKey::Ecdh {
+                ref point,
+                ref wrapped_key,
+            } => {
+                let point = BigUint::from_bytes_be(point);
+                out.write_u16::<BigEndian>(point.bits() as u16)?;
+                out.extend(&point.to_bytes_be());
+
+                if wrapped_key.len() > 0xFF {
+                    bail!(PublicKeySessionKeyError::InvalidFormat {
+                        reason: "wrapped session key is too long".to_string(),
+                    });
+                }
+                out.push(wrapped_key.len() as u8);
+                out.extend(wrapped_key);
+            }
+            EncryptedSessionKey::Unknown(ref data) => out.extend(data),
+        }
+
+        Ok(out)
+    }
+}
+
+/// Encode a message to be encrypted with RSA or Elgamal using EME-PKCS1-v1_5, as specified in
+/// [RFC4880 &sect;13.1.1], for a modulus of `len` octets.
+///
+/// [RFC4880 &sect;13.1.1]: https://tools.ietf.org/html/rfc4880#section-13.1.1
+fn eme_pkcs1_v1_5(message: &[u8], len: usize) -> Result<BigUint, Error> {
+    if len < message.len() + 11 {
+        bail!(PublicKeySessionKeyError::KeyTooSmall);
+    }
+
+    let mut padding = vec![0u8; len - message.len() - 3];
+    for octet in &mut padding {
+        // The padding octets must all be nonzero.
+        *octet = rand::thread_rng().gen_range(1..=255);
+    }
+
+    let mut encoded = vec![0x00u8, 0x02];
+    encoded.extend(&padding);
+    encoded.push(0x00);
+    encoded.extend(message);
+
+    Ok(BigUint::from_bytes_be(&encoded))
+}
+
+/// The parameters of the key derivation function for ECDH, as specified in [RFC6637 &sect;8].
+///
+/// [RFC6637 &sect;8]: https://tools.ietf.org/html/rfc6637#section-8
+fn ecdh_kdf_param(public: &EcdhPublicKey, fingerprint: &[u8]) -> Vec<u8> {
+    let oid = public.curve.oid();
+    let mut param = vec![oid.len() as u8];
+    param.extend(oid);
+    param.push(PublicKeyAlgorithm::EllipticCurve.into());
+    param.extend(&[3, 1, public.kdf_hash.into(), public.kdf_cipher.into()]);
+    param.extend(b"Anonymous Sender    ");
+    param.extend(fingerprint);
+
+    param
+}
+
+/// Derive the key-encryption key for an ECDH session key from the shared secret.
+pub(crate) fn ecdh_kdf(
+    public: &EcdhPublicKey,
+    fingerprint: &[u8],
+    shared_secret: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let mut input = vec![0u8, 0, 0, 1];
+    input.extend(shared_secret);
+    input.extend(&ecdh_kdf_param(public, fingerprint));
+
+    let mut kek = public.kdf_hash.hash(&input)?;
+    let key_bytes = public.kdf_cipher.key_bytes();
+    if kek.len() < key_bytes {
+        bail!(PublicKeySessionKeyError::InvalidKdfParameters);
+    }
+    kek.truncate(key_bytes);
+
+    Ok(kek)
+}
+
+fn ecdh_encrypt(
+    message: &[u8],
+    public: &EcdhPublicKey,
+    fingerprint: &[u8],
+) -> Result<EncryptedSessionKey, Error> {
+    if public.curve != Curve::Curve25519 {
+        bail!(KeyError::UnsupportedCurve {
+            curve: format!("{:?}", public.curve),
+        });
+    }
+
+    let mut recipient = [0u8; 32];
+    recipient.copy_from_slice(public.native_point()?);
+    let recipient = x25519_dalek::PublicKey::from(recipient);
+
+    let ephemeral = x25519_dalek::StaticSecret::from(rand::random::<[u8; 32]>());
+    let shared_secret = ephemeral.diffie_hellman(&recipient);
+    let kek = ecdh_kdf(public, fingerprint, shared_secret.as_bytes())?;
+
+    // The message is padded to a multiple of 8 octets as in PKCS#5 before being wrapped.
+    let mut padded = Vec::from(message);
+    let padding = 8 - message.len() % 8;
+    padded.resize(message.len() + padding, padding as u8);
+
+    let mut point = vec![0x40];
+    point.extend(x25519_dalek::PublicKey::from(&ephemeral).as_bytes());
+
+    Ok(EncryptedSessionKey::Ecdh {
+        point,
+        wrapped_key: key_wrap(public.kdf_cipher, &kek, &padded)?,
+    })
+}
+
+/// The algorithm-specific contents of a [`PublicKeySessionKeyPacket`]: the session key,
+/// encrypted to the recipient's public key.
+///
+/// [`PublicKeySessionKeyPacket`]: struct.PublicKeySessionKeyPacket.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncryptedSessionKey {
+    /// The RSA-encrypted value `m^e mod n`.
+    Rsa(BigUint),
+    /// The Elgamal-encrypted values `g^k mod p` and `m * y^k mod p`.
+    Elgamal(BigUint, BigUint),
+    /// The ephemeral ECDH public point, in the same representation as the point of an
+    /// [`EcdhPublicKey`], and the session key wrapped with the shared secret.
+    ///
+    /// [`EcdhPublicKey`]: struct.EcdhPublicKey.html
+    Ecdh { point: Vec<u8>, wrapped_key: Vec<u8> },
+    /// The raw contents for any other public key algorithm.
+    Unknown(Vec<u8>),
+}
+
+/// Error type for [`PublicKeySessionKeyPacket`]-level errors.
+///
+/// [`PublicKeySessionKeyPacket`]: struct.PublicKeySessionKeyPacket.html
+#[derive(Debug, Fail)]
+pub enum PublicKeySessionKeyError {
+    #[fail(display = "Invalid public-key encrypted session key format: {}", reason)]
+    InvalidFormat { reason: String },
+    #[fail(display = "Can't encrypt session keys with {:?} keys", algorithm)]
+    UnsupportedAlgorithm { algorithm: PublicKeyAlgorithm },
+    #[fail(display = "Key is too small to encrypt a session key with")]
+    KeyTooSmall,
+    #[fail(display = "Invalid ECDH key derivation parameters")]
+    InvalidKdfParameters,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet::Packet;
+
+    const RSA_MESSAGE: &[u8] = include_bytes!("../tests/data/rsa.enc.gpg");
+    const ELGAMAL_MESSAGE: &[u8] = include_bytes!("../tests/data/elg.enc.gpg");
+    const P256_MESSAGE: &[u8] = include_bytes!("../tests/data/enistp256.enc.gpg");
+    const P384_MESSAGE: &[u8] = include_bytes!("../tests/data/nistp384.enc.gpg");
+    const P521_MESSAGE: &[u8] = include_bytes!("../tests/data/nistp521.enc.gpg");
+    const CURVE25519_MESSAGE: &[u8] = include_bytes!("../tests/data/ed.signed.enc.gpg");
+    const HIDDEN_MESSAGE: &[u8] = include_bytes!("../tests/data/hidden.enc.gpg");
+
+    // The body of the session key packet at the start of a GnuPG message, which has an
+    // old-format header with a one or two octet length.
+    fn first_body(message: &[u8]) -> &[u8] {
+        match message[0] {
+            0x84 => &message[2..2 + usize::from(message[1])],
+            0x85 => {
+                let len = usize::from(message[1]) << 8 | usize::from(message[2]);
+                &message[3..3 + len]
+            }
+            tag => panic!("unexpected packet header {:#x}", tag),
+        }
+    }
+
+    #[test]
+    fn read_gnupg_session_keys() {
+        for &(message, recipient, pubkey_algo) in &[
+            (RSA_MESSAGE, 0xA9EA_41C4_AD44_2519, PublicKeyAlgorithm::Rsa),
+            (ELGAMAL_MESSAGE, 0xB3BE_A687_BB87_4E3F, PublicKeyAlgorithm::ElgamalEncryptOnly),
+            (P256_MESSAGE, 0xB5B0_DF7B_B4E4_FCD3, PublicKeyAlgorithm::EllipticCurve),
+            (P384_MESSAGE, 0x1966_0526_930F_D575, PublicKeyAlgorithm::EllipticCurve),
+            (P521_MESSAGE, 0x8A5F_816E_CD8A_D6C4, PublicKeyAlgorithm::EllipticCurve),
+            (CURVE25519_MESSAGE, 0xD88B_EE62_C596_6048, PublicKeyAlgorithm::EllipticCurve),
+        ] {
+            let body = first_body(message);
+            let pkesk = PublicKeySessionKeyPacket::from_bytes(body).unwrap();
+
+            assert_eq!(pkesk.version, 3);
+            assert_eq!(pkesk.recipient, recipient);
+            assert_eq!(pkesk.pubkey_algo, pubkey_algo);
+            assert!(!pkesk.is_wildcard());
+            assert_eq!(pkesk.to_bytes().unwrap(), body);
+
+            match Packet::from_bytes(message).unwrap().0 {
+                Packet::PublicKeySessionKey(ref packet) => assert_eq!(*packet, pkesk),
+                ref p => panic!("expected a session key, found {:?}", p),
+            }
+        }
+    }
+
+    #[test]
+    fn session_key_contents() {
+        let pkesk = PublicKeySessionKeyPacket::from_bytes(first_body(RSA_MESSAGE)).unwrap();
+        match pkesk.session_key {
+            EncryptedSessionKey::Rsa(_) => {}
+            ref k => panic!("expected an RSA session key, found {:?}", k),
+        }
+
+        let pkesk = PublicKeySessionKeyPacket::from_bytes(first_body(ELGAMAL_MESSAGE)).unwrap();
+        match pkesk.session_key {
+            EncryptedSessionKey::Elgamal(..) => {}
+            ref k => panic!("expected an Elgamal session key, found {:?}", k),
+        }
+
+        // Curve25519 points are prefixed with 0x40, and NIST points are uncompressed. Wrapped
+        // keys are a whole number of 64-bit blocks.
+        for &(message, point_len, prefix) in &[
+            (CURVE25519_MESSAGE, 33, 0x40),
+            (P256_MESSAGE, 65, 0x04),
+            (P384_MESSAGE, 97, 0x04),
+            (P521_MESSAGE, 133, 0x04),
+        ] {
+            let pkesk = PublicKeySessionKeyPacket::from_bytes(first_body(message)).unwrap();
+            match pkesk.session_key {
+                EncryptedSessionKey::Ecdh {
+                    ref point,
+                    ref wrapped_key,
+                } => {
+                    assert_eq!(point.len(), point_len);
+                    assert_eq!(point[0], prefix);
+                    assert_eq!(wrapped_key.len() % 8, 0);
+                }
+                ref k => panic!("expected an ECDH session key, found {:?}", k),
+            }
+        }
+    }
+
+    #[test]
+    fn wildcard_recipient() {
+        let body = first_body(HIDDEN_MESSAGE);
+        let pkesk = PublicKeySessionKeyPacket::from_bytes(body).unwrap();
+
+        assert!(pkesk.is_wildcard());
+        assert_eq!(pkesk.recipient, 0);
+        assert_eq!(pkesk.to_bytes().unwrap(), body);
+    }
+
+    #[test]
+    fn unknown_algorithm() {
+        let mut body = first_body(RSA_MESSAGE).to_vec();
+        body[9] = PublicKeyAlgorithm::EdDsa.into();
+
+        let pkesk = PublicKeySessionKeyPacket::from_bytes(&body).unwrap();
+        assert_eq!(pkesk.session_key, EncryptedSessionKey::Unknown(body[10..].to_vec()));
+        assert_eq!(pkesk.to_bytes().unwrap(), body);
+    }
+
+    #[test]
+    fn reject_invalid_packets() {
+        let body = first_body(RSA_MESSAGE);
+
+        let mut version = body.to_vec();
+        version[0] = 2;
+        assert!(PublicKeySessionKeyPacket::from_bytes(&version).is_err());
+
+        assert!(PublicKeySessionKeyPacket::from_bytes(&body[..body.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::io;
+use std::io::Read;
+use std::rc::Rc;
+
+use byteorder::{BigEndian, ReadBytesExt};
+use failure::Error;
+
+use compression::Decompressor;
+use literal::LiteralHeader;
+use packet::{BodyLength, Packet, PacketError};
+use types::CompressionAlgorithm;
+
+// Size of the scratch buffer used to skip over unread packet bodies.
+const SKIP_BUFFER_SIZE: usize = 8192;
+
+// The largest body read into memory for packets that aren't streamed, such as keys and
+// signatures. Even large photo IDs are far below this.
+const MAX_PACKET_SIZE: u64 = 1 << 24;
+
+/// Read a packet header, returning `None` if the input ends cleanly before the header starts.
+fn read_header<R: Read>(reader: &mut R) -> Result<Option<(u8, BodyLength)>, Error> {
+    let mut first = [0u8; 1];
+    loop {
+        match reader.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let first = first[0];
+    if first & 0x80 == 0 {
+        bail!(PacketError::InvalidHeader {
+            reason: "packet tag does not have its high bit set".to_string(),
+        });
+    }
+
+    let header = if first & 0x40 == 0 {
+        // Old format: the tag is in bits 5-2, and the length type in bits 1-0.
+        let tag = (first >> 2) & 0x0F;
+        let length = match first & 0x03 {
+            0 => BodyLength::Fixed(u32::from(reader.read_u8()?)),
+            1 => BodyLength::Fixed(u32::from(reader.read_u16::<BigEndian>()?)),
+            2 => BodyLength::Fixed(reader.read_u32::<BigEndian>()?),
+            _ => BodyLength::Indeterminate,
+        };
+
+        (tag, length)
+    } else {
+        // New format: the tag is in bits 5-0, followed by a variable-length length.
+        let tag = first & 0x3F;
+        let first_octet = reader.read_u8()?;
+
+        (tag, BodyLength::decode(first_octet, || reader.read_u8())?)
+    };
+
+    Ok(Some(header))
+}
+
+/// State shared between a [`PacketReader`] and the bodies it hands out.
+///
+/// [`PacketReader`]: struct.PacketReader.html
+struct ReaderState<R: Read> {
+    inner: R,
+    remaining: BodyLength,
+    // Incremented each time the reader moves on to a new packet, invalidating older bodies.
+    generation: u64,
+}
+
+impl<R: Read> ReaderState<R> {
+    fn read_body(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Move on to the next chunk of a partial body once the current one is used up.
+        while self.remaining == BodyLength::Partial(0) {
+            let first_octet = self.inner.read_u8()?;
+            let inner = &mut self.inner;
+            self.remaining = BodyLength::decode(first_octet, || inner.read_u8())?;
+        }
+
+        match self.remaining {
+            BodyLength::Fixed(0) => Ok(0),
+            BodyLength::Fixed(remaining) | BodyLength::Partial(remaining) => {
+                let len = cmp::min(buf.len(), remaining as usize);
+                let read = self.inner.read(&mut buf[..len])?;
+                if read == 0 && len > 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "packet body is truncated",
+                    ));
+                }
+
+                let remaining = remaining - read as u32;
+                self.remaining = match self.remaining {
+                    BodyLength::Partial(_) => BodyLength::Partial(remaining),
+                    _ => BodyLength::Fixed(remaining),
+                };
+                Ok(read)
+            }
+            BodyLength::Indeterminate => self.inner.read(buf),
+        }
+    }
+
+    fn skip_body(&mut self) -> io::Result<()> {
+        let mut scratch = [0u8; SKIP_BUFFER_SIZE];
+        while self.read_body(&mut scratch)? > 0 {}
+
+        Ok(())
+    }
+}
+
+/// The body of a packet being read by a [`PacketReader`], which can be read incrementally.
+///
+/// A body is only readable until the reader moves on to the next packet; after that, reads will
+/// fail.
+///
+/// [`PacketReader`]: struct.PacketReader.html
+pub struct PacketBody<R: Read> {
+    state: Rc<RefCell<ReaderState<R>>>,
+    generation: u64,
+}
+
+impl<R: Read> Read for PacketBody<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        if state.generation != self.generation {
+            return Err(io::Error::other("packet reader has moved past this packet"));
+        }
+
+        state.read_body(buf)
+    }
+}
+
+/// A packet read by a [`PacketReader`].
+///
+/// Packets that can hold arbitrarily large amounts of data expose their bodies as streams;
+/// everything else is parsed into a [`Packet`].
+///
+/// [`PacketReader`]: struct.PacketReader.html
+/// [`Packet`]: enum.Packet.html
+// Only one packet is held at a time while reading, so boxing the common `Packet` variant would
+// cost an allocation per packet to save space that is never multiplied.
+#[allow(clippy::large_enum_variant)]
+pub enum StreamedPacket<R: Read> {
+    Packet(Packet),
+    LiteralData(LiteralHeader, PacketBody<R>),
+    /// A Compressed Data packet. The packets it contains can be read by passing the body through
+    /// a [`Decompressor`] and into another [`PacketReader`]; see [`PacketReader::decompress`].
+    ///
+    /// [`Decompressor`]: enum.Decompressor.html
+    /// [`PacketReader`]: struct.PacketReader.html
+    /// [`PacketReader::decompress`]: struct.PacketReader.html#method.decompress
+    CompressedData(CompressionAlgorithm, PacketBody<R>),
+    SymmetricEncryptedData(PacketBody<R>),
+    SymmetricEncryptedIntegrityProtectedData(PacketBody<R>),
+}
+
+/// A reader yielding packets one at a time from any [`Read`] source.
+///
+/// Unlike [`Packet::all_from_bytes`], the input doesn't need to fit in memory: bodies of literal,
+/// compressed and encrypted data packets are handed out as [`PacketBody`] streams, and any part
+/// of a body left unread is skipped when the next packet is requested. Compressed data is not
+/// descended into automatically.
+///
+/// Other packets are read into memory whole, and are rejected if they are longer than 16 MiB or
+/// use partial body lengths, so memory use stays bounded for any input.
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [`Packet::all_from_bytes`]: enum.Packet.html#method.all_from_bytes
+/// [`PacketBody`]: struct.PacketBody.html
+pub struct PacketReader<R: Read> {
+    state: Rc<RefCell<ReaderState<R>>>,
+    done: bool,
+}
+
+impl<R: Read> PacketReader<R> {
+    /// Create a packet reader over some input.
+    pub fn new(inner: R) -> PacketReader<R> {
+        PacketReader {
+            state: Rc::new(RefCell::new(ReaderState {
+                inner,
+                remaining: BodyLength::Fixed(0),
+                generation: 0,
+            })),
+            done: false,
+        }
+    }
+
+    fn next_packet(&mut self) -> Result<Option<StreamedPacket<R>>, Error> {
+        let (tag, length, generation) = {
+            let mut state = self.state.borrow_mut();
+            state.skip_body()?;
+            state.generation += 1;
+
+            let (tag, length) = match read_header(&mut state.inner)? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+            state.remaining = length;
+
+            (tag, length, state.generation)
+        };
+
+        let mut body = PacketBody {
+            state: self.state.clone(),
+            generation,
+        };
+
+        let packet = match tag {
+            8 => StreamedPacket::CompressedData(CompressionAlgorithm::from(body.read_u8()?), body),
+            9 => StreamedPacket::SymmetricEncryptedData(body),
+            11 => StreamedPacket::LiteralData(LiteralHeader::from_reader(&mut body)?, body),
+            18 => StreamedPacket::SymmetricEncryptedIntegrityProtectedData(body),
+            tag => {
+                // Only the packets above can be arbitrarily long; the rest are read into memory,
+                // so their size is capped.
+                match length {
+                    BodyLength::Partial(_) => bail!(PacketError::PartialLengthNotAllowed { tag }),
+                    BodyLength::Fixed(len) if u64::from(len) > MAX_PACKET_SIZE => {
+                        bail!(PacketError::BodyTooLong)
+                    }
+                    _ => {}
+                }
+
+                let mut data = Vec::new();
+                (&mut body).take(MAX_PACKET_SIZE + 1).read_to_end(&mut data)?;
+                if data.len() as u64 > MAX_PACKET_SIZE {
+                    bail!(PacketError::BodyTooLong);
+                }
+
+                StreamedPacket::Packet(Packet::from_body(tag, &data)?)
+            }
+        };
+
+        Ok(Some(packet))
+    }
+}
+
+impl<R: Read> PacketReader<Decompressor<PacketBody<R>>> {
+    /// Create a packet reader over the decompressed contents of a Compressed Data packet.
+    pub fn decompress(
+        algo: CompressionAlgorithm,
+        body: PacketBody<R>,
+    ) -> Result<PacketReader<Decompressor<PacketBody<R>>>, Error> {
+        Ok(PacketReader::new(Decompressor::new(algo, body)?))
+    }
+}
+
+impl<R: Read> Iterator for PacketReader<R> {
+    type Item = Result<StreamedPacket<R>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_packet() {
+            Ok(Some(packet)) => Some(Ok(packet)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // The input can't be resynchronized after an error, so stop here.
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use literal::LiteralFormat;
+
+    const CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const SIGNED_MESSAGE: &[u8] = include_bytes!("../tests/data/ed.signed.gpg");
+    const COMPRESSED_MESSAGE: &[u8] = include_bytes!("../tests/data/rsa.signed.gpg");
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/message.txt");
+
+    fn next<R: Read>(reader: &mut PacketReader<R>) -> StreamedPacket<R> {
+        reader.next().unwrap().unwrap()
+    }
+
+    fn read_error(input: &[u8]) -> Error {
+        match PacketReader::new(input).next() {
+            Some(Err(e)) => e,
+            Some(Ok(_)) => panic!("expected an error, read a packet"),
+            None => panic!("expected an error, found no packets"),
+        }
+    }
+
+    #[test]
+    fn read_certificate() {
+        let packets = PacketReader::new(CERTIFICATE)
+            .map(|packet| match packet.unwrap() {
+                StreamedPacket::Packet(packet) => packet,
+                _ => panic!("expected only keys, user IDs and signatures"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(packets.len(), 5);
+        match (&packets[0], &packets[1], &packets[3]) {
+            (&Packet::PublicKey(_), &Packet::UserId(ref user_id), &Packet::PublicSubkey(_)) => {
+                assert_eq!(user_id, "Ed Test <ed@example.com>");
+            }
+            _ => panic!("unexpected packets {:?}", packets),
+        }
+    }
+
+    #[test]
+    fn read_signed_message() {
+        let mut reader = PacketReader::new(SIGNED_MESSAGE);
+
+        match next(&mut reader) {
+            StreamedPacket::Packet(Packet::OnePassSignature(_)) => {}
+            _ => panic!("expected a one-pass signature"),
+        }
+        match next(&mut reader) {
+            StreamedPacket::LiteralData(header, mut body) => {
+                assert_eq!(header.format, LiteralFormat::Binary);
+                assert_eq!(header.filename, "message.txt");
+
+                let mut contents = Vec::new();
+                body.read_to_end(&mut contents).unwrap();
+                assert_eq!(contents, MESSAGE);
+            }
+            _ => panic!("expected literal data"),
+        }
+        match next(&mut reader) {
+            StreamedPacket::Packet(Packet::Signature(_)) => {}
+            _ => panic!("expected a signature"),
+        }
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn skip_unread_body() {
+        let mut reader = PacketReader::new(SIGNED_MESSAGE);
+        next(&mut reader);
+
+        let mut body = match next(&mut reader) {
+            StreamedPacket::LiteralData(_, body) => body,
+            _ => panic!("expected literal data"),
+        };
+        let mut start = [0u8; 5];
+        body.read_exact(&mut start).unwrap();
+        assert_eq!(&start, b"Hello");
+
+        match next(&mut reader) {
+            StreamedPacket::Packet(Packet::Signature(_)) => {}
+            _ => panic!("expected a signature"),
+        }
+
+        // The body can't be read once the reader has moved past it.
+        assert!(body.read(&mut start).is_err());
+    }
+
+    #[test]
+    fn read_compressed_message() {
+        let mut reader = PacketReader::new(COMPRESSED_MESSAGE);
+
+        let body = match next(&mut reader) {
+            StreamedPacket::CompressedData(CompressionAlgorithm::Zlib, body) => body,
+            _ => panic!("expected zlib compressed data"),
+        };
+        let mut inner = PacketReader::decompress(CompressionAlgorithm::Zlib, body).unwrap();
+        next(&mut inner);
+        match next(&mut inner) {
+            StreamedPacket::LiteralData(_, mut body) => {
+                let mut contents = Vec::new();
+                body.read_to_end(&mut contents).unwrap();
+                assert_eq!(contents, MESSAGE);
+            }
+            _ => panic!("expected literal data"),
+        }
+        assert_eq!(inner.count(), 1);
+
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn read_indeterminate_length() {
+        // An old-format literal data packet running to the end of the input.
+        let mut input = vec![0xAF, b'b', 0, 0, 0, 0, 0];
+        input.extend(MESSAGE);
+
+        let mut reader = PacketReader::new(&input[..]);
+        match next(&mut reader) {
+            StreamedPacket::LiteralData(_, mut body) => {
+                let mut contents = Vec::new();
+                body.read_to_end(&mut contents).unwrap();
+                assert_eq!(contents, MESSAGE);
+            }
+            _ => panic!("expected literal data"),
+        }
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn reject_truncated_body() {
+        let err = read_error(&CERTIFICATE[..40]);
+        let err = err.downcast::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reject_partial_length_on_buffered_packet() {
+        // A public key with a 512 octet partial body chunk.
+        let err = read_error(&[0xC6, 0xE9]);
+        let expected = PacketError::PartialLengthNotAllowed { tag: 6 };
+        assert_eq!(err.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn reject_oversized_packet() {
+        // A signature claiming to be 16 MiB and one octet long.
+        let err = read_error(&[0xC2, 0xFF, 0x01, 0x00, 0x00, 0x01]);
+        assert_eq!(err.to_string(), PacketError::BodyTooLong.to_string());
+
+        // An old-format signature running to the end of a larger input.
+        let mut input = vec![0x8B];
+        input.resize(MAX_PACKET_SIZE as usize + 2, 0);
+        let err = read_error(&input);
+        assert_eq!(err.to_string(), PacketError::BodyTooLong.to_string());
+    }
+}
@@ -2,6 +2,7 @@ use failure::Error;
 use nom::be_u8;
 use nom::{ErrorKind, IResult};
 use nom::Err as NomErr;
+use rand;
 
 use types::*;
 
@@ -24,6 +25,13 @@ fn s2k_decode_count(c: u8) -> u32 {
     (16 + (c & 15)) << ((c >> 4) + 6)
 }
 
+/// Encode an iteration count, rounding it up to the nearest representable value.
+fn s2k_encode_count(count: u32) -> u8 {
+    (0..=255u8)
+        .find(|c| s2k_decode_count(*c) >= count)
+        .unwrap_or(255)
+}
+
 named!(simple_s2k<StringToKey>,
     do_parse!(
         tag!(&[0u8]) >>
@@ -51,16 +59,155 @@ named!(iterated_salted_s2k<StringToKey>,
     )
 );
 
-named!(pub s2k<StringToKey>, alt!(simple_s2k | salted_s2k | iterated_salted_s2k));
+named!(gnu_dummy_s2k<StringToKey>,
+    do_parse!(
+        tag!(&[101u8]) >>
+        hash_algo: be_u8 >>
+        tag!(b"GNU\x01") >>
+        (StringToKey::GnuDummy(hash_algo))
+    )
+);
+
+named!(gnu_divert_to_card_s2k<StringToKey>,
+    do_parse!(
+        tag!(&[101u8]) >>
+        hash_algo: be_u8 >>
+        tag!(b"GNU\x02") >>
+        serial: length_bytes!(be_u8) >>
+        (StringToKey::GnuDivertToCard(hash_algo, Vec::from(serial)))
+    )
+);
+
+named!(pub s2k<StringToKey>,
+    alt!(simple_s2k | salted_s2k | iterated_salted_s2k | gnu_dummy_s2k | gnu_divert_to_card_s2k)
+);
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum StringToKey {
     Simple(HashAlgorithm),
     Salted(HashAlgorithm, [u8; 8]),
     IteratedSalted(HashAlgorithm, [u8; 8], u32),
+    /// GnuPG's extension for secret keys whose secret key material has been removed, such as
+    /// primary keys exported with `gpg --export-secret-subkeys`. The hash algorithm is unused, and
+    /// kept as the raw octet since GnuPG writes 0 there.
+    GnuDummy(u8),
+    /// GnuPG's extension for secret keys whose secret key material is held on a smartcard, with
+    /// the card's serial number.
+    GnuDivertToCard(u8, Vec<u8>),
 }
 
 impl StringToKey {
+    /// Create an iterated and salted string-to-key specifier with a random salt.
+    ///
+    /// `count` is the number of octets to hash; it is rounded up to the nearest count that can be
+    /// encoded.
+    pub fn iterated(hash_algo: HashAlgorithm, count: u32) -> StringToKey {
+        let salt = rand::random::<[u8; 8]>();
+
+        StringToKey::IteratedSalted(hash_algo, salt, s2k_decode_count(s2k_encode_count(count)))
+    }
+
+    /// Whether this is one of GnuPG's extensions for secret keys that hold no secret key
+    /// material.
+    pub fn is_gnu_stub(&self) -> bool {
+        matches!(
+            *self,
+            StringToKey::GnuDummy(_) | StringToKey::GnuDivertToCard(_, _)
+        )
+    }
+
+    /// Derive a key of `key_bytes` octets from a passphrase, as specified in
+    /// [RFC4880 &sect;3.7.1].
+    ///
+    /// [RFC4880 &sect;3.7.1]: https://tools.ietf.org/html/rfc4880#section-3.7.1
+    pub fn derive_key<T: AsRef<[u8]>>(
+        &self,
+        passphrase: T,
+        key_bytes: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let (hash_algo, salt, count) = match *self {
+            StringToKey::Simple(hash_algo) => (hash_algo, None, 0),
+            StringToKey::Salted(hash_algo, salt) => (hash_algo, Some(salt), 0),
+            StringToKey::IteratedSalted(hash_algo, salt, count) => {
+                (hash_algo, Some(salt), count as usize)
+            }
+            StringToKey::GnuDummy(_) | StringToKey::GnuDivertToCard(_, _) => {
+                bail!(S2kError::NoSecretKey)
+            }
+        };
+
+        let mut input = salt.map(|salt| Vec::from(&salt[..])).unwrap_or_default();
+        input.extend(passphrase.as_ref());
+
+        // The salt and passphrase are hashed at least once, even if the count is smaller than
+        // their combined length. Repeat them into a larger buffer so that long iteration counts
+        // don't turn into many tiny hash updates.
+        let total = ::std::cmp::max(count, input.len());
+        let mut repeated = Vec::new();
+        while repeated.len() < 4096 && !input.is_empty() {
+            repeated.extend(&input);
+        }
+
+        // If more key material is needed than a single hash provides, further hash contexts are
+        // preloaded with an increasing number of zero octets.
+        let mut key = Vec::with_capacity(key_bytes);
+        let mut preload = 0;
+        while key.len() < key_bytes {
+            let mut hasher = Hasher::new(hash_algo)?;
+            hasher.update(&vec![0u8; preload]);
+
+            let mut remaining = total;
+            while remaining > 0 {
+                let len = ::std::cmp::min(remaining, repeated.len());
+                hasher.update(&repeated[..len]);
+                remaining -= len;
+            }
+
+            key.extend(hasher.finish());
+            preload += 1;
+        }
+
+        key.truncate(key_bytes);
+        Ok(key)
+    }
+
+    /// Serialize this string-to-key specifier to bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        match *self {
+            StringToKey::Simple(hash_algo) => {
+                out.push(0);
+                out.push(hash_algo.into());
+            }
+            StringToKey::Salted(hash_algo, salt) => {
+                out.push(1);
+                out.push(hash_algo.into());
+                out.extend(&salt);
+            }
+            StringToKey::IteratedSalted(hash_algo, salt, count) => {
+                out.push(3);
+                out.push(hash_algo.into());
+                out.extend(&salt);
+                out.push(s2k_encode_count(count));
+            }
+            StringToKey::GnuDummy(hash_algo) => {
+                out.push(101);
+                out.push(hash_algo);
+                out.extend(b"GNU\x01");
+            }
+            StringToKey::GnuDivertToCard(hash_algo, ref serial) => {
+                out.push(101);
+                out.push(hash_algo);
+                out.extend(b"GNU\x02");
+                out.push(serial.len() as u8);
+                out.extend(serial);
+            }
+        }
+
+        out
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<StringToKey, Error> {
         let (_, string_to_key) = match s2k(bytes) {
             IResult::Done(remaining, string_to_key) => (remaining, string_to_key),
@@ -87,4 +234,101 @@ impl StringToKey {
 pub enum S2kError {
     #[fail(display = "Invalid string to key specifier: {}", reason)]
     InvalidFormat { reason: String },
+    #[fail(display = "GnuPG stub string to key specifiers hold no secret key")]
+    NoSecretKey,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::from_hex;
+
+    const PASSPHRASE: &str = "password";
+    const SALT: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    #[test]
+    fn count_encoding() {
+        assert_eq!(s2k_decode_count(0x00), 1024);
+        assert_eq!(s2k_decode_count(0x60), 65536);
+        assert_eq!(s2k_decode_count(0xFF), 65_011_712);
+
+        assert_eq!(s2k_encode_count(65536), 0x60);
+        assert_eq!(s2k_encode_count(65537), 0x61);
+        assert_eq!(s2k_encode_count(u32::MAX), 0xFF);
+    }
+
+    #[test]
+    fn simple() {
+        let s2k = StringToKey::Simple(HashAlgorithm::Sha1);
+
+        let key = s2k.derive_key(PASSPHRASE, 16).unwrap();
+        assert_eq!(key, from_hex("5baa61e4c9b93f3f0682250b6cf8331b"));
+
+        // Longer keys continue with a hash preloaded with a zero octet.
+        let key = s2k.derive_key(PASSPHRASE, 32).unwrap();
+        assert_eq!(
+            key,
+            from_hex("5baa61e4c9b93f3f0682250b6cf8331b7ee68fd89d33953d511c74f6c70241d2")
+        );
+    }
+
+    #[test]
+    fn salted() {
+        let s2k = StringToKey::Salted(HashAlgorithm::Sha256, SALT);
+
+        let key = s2k.derive_key(PASSPHRASE, 32).unwrap();
+        assert_eq!(
+            key,
+            from_hex("401cee42f1e78e4ce02e01102870bd121c252c9f617db439352f749ceecaadfc")
+        );
+    }
+
+    #[test]
+    fn iterated_and_salted() {
+        let s2k = StringToKey::IteratedSalted(HashAlgorithm::Sha256, SALT, 65536);
+        let key = s2k.derive_key(PASSPHRASE, 32).unwrap();
+        assert_eq!(
+            key,
+            from_hex("2a07ab96719b61e9e5f02c563ff8f8ad9b1e85d832b51a709904ddf536220f49")
+        );
+
+        let s2k = StringToKey::IteratedSalted(HashAlgorithm::Sha1, SALT, 1024);
+        let key = s2k.derive_key(PASSPHRASE, 24).unwrap();
+        assert_eq!(key, from_hex("bf017b22bef28b692eaa0b9074f8cd3017f4ff9f2b6df781"));
+    }
+
+    #[test]
+    fn count_below_input_length() {
+        // The salt and passphrase are always hashed in full.
+        let iterated = StringToKey::IteratedSalted(HashAlgorithm::Sha256, SALT, 1);
+        let salted = StringToKey::Salted(HashAlgorithm::Sha256, SALT);
+
+        assert_eq!(
+            iterated.derive_key(PASSPHRASE, 32).unwrap(),
+            salted.derive_key(PASSPHRASE, 32).unwrap()
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = [3, 8, 1, 2, 3, 4, 5, 6, 7, 8, 0x60];
+        let s2k = StringToKey::from_bytes(&bytes).unwrap();
+
+        match s2k {
+            StringToKey::IteratedSalted(HashAlgorithm::Sha256, salt, 65536) => {
+                assert_eq!(salt, SALT)
+            }
+            ref s2k => panic!("unexpected S2K specifier {:?}", s2k),
+        }
+        assert_eq!(s2k.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn gnu_dummy() {
+        let s2k = StringToKey::from_bytes(b"\x65\x00GNU\x01").unwrap();
+
+        assert!(s2k.is_gnu_stub());
+        let err = s2k.derive_key(PASSPHRASE, 16).unwrap_err();
+        assert_eq!(err.to_string(), S2kError::NoSecretKey.to_string());
+    }
 }
@@ -0,0 +1,340 @@
+use std::io::Read;
+
+use digest::Digest;
+use failure::Error;
+use rand;
+use rand::RngCore;
+use sha1::Sha1;
+
+use packet::Packet;
+use symmetric::Cfb;
+use types::{PublicKeyAlgorithm, SymmetricKeyAlgorithm};
+
+// The MDC packet header, as it appears at the end of the decrypted data.
+const MDC_HEADER: [u8; 2] = [0xD3, 0x14];
+
+// The length of the MDC packet, header included.
+const MDC_LENGTH: usize = 22;
+
+/// A Symmetrically Encrypted Integrity Protected Data packet as specified in
+/// [RFC4880 &sect;5.13], holding the encrypted packets of a message.
+///
+/// The packets are encrypted with the message's session key in CFB mode, after a random prefix,
+/// and followed by a Modification Detection Code: a SHA-1 hash of everything before it, which is
+/// checked when decrypting.
+///
+/// [RFC4880 &sect;5.13]: https://tools.ietf.org/html/rfc4880#section-5.13
+#[derive(Clone, Debug)]
+pub struct IntegrityProtectedDataPacket {
+    pub version: u8,
+    pub ciphertext: Vec<u8>,
+}
+
+impl IntegrityProtectedDataPacket {
+    /// Read in an integrity protected data packet from some bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<IntegrityProtectedDataPacket, Error> {
+        match bytes.split_first() {
+            Some((&1, ciphertext)) => Ok(IntegrityProtectedDataPacket {
+                version: 1,
+                ciphertext: Vec::from(ciphertext),
+            }),
+            Some((&version, _)) => bail!(DecryptionError::UnsupportedVersion { version }),
+            None => bail!(DecryptionError::Truncated),
+        }
+    }
+
+    /// Encrypt some encoded packets with a session key, appending the Modification Detection
+    /// Code.
+    pub fn encrypt(
+        algo: SymmetricKeyAlgorithm,
+        session_key: &[u8],
+        plaintext: &[u8],
+    ) -> Result<IntegrityProtectedDataPacket, Error> {
+        let block_bytes = algo.block_bytes();
+        let mut cfb = Cfb::new(algo, session_key, &vec![0u8; block_bytes])?;
+
+        let mut data = vec![0u8; block_bytes];
+        rand::thread_rng().fill_bytes(&mut data);
+        let repeated = [data[block_bytes - 2], data[block_bytes - 1]];
+        data.extend(&repeated);
+
+        data.extend(plaintext);
+        data.extend(&MDC_HEADER);
+        let mdc = Sha1::digest(&data);
+        data.extend(mdc.as_slice());
+
+        cfb.encrypt(&mut data);
+
+        Ok(IntegrityProtectedDataPacket {
+            version: 1,
+            ciphertext: data,
+        })
+    }
+
+    /// Decrypt this packet with a session key, returning the packets it contains. See
+    /// [`decrypt_integrity_protected`].
+    ///
+    /// [`decrypt_integrity_protected`]: fn.decrypt_integrity_protected.html
+    pub fn decrypt(
+        &self,
+        algo: SymmetricKeyAlgorithm,
+        session_key: &[u8],
+    ) -> Result<Vec<Packet>, Error> {
+        let plaintext = decrypt_ciphertext(algo, session_key, self.ciphertext.clone())?;
+
+        Packet::all_from_bytes(&plaintext)
+    }
+
+    /// Serialize this integrity protected data packet to bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = vec![self.version];
+        out.extend(&self.ciphertext);
+
+        Ok(out)
+    }
+}
+
+/// Decrypt the body of a Symmetrically Encrypted Integrity Protected Data packet with a session
+/// key, such as a body handed out by a [`PacketReader`], and return the encoded packets it
+/// contains.
+///
+/// The whole body is decrypted before anything is returned, so that no plaintext is released
+/// unless the Modification Detection Code matches; a mismatch results in a
+/// [`DecryptionError::ModificationDetected`]. A [`DecryptionError::QuickCheckFailed`] usually
+/// means the session key is wrong.
+///
+/// [`PacketReader`]: struct.PacketReader.html
+/// [`DecryptionError::ModificationDetected`]: enum.DecryptionError.html#variant.ModificationDetected
+/// [`DecryptionError::QuickCheckFailed`]: enum.DecryptionError.html#variant.QuickCheckFailed
+pub fn decrypt_integrity_protected<R: Read>(
+    algo: SymmetricKeyAlgorithm,
+    session_key: &[u8],
+    mut body: R,
+) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    body.read_to_end(&mut data)?;
+
+    match data.first() {
+        Some(&1) => {}
+        Some(&version) => bail!(DecryptionError::UnsupportedVersion { version }),
+        None => bail!(DecryptionError::Truncated),
+    }
+
+    data.remove(0);
+    decrypt_ciphertext(algo, session_key, data)
+}
+
+fn decrypt_ciphertext(
+    algo: SymmetricKeyAlgorithm,
+    session_key: &[u8],
+    mut data: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let block_bytes = algo.block_bytes();
+    if data.len() < block_bytes + 2 + MDC_LENGTH {
+        bail!(DecryptionError::Truncated);
+    }
+
+    // Integrity protected data uses plain CFB mode with an IV of all zeros; the random prefix
+    // takes the place of the IV.
+    let iv = vec![0u8; block_bytes];
+    Cfb::new(algo, session_key, &iv)?.decrypt(&mut data);
+
+    // The last two octets of the prefix are repeated, as a quick check of the session key.
+    if data[block_bytes - 2..block_bytes] != data[block_bytes..block_bytes + 2] {
+        return Err(DecryptionError::QuickCheckFailed.into());
+    }
+
+    // The data must end with an MDC packet, whose header is covered by its own hash.
+    let (hashed, mdc) = data.split_at(data.len() - MDC_LENGTH + MDC_HEADER.len());
+    let header = &hashed[hashed.len() - MDC_HEADER.len()..];
+    if header != MDC_HEADER || Sha1::digest(hashed).as_slice() != mdc {
+        return Err(DecryptionError::ModificationDetected.into());
+    }
+
+    let end = data.len() - MDC_LENGTH;
+    data.truncate(end);
+    data.drain(..block_bytes + 2);
+
+    Ok(data)
+}
+
+/// Error type for decrypting encrypted data.
+#[derive(Debug, Fail)]
+pub enum DecryptionError {
+    #[fail(display = "Unsupported encrypted data packet version {}", version)]
+    UnsupportedVersion { version: u8 },
+    #[fail(display = "Encrypted data is truncated")]
+    Truncated,
+    #[fail(display = "Quick check on decrypted data failed, the session key may be wrong")]
+    QuickCheckFailed,
+    #[fail(display = "Modification detection code does not match, the data has been modified")]
+    ModificationDetected,
+    #[fail(display = "Encrypted data has no integrity protection")]
+    UnprotectedData,
+    #[fail(display = "Message has no encrypted data")]
+    MissingEncryptedData,
+    #[fail(display = "No key or passphrase could decrypt the session key")]
+    NoSessionKey,
+    #[fail(display = "Decrypted session key is malformed")]
+    InvalidSessionKey,
+    #[fail(display = "Can't decrypt session keys with {:?} keys", algorithm)]
+    UnsupportedAlgorithm { algorithm: PublicKeyAlgorithm },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use literal::LiteralData;
+    use util::from_hex;
+
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/message.txt");
+    const SYMMETRIC_MESSAGE: &[u8] = include_bytes!("../tests/data/symmetric.gpg");
+
+    const SESSION_KEY: &str = "00B4628F54A9B1344B0AB65BD7204CE49A2FBA6C0C1FF117A64E5DDBD33B9F3A";
+
+    // The body of the integrity protected data packet in the GnuPG message, which has a new-format
+    // header with a one octet length.
+    fn body() -> &'static [u8] {
+        assert_eq!(SYMMETRIC_MESSAGE[15], 0xD2);
+        &SYMMETRIC_MESSAGE[17..17 + usize::from(SYMMETRIC_MESSAGE[16])]
+    }
+
+    // Encrypt some data as integrity protected data would be, with the MDC given by `mdc`.
+    fn encrypt_with_mdc<F: Fn(&[u8]) -> Vec<u8>>(key: &[u8], plaintext: &[u8], mdc: F) -> Vec<u8> {
+        let mut data = vec![0x5A; 16];
+        data.extend(&[0x5A, 0x5A]);
+        data.extend(plaintext);
+        let mdc = mdc(&data);
+        data.extend(mdc);
+
+        Cfb::new(SymmetricKeyAlgorithm::Aes256, key, &[0u8; 16])
+            .unwrap()
+            .encrypt(&mut data);
+
+        let mut body = vec![1];
+        body.extend(data);
+        body
+    }
+
+    fn assert_error(result: Result<Vec<u8>, Error>, expected: DecryptionError) {
+        assert_eq!(result.unwrap_err().to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn decrypt_gnupg_data() {
+        let key = from_hex(SESSION_KEY);
+        let plaintext =
+            decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, body()).unwrap();
+
+        match Packet::all_from_bytes(&plaintext).unwrap().pop() {
+            Some(Packet::LiteralData(literal)) => {
+                assert_eq!(literal.filename, "message.txt");
+                match literal.contents {
+                    LiteralData::Binary(ref contents) => assert_eq!(contents, MESSAGE),
+                    ref c => panic!("expected binary data, found {:?}", c),
+                }
+            }
+            p => panic!("expected literal data, found {:?}", p),
+        }
+
+        let packet = IntegrityProtectedDataPacket::from_bytes(body()).unwrap();
+        assert_eq!(packet.version, 1);
+        assert_eq!(packet.to_bytes().unwrap(), body());
+        let packets = packet.decrypt(SymmetricKeyAlgorithm::Aes256, &key).unwrap();
+        assert_eq!(packets.len(), 1);
+    }
+
+    #[test]
+    fn modification_detected() {
+        let key = from_hex(SESSION_KEY);
+
+        // Flip a bit in the MDC itself, and in the middle of the literal data.
+        for &offset in &[body().len() - 1, body().len() / 2] {
+            let mut body = body().to_vec();
+            body[offset] ^= 0x01;
+
+            // Decryption goes on to try other session keys unless the data was modified, so the
+            // error must keep its type.
+            let result =
+                decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, &body[..]);
+            match result.unwrap_err().downcast::<DecryptionError>() {
+                Ok(DecryptionError::ModificationDetected) => {}
+                e => panic!("expected a modification to be detected, found {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn quick_check_failed() {
+        let mut key = from_hex(SESSION_KEY);
+        key[0] ^= 0x01;
+
+        let result = decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, body());
+        match result.unwrap_err().downcast::<DecryptionError>() {
+            Ok(DecryptionError::QuickCheckFailed) => {}
+            e => panic!("expected a failed quick check, found {:?}", e),
+        }
+    }
+
+    #[test]
+    fn mdc_format() {
+        let key = from_hex(SESSION_KEY);
+
+        let body = encrypt_with_mdc(&key, MESSAGE, |data| {
+            let mut mdc = MDC_HEADER.to_vec();
+            mdc.extend(Sha1::digest(&[data, &MDC_HEADER[..]].concat()).as_slice());
+            mdc
+        });
+        let plaintext =
+            decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, &body[..]).unwrap();
+        assert_eq!(plaintext, MESSAGE);
+
+        // The MDC hash must cover its own header, and the header must be that of an MDC packet.
+        let body = encrypt_with_mdc(&key, MESSAGE, |data| {
+            let mut mdc = MDC_HEADER.to_vec();
+            mdc.extend(Sha1::digest(data).as_slice());
+            mdc
+        });
+        assert_error(
+            decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, &body[..]),
+            DecryptionError::ModificationDetected,
+        );
+
+        let body = encrypt_with_mdc(&key, MESSAGE, |data| {
+            let mut mdc = vec![0xD3, 0x15];
+            mdc.extend(Sha1::digest(&[data, &[0xD3, 0x15][..]].concat()).as_slice());
+            mdc
+        });
+        assert_error(
+            decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, &body[..]),
+            DecryptionError::ModificationDetected,
+        );
+    }
+
+    #[test]
+    fn truncated_data() {
+        let key = from_hex(SESSION_KEY);
+
+        assert_error(
+            decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, &body()[..20]),
+            DecryptionError::Truncated,
+        );
+        assert_error(
+            decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, &[][..]),
+            DecryptionError::Truncated,
+        );
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let key = from_hex(SESSION_KEY);
+        let mut body = body().to_vec();
+        body[0] = 2;
+
+        assert_error(
+            decrypt_integrity_protected(SymmetricKeyAlgorithm::Aes256, &key, &body[..]),
+            DecryptionError::UnsupportedVersion { version: 2 },
+        );
+        assert!(IntegrityProtectedDataPacket::from_bytes(&body).is_err());
+    }
+}
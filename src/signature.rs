@@ -1,15 +1,20 @@
 use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::str;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 use failure::Error;
 use nom::{rest, be_u16, be_u32, be_u64, be_u8};
 use nom::{ErrorKind, IResult};
 use nom::Err as NomErr;
 use num::BigUint;
 
+use key::{Key, KeyError};
 use types::*;
 use util::parse_time_subpacket;
+use writer::write_new_format_length;
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 named!(
@@ -25,6 +30,7 @@ named!(
         payload_hash: take!(2) >>
         signature: call!(rest) >>
         (SignaturePacket {
+            version: 3,
             sig_type: SignatureType::from(signature_type),
             timestamp: Some(Duration::from_secs(u64::from(creation_time))),
             signer: Some(signer),
@@ -73,6 +79,16 @@ fn parse_hash_algorithms(inp: &[u8]) -> Vec<HashAlgorithm> {
         .collect::<Vec<_>>()
 }
 
+// Decode a symmetric algorithm, unless it would not serialize back to the same value.
+fn known_symmetric_algorithm(val: u8) -> Option<SymmetricKeyAlgorithm> {
+    let algo = SymmetricKeyAlgorithm::from(val);
+    if u8::from(algo) == val {
+        Some(algo)
+    } else {
+        None
+    }
+}
+
 fn parse_bool(inp: &[u8]) -> bool {
     inp[0] != 0
 }
@@ -120,6 +136,15 @@ fn parse_subpacket(inp: &[u8]) -> IResult<&[u8], Subpacket> {
         SubpacketType::Issuer => parse_keyid_subpacket(packet_contents)
             .map(|key_id| IResult::Done(remaining, Subpacket::Issuer(key_id)))
             .unwrap_or_else(IResult::Error),
+        SubpacketType::PreferredSymmetricAlgorithms => IResult::Done(
+            remaining,
+            // Preferences including algorithms we don't know are kept as unknown subpackets, so
+            // that they serialize back unchanged.
+            match packet_contents.iter().map(|val| known_symmetric_algorithm(*val)).collect() {
+                Some(algos) => Subpacket::PreferredSymmetricAlgorithms(algos),
+                None => Subpacket::Unknown(subpacket_type, Vec::from(packet_contents)),
+            },
+        ),
         SubpacketType::PreferredHashAlgorithms => IResult::Done(
             remaining,
             Subpacket::PreferredHashAlgorithms(parse_hash_algorithms(packet_contents)),
@@ -128,6 +153,36 @@ fn parse_subpacket(inp: &[u8]) -> IResult<&[u8], Subpacket> {
             remaining,
             Subpacket::PrimaryUserId(parse_bool(packet_contents)),
         ),
+        SubpacketType::KeyFlags => IResult::Done(
+            remaining,
+            Subpacket::KeyFlags(KeyFlags::from_bytes(packet_contents)),
+        ),
+        SubpacketType::RevocationReason if !packet_contents.is_empty() => {
+            match str::from_utf8(&packet_contents[1..]) {
+                Ok(reason) => IResult::Done(
+                    remaining,
+                    Subpacket::RevocationReason(packet_contents[0], String::from(reason)),
+                ),
+                Err(_) => IResult::Done(
+                    remaining,
+                    Subpacket::Unknown(subpacket_type, Vec::from(packet_contents)),
+                ),
+            }
+        }
+        SubpacketType::EmbeddedSignature => match SignaturePacket::from_bytes(packet_contents) {
+            Ok(signature) => IResult::Done(
+                remaining,
+                Subpacket::EmbeddedSignature(Box::new(signature)),
+            ),
+            Err(_) => IResult::Done(
+                remaining,
+                Subpacket::Unknown(subpacket_type, Vec::from(packet_contents)),
+            ),
+        },
+        SubpacketType::IssuerFingerprint if !packet_contents.is_empty() => IResult::Done(
+            remaining,
+            Subpacket::IssuerFingerprint(packet_contents[0], Vec::from(&packet_contents[1..])),
+        ),
         _ => IResult::Done(remaining, Subpacket::Unknown(subpacket_type, Vec::from(packet_contents))),
     }
 }
@@ -154,6 +209,38 @@ fn find_signer(subpackets: &[Subpacket]) -> Option<u64> {
     None
 }
 
+/// Read the two multiprecision integers `r` and `s` making up a DSA, ECDSA or EdDSA signature.
+fn two_mpis(contents: &[u8]) -> Result<(BigUint, BigUint), Error> {
+    if contents.len() < 2 {
+        bail!(SignatureError::MalformedMpi);
+    }
+
+    let (mut header_r_slice, remaining) = contents.split_at(2);
+    let header_r = header_r_slice.read_u16::<BigEndian>()?;
+    // GPG uses the header to indicate the number of bits in the MPI; we care about the
+    // number of bytes.
+    let header_r = (header_r as f64 / 8.0).ceil() as usize;
+
+    if remaining.len() < header_r + 2 {
+        bail!(SignatureError::MalformedMpi);
+    }
+
+    let (mpi_r_slice, remaining) = remaining.split_at(header_r);
+
+    let (mut header_s_slice, mpi_s_slice) = remaining.split_at(2);
+    let header_s = header_s_slice.read_u16::<BigEndian>()?;
+    let header_s = (header_s as f64 / 8.0).ceil() as usize;
+
+    if mpi_s_slice.len() < header_s {
+        bail!(SignatureError::MalformedMpi);
+    }
+
+    let mpi_r = BigUint::from_bytes_be(mpi_r_slice);
+    let mpi_s = BigUint::from_bytes_be(&mpi_s_slice[..header_s]);
+
+    Ok((mpi_r, mpi_s))
+}
+
 #[cfg_attr(rustfmt, rustfmt_skip)]
 named!(
     v4_sig<SignaturePacket>,
@@ -167,6 +254,7 @@ named!(
         payload_hash: take!(2) >>
         signature: call!(rest) >>
         (SignaturePacket {
+            version: 4,
             sig_type: SignatureType::from(signature_type),
             timestamp: find_timestamp(&hashed_subs).or_else(|| find_timestamp(&unhashed_subs)),
             signer: find_signer(&hashed_subs).or_else(|| find_signer(&unhashed_subs)),
@@ -185,6 +273,9 @@ named!(signature<SignaturePacket>, alt!(v3_sig | v4_sig));
 /// The contents of a PGP signature packet.
 #[derive(Clone, Debug)]
 pub struct SignaturePacket {
+    // The version the signature was read as. Version 3 signatures hash different material, but
+    // are written back out as version 4.
+    pub(crate) version: u8,
     pub sig_type: SignatureType,
     timestamp: Option<Duration>,
     signer: Option<u64>,
@@ -207,6 +298,7 @@ impl SignaturePacket {
         let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
 
         Ok(SignaturePacket {
+            version: 4,
             sig_type: sig_type,
             timestamp: Some(timestamp),
             signer: None,
@@ -220,8 +312,8 @@ impl SignaturePacket {
     }
 
     /// Retrieve the contents of this signature. For RSA signatures, this is a single
-    /// multiprecision integer representing `m^d mod n`; for DSA signatures this is two
-    /// multiprecision integers representing `r` and `s`.
+    /// multiprecision integer representing `m^d mod n`; for DSA, ECDSA and EdDSA signatures this
+    /// is two multiprecision integers representing `r` and `s`.
     pub fn contents(&self) -> Result<Signature, Error> {
         match self.pubkey_algo {
             PublicKeyAlgorithm::Rsa
@@ -242,30 +334,16 @@ impl SignaturePacket {
                 Ok(Signature::Rsa(mpi))
             }
             PublicKeyAlgorithm::Dsa => {
-                let (mut header_r_slice, remaining) = self.signature_contents.split_at(2);
-                let header_r = header_r_slice.read_u16::<BigEndian>()?;
-                // GPG uses the header to indicate the number of bits in the MPI; we care about the
-                // number of bytes.
-                let header_r = (header_r as f64 / 8.0).ceil() as usize;
-
-                if remaining.len() < header_r {
-                    bail!(SignatureError::MalformedMpi);
-                }
-
-                let (mpi_r_slice, remaining) = remaining.split_at(header_r);
-
-                let (mut header_s_slice, mpi_s_slice) = remaining.split_at(2);
-                let header_s = header_s_slice.read_u16::<BigEndian>()?;
-                let header_s = (header_s as f64 / 8.0).ceil() as usize;
-
-                if mpi_s_slice.len() < header_s {
-                    bail!(SignatureError::MalformedMpi);
-                }
-
-                let mpi_r = BigUint::from_bytes_be(mpi_r_slice);
-                let mpi_s = BigUint::from_bytes_be(mpi_s_slice);
-
-                Ok(Signature::Dsa(mpi_r, mpi_s))
+                let (r, s) = two_mpis(&self.signature_contents)?;
+                Ok(Signature::Dsa(r, s))
+            }
+            PublicKeyAlgorithm::Ecdsa => {
+                let (r, s) = two_mpis(&self.signature_contents)?;
+                Ok(Signature::Ecdsa(r, s))
+            }
+            PublicKeyAlgorithm::EdDsa => {
+                let (r, s) = two_mpis(&self.signature_contents)?;
+                Ok(Signature::EdDsa(r, s))
             }
             _ => Ok(Signature::Unknown(self.signature_contents.clone())),
         }
@@ -282,7 +360,7 @@ impl SignaturePacket {
 
                 self.signature_contents = mpi_header;
             }
-            Signature::Dsa(r, s) => {
+            Signature::Dsa(r, s) | Signature::Ecdsa(r, s) | Signature::EdDsa(r, s) => {
                 let mut mpis = Vec::new();
 
                 mpis.write_u16::<BigEndian>(r.bits() as u16)?;
@@ -356,6 +434,148 @@ impl SignaturePacket {
         self.signer = Some(signer);
     }
 
+    /// Retrieve the fingerprint of this signature's issuer.
+    pub fn issuer_fingerprint(&self) -> Option<Vec<u8>> {
+        for subpacket in self.hashed_subpackets.iter().chain(&self.unhashed_subpackets) {
+            if let Subpacket::IssuerFingerprint(_, ref fingerprint) = *subpacket {
+                return Some(fingerprint.clone());
+            }
+        }
+
+        None
+    }
+
+    /// Set the fingerprint of this signature's issuer, along with the version of the issuer's key.
+    /// This subpacket will be added as a hashed subpacket.
+    pub fn set_issuer_fingerprint<T: AsRef<[u8]>>(&mut self, key_version: u8, fingerprint: T) {
+        self.clear_issuer_fingerprint();
+        self.hashed_subpackets.push(Subpacket::IssuerFingerprint(
+            key_version,
+            Vec::from(fingerprint.as_ref()),
+        ));
+    }
+
+    /// Remove the fingerprint of this signature's issuer.
+    pub(crate) fn clear_issuer_fingerprint(&mut self) {
+        self.hashed_subpackets.retain(|subpacket| {
+            if let Subpacket::IssuerFingerprint(..) = *subpacket {
+                false
+            } else {
+                true
+            }
+        });
+        self.unhashed_subpackets.retain(|subpacket| {
+            if let Subpacket::IssuerFingerprint(..) = *subpacket {
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Retrieve how long after its creation this signature expires, if it does.
+    ///
+    /// Only hashed subpackets are considered, since unhashed ones could be altered by anyone.
+    pub fn expiration_time(&self) -> Option<Duration> {
+        for subpacket in &self.hashed_subpackets {
+            if let Subpacket::SignatureExpirationTime(time) = *subpacket {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+
+    /// Whether this signature has expired at the given time, measured since the Unix epoch.
+    ///
+    /// An expiration time of zero means the signature never expires.
+    pub fn is_expired_at(&self, time: Duration) -> bool {
+        match (self.timestamp(), self.expiration_time()) {
+            (Some(created), Some(expiration)) if expiration.as_secs() != 0 => {
+                created + expiration <= time
+            }
+            _ => false,
+        }
+    }
+
+    /// Retrieve how long after its creation the key this self-signature is over expires, if it
+    /// does. Only hashed subpackets are considered.
+    pub fn key_expiration_time(&self) -> Option<Duration> {
+        for subpacket in &self.hashed_subpackets {
+            if let Subpacket::KeyExpirationTime(time) = *subpacket {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+
+    /// Retrieve the capabilities this self-signature gives the key it is over. Only hashed
+    /// subpackets are considered.
+    pub fn key_flags(&self) -> Option<KeyFlags> {
+        for subpacket in &self.hashed_subpackets {
+            if let Subpacket::KeyFlags(ref flags) = *subpacket {
+                return Some(flags.clone());
+            }
+        }
+
+        None
+    }
+
+    /// Whether this self-signature marks its user ID as the primary one. Only hashed subpackets
+    /// are considered.
+    pub fn is_primary_user_id(&self) -> bool {
+        for subpacket in &self.hashed_subpackets {
+            if let Subpacket::PrimaryUserId(primary) = *subpacket {
+                return primary;
+            }
+        }
+
+        false
+    }
+
+    /// Retrieve the reason code and human-readable reason given by a revocation signature.
+    pub fn revocation_reason(&self) -> Option<(u8, String)> {
+        for subpacket in &self.hashed_subpackets {
+            if let Subpacket::RevocationReason(code, ref reason) = *subpacket {
+                return Some((code, reason.clone()));
+            }
+        }
+
+        None
+    }
+
+    /// Retrieve a signature embedded in this one, such as the primary key binding signature
+    /// ("back signature") in the binding signature of a signing subkey.
+    pub fn embedded_signature(&self) -> Option<SignaturePacket> {
+        for subpacket in self.hashed_subpackets.iter().chain(&self.unhashed_subpackets) {
+            if let Subpacket::EmbeddedSignature(ref signature) = *subpacket {
+                return Some((**signature).clone());
+            }
+        }
+
+        None
+    }
+
+    /// Retrieve the preferred symmetric algorithms of this signature, from its hashed subpackets.
+    /// Any algorithms that aren't supported are left out.
+    pub fn preferred_symmetric_algorithms(&self) -> Option<Vec<SymmetricKeyAlgorithm>> {
+        let tag = SubpacketType::PreferredSymmetricAlgorithms as u8;
+        for subpacket in &self.hashed_subpackets {
+            match *subpacket {
+                Subpacket::PreferredSymmetricAlgorithms(ref algos) => return Some(algos.clone()),
+                Subpacket::Unknown(unknown_tag, ref algos) if unknown_tag == tag => {
+                    return Some(
+                        algos.iter().filter_map(|algo| known_symmetric_algorithm(*algo)).collect(),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
     /// Retrieve the preferred hash algorithms of this signature.
     pub fn preferred_hash_algorithms(&self) -> Option<Vec<HashAlgorithm>> {
         for subpacket in &self.hashed_subpackets {
@@ -441,13 +661,185 @@ impl SignaturePacket {
 
     /// Build a payload suitable for signing.
     ///
-    /// Note that this payload must be placed in an ASN.1 DigestInfo structure prior to signing,
-    /// which is outside the scope of this library.
+    /// For text document signatures, the line endings of the payload are converted to `<CR><LF>`
+    /// before it is hashed, as required by [RFC4880 &sect;5.2.1]. Earlier versions hashed the
+    /// payload exactly as given, so text with bare `<LF>` line endings now produces a different,
+    /// interoperable digest; payloads that already use `<CR><LF>` hash the same as before.
+    ///
+    /// Note that for RSA signatures, this payload must be placed in an ASN.1 DigestInfo structure
+    /// prior to signing; see [`HashAlgorithm::digest_info`].
+    ///
+    /// [RFC4880 &sect;5.2.1]: https://tools.ietf.org/html/rfc4880#section-5.2.1
+    /// [`HashAlgorithm::digest_info`]: enum.HashAlgorithm.html#method.digest_info
     pub fn signable_payload<T: AsRef<[u8]>>(&self, payload: T) -> Result<Vec<u8>, Error> {
-        let mut signing_payload = Vec::from(payload.as_ref());
+        let hash = self.digest(payload)?;
+        if hash.len() >= 2 {
+            self.payload_hash.replace(Some([hash[0], hash[1]]));
+        }
+
+        Ok(hash)
+    }
+
+    /// Create a hasher to compute the digest of a payload incrementally, without holding the
+    /// whole payload in memory.
+    ///
+    /// See [`SignatureHasher`] for details.
+    ///
+    /// [`SignatureHasher`]: struct.SignatureHasher.html
+    pub fn hasher(&self) -> Result<SignatureHasher<'_>, Error> {
+        Ok(SignatureHasher {
+            signature: self,
+            data: SignedDataHasher::new(self.hash_algo, self.sig_type)?,
+        })
+    }
+
+    /// Build a payload suitable for signing as a certification of a user ID on a key, or as the
+    /// revocation of such a certification.
+    ///
+    /// As described in [RFC4880 &sect;5.2.4], both the key and the user ID are hashed with a
+    /// prefix giving their length. Like [`signable_payload`], this records the first two octets of
+    /// the digest in the signature.
+    ///
+    /// [RFC4880 &sect;5.2.4]: https://tools.ietf.org/html/rfc4880#section-5.2.4
+    /// [`signable_payload`]: #method.signable_payload
+    pub fn certification_payload(&self, key: &Key, user_id: &str) -> Result<Vec<u8>, Error> {
+        self.certification_hasher(key, user_id)?.finish()
+    }
+
+    /// Build a payload suitable for signing as a certification of a user attribute on a key, or
+    /// as the revocation of such a certification. The attribute is given as the contents of its
+    /// packet.
+    ///
+    /// Like [`signable_payload`], this records the first two octets of the digest in the
+    /// signature.
+    ///
+    /// [`signable_payload`]: #method.signable_payload
+    pub fn attribute_certification_payload(
+        &self,
+        key: &Key,
+        attribute: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        self.attribute_certification_hasher(key, attribute)?.finish()
+    }
+
+    /// Build a payload suitable for signing as a subkey binding, primary key binding or subkey
+    /// revocation signature, which cover both the primary key and the subkey.
+    ///
+    /// Like [`signable_payload`], this records the first two octets of the digest in the
+    /// signature.
+    ///
+    /// [`signable_payload`]: #method.signable_payload
+    pub fn key_binding_payload(&self, primary: &Key, subkey: &Key) -> Result<Vec<u8>, Error> {
+        self.key_binding_hasher(primary, subkey)?.finish()
+    }
+
+    /// Build a payload suitable for signing as a direct-key or key revocation signature, which
+    /// cover only the key itself.
+    ///
+    /// Like [`signable_payload`], this records the first two octets of the digest in the
+    /// signature.
+    ///
+    /// [`signable_payload`]: #method.signable_payload
+    pub fn direct_key_payload(&self, key: &Key) -> Result<Vec<u8>, Error> {
+        self.direct_key_hasher(key)?.finish()
+    }
+
+    pub(crate) fn certification_hasher(
+        &self,
+        key: &Key,
+        user_id: &str,
+    ) -> Result<SignatureHasher<'_>, Error> {
+        self.check_type(CERTIFICATION_TYPES)?;
+
+        let mut hasher = self.hasher()?;
+        hasher.update_key(key)?;
+        hasher.update_user_id(user_id);
+
+        Ok(hasher)
+    }
+
+    pub(crate) fn attribute_certification_hasher(
+        &self,
+        key: &Key,
+        attribute: &[u8],
+    ) -> Result<SignatureHasher<'_>, Error> {
+        self.check_type(CERTIFICATION_TYPES)?;
+
+        let mut hasher = self.hasher()?;
+        hasher.update_key(key)?;
+        hasher.update_user_attribute(attribute);
+
+        Ok(hasher)
+    }
+
+    pub(crate) fn key_binding_hasher(
+        &self,
+        primary: &Key,
+        subkey: &Key,
+    ) -> Result<SignatureHasher<'_>, Error> {
+        self.check_type(&[
+            SignatureType::SubkeyBinding,
+            SignatureType::PrimaryKeyBinding,
+            SignatureType::SubkeyRevocation,
+        ])?;
+
+        let mut hasher = self.hasher()?;
+        hasher.update_key(primary)?;
+        hasher.update_key(subkey)?;
+
+        Ok(hasher)
+    }
+
+    pub(crate) fn direct_key_hasher(&self, key: &Key) -> Result<SignatureHasher<'_>, Error> {
+        self.check_type(&[SignatureType::DirectKey, SignatureType::KeyRevocation])?;
+
+        let mut hasher = self.hasher()?;
+        hasher.update_key(key)?;
+
+        Ok(hasher)
+    }
+
+    fn check_type(&self, allowed: &[SignatureType]) -> Result<(), Error> {
+        if !allowed.contains(&self.sig_type) {
+            bail!(SignatureError::WrongType {
+                sig_type: format!("{:?}", self.sig_type),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Compute the digest of a payload signed by this signature, without recording its first two
+    /// octets in the signature. Like [`hasher`], this converts the line endings of text documents.
+    ///
+    /// [`hasher`]: #method.hasher
+    pub(crate) fn digest<T: AsRef<[u8]>>(&self, payload: T) -> Result<Vec<u8>, Error> {
+        let mut hasher = SignedDataHasher::new(self.hash_algo, self.sig_type)?;
+        hasher.update(payload.as_ref());
+
+        self.finish_digest(hasher.finish())
+    }
+
+    /// Finish computing the digest of a payload signed by this signature, given a hasher that has
+    /// already been fed the payload.
+    pub(crate) fn finish_digest(&self, mut hasher: Hasher) -> Result<Vec<u8>, Error> {
+        // Version 3 signatures only hash the signature type and creation time, with no trailer;
+        // see RFC4880, Section 5.2.2.
+        if self.version == 3 {
+            let timestamp = match self.timestamp {
+                Some(timestamp) => timestamp.as_secs() as u32,
+                None => bail!(SignatureError::Unusable {
+                    reason: "no creation time".to_string(),
+                }),
+            };
+
+            hasher.update(&[self.sig_type.into()]);
+            hasher.update(&timestamp.to_be_bytes());
+            return Ok(hasher.finish());
+        }
 
         let common_header = self.common_header()?;
-        signing_payload.extend(&common_header);
+        hasher.update(&common_header);
 
         // From RFC4880, Section 5.2.4:
         // V4 signatures also hash in a final trailer of six octets: the
@@ -459,14 +851,9 @@ impl SignaturePacket {
         suffix.push(0x04);
         suffix.push(0xFF);
         suffix.write_u32::<BigEndian>(common_header.len() as u32)?;
-        signing_payload.extend(&suffix);
-
-        let hash = self.hash_algo.hash(signing_payload)?;
-        if hash.len() >= 2 {
-            self.payload_hash.replace(Some([hash[0], hash[1]]));
-        }
+        hasher.update(&suffix);
 
-        Ok(hash)
+        Ok(hasher.finish())
     }
 
     /// Retrieve the header for this signature, i.e. everything except the MPI contents of the
@@ -527,6 +914,163 @@ impl SignaturePacket {
     }
 }
 
+// Signature types that certify a user ID or attribute, or revoke such a certification.
+const CERTIFICATION_TYPES: &[SignatureType] = &[
+    SignatureType::GenericCertification,
+    SignatureType::PersonaCertification,
+    SignatureType::CasualCertification,
+    SignatureType::PositiveCertification,
+    SignatureType::CertificationRevocation,
+];
+
+/// An incremental hash of signed data, converting line endings to `<CR><LF>` first for text
+/// signatures as required by [RFC4880 &sect;5.2.1].
+///
+/// [RFC4880 &sect;5.2.1]: https://tools.ietf.org/html/rfc4880#section-5.2.1
+#[derive(Clone)]
+pub(crate) struct SignedDataHasher {
+    hasher: Hasher,
+    text: bool,
+    last_cr: bool,
+}
+
+impl SignedDataHasher {
+    pub(crate) fn new(
+        hash_algo: HashAlgorithm,
+        sig_type: SignatureType,
+    ) -> Result<SignedDataHasher, Error> {
+        Ok(SignedDataHasher {
+            hasher: Hasher::new(hash_algo)?,
+            text: sig_type == SignatureType::TextDocument,
+            last_cr: false,
+        })
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        if !self.text {
+            self.hasher.update(data);
+            return;
+        }
+
+        let mut start = 0;
+        for (i, byte) in data.iter().enumerate() {
+            if *byte != b'\n' {
+                continue;
+            }
+
+            let after_cr = if i == 0 { self.last_cr } else { data[i - 1] == b'\r' };
+            if !after_cr {
+                self.hasher.update(&data[start..i]);
+                self.hasher.update(b"\r");
+                start = i;
+            }
+        }
+        self.hasher.update(&data[start..]);
+
+        if let Some(last) = data.last() {
+            self.last_cr = *last == b'\r';
+        }
+    }
+
+    pub(crate) fn finish(self) -> Hasher {
+        self.hasher
+    }
+}
+
+/// A streaming hasher for the material covered by a signature, created by
+/// [`SignaturePacket::hasher`].
+///
+/// Data fed to the hasher with [`update`] or through its `Write` implementation is hashed as-is,
+/// except for text document signatures, whose line endings are converted to `<CR><LF>`. For
+/// signature types that don't cover a document, such as key certifications, the caller feeds in
+/// whatever material the signature type calls for; standalone and timestamp signatures cover
+/// nothing at all. [`finish`] then hashes in the signature's own fields and the v4 trailer, like
+/// [`SignaturePacket::signable_payload`].
+///
+/// [`SignaturePacket::hasher`]: struct.SignaturePacket.html#method.hasher
+/// [`update`]: #method.update
+/// [`finish`]: #method.finish
+/// [`SignaturePacket::signable_payload`]: struct.SignaturePacket.html#method.signable_payload
+pub struct SignatureHasher<'a> {
+    signature: &'a SignaturePacket,
+    data: SignedDataHasher,
+}
+
+impl<'a> SignatureHasher<'a> {
+    /// Hash some more of the signed data.
+    pub fn update(&mut self, data: &[u8]) {
+        self.data.update(data);
+    }
+
+    /// Hash a key as certification and key binding signatures do: its public key packet body,
+    /// prefixed with `0x99` and a two-octet length. Keys too long for that length are rejected.
+    pub fn update_key(&mut self, key: &Key) -> Result<(), Error> {
+        let key_data = key.public_to_bytes()?;
+        if key_data.len() > 0xFFFF {
+            bail!(KeyError::InvalidFormat {
+                reason: "public key is too long to be hashed".to_string(),
+            });
+        }
+
+        let mut prefix = [0x99, 0, 0];
+        BigEndian::write_u16(&mut prefix[1..], key_data.len() as u16);
+        self.update(&prefix);
+        self.update(&key_data);
+
+        Ok(())
+    }
+
+    /// Hash a user ID as certification signatures do: its contents, prefixed with `0xB4` and a
+    /// four-octet length.
+    pub fn update_user_id(&mut self, user_id: &str) {
+        let mut prefix = [0xB4, 0, 0, 0, 0];
+        BigEndian::write_u32(&mut prefix[1..], user_id.len() as u32);
+        self.update(&prefix);
+        self.update(user_id.as_bytes());
+    }
+
+    /// Hash a user attribute as certification signatures do: the contents of its packet,
+    /// prefixed with `0xD1` and a four-octet length.
+    pub fn update_user_attribute(&mut self, attribute: &[u8]) {
+        let mut prefix = [0xD1, 0, 0, 0, 0];
+        BigEndian::write_u32(&mut prefix[1..], attribute.len() as u32);
+        self.update(&prefix);
+        self.update(attribute);
+    }
+
+    /// Finish hashing, returning the digest to be signed or verified.
+    ///
+    /// As with [`SignaturePacket::signable_payload`], the first two octets of the digest are
+    /// recorded in the signature.
+    ///
+    /// [`SignaturePacket::signable_payload`]: struct.SignaturePacket.html#method.signable_payload
+    pub fn finish(self) -> Result<Vec<u8>, Error> {
+        let signature = self.signature;
+        let hash = self.digest()?;
+        if hash.len() >= 2 {
+            signature.payload_hash.replace(Some([hash[0], hash[1]]));
+        }
+
+        Ok(hash)
+    }
+
+    /// Finish hashing without recording anything in the signature, as when verifying it.
+    pub(crate) fn digest(self) -> Result<Vec<u8>, Error> {
+        self.signature.finish_digest(self.data.finish())
+    }
+}
+
+impl<'a> Write for SignatureHasher<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 /// The type of content a signature covers. See [RFC4880 &sect;5.2.1].
 ///
 /// [RFC4880 &sect;5.2.1]: https://tools.ietf.org/html/rfc4880#section-5.2.1
@@ -610,7 +1154,7 @@ pub enum Subpacket {
     RegularExpression,
     Revocable(bool),
     KeyExpirationTime(Duration),
-    PreferredSymmetricAlgorithms,
+    PreferredSymmetricAlgorithms(Vec<SymmetricKeyAlgorithm>),
     RevocationKey,
     Issuer(u64),
     NotationData,
@@ -620,12 +1164,15 @@ pub enum Subpacket {
     PreferredKeyServer,
     PrimaryUserId(bool),
     PolicyUri,
-    KeyFlags,
+    KeyFlags(KeyFlags),
     SignerUserId,
-    RevocationReason,
+    /// A machine-readable reason code, and a human-readable reason for a revocation.
+    RevocationReason(u8, String),
     Features,
     SignatureTarget,
-    EmbeddedSignature,
+    EmbeddedSignature(Box<SignaturePacket>),
+    /// The version of the issuer's key, and its fingerprint.
+    IssuerFingerprint(u8, Vec<u8>),
     Unknown(u8, Vec<u8>),
 }
 
@@ -658,6 +1205,12 @@ impl Subpacket {
                 out.push(SubpacketType::Issuer as u8);
                 out.write_u64::<BigEndian>(issuer)?;
             }
+            Subpacket::PreferredSymmetricAlgorithms(ref algos) => {
+                out.push(SubpacketType::PreferredSymmetricAlgorithms as u8);
+                for algo in algos {
+                    out.push((*algo).into());
+                }
+            }
             Subpacket::PreferredHashAlgorithms(ref algos) => {
                 out.push(SubpacketType::PreferredHashAlgorithms as u8);
                 for algo in algos {
@@ -668,6 +1221,24 @@ impl Subpacket {
                 out.push(SubpacketType::PrimaryUserId as u8);
                 out.push(primary as u8);
             }
+            Subpacket::KeyFlags(ref flags) => {
+                out.push(SubpacketType::KeyFlags as u8);
+                out.extend(&flags.to_bytes());
+            }
+            Subpacket::RevocationReason(code, ref reason) => {
+                out.push(SubpacketType::RevocationReason as u8);
+                out.push(code);
+                out.extend(reason.as_bytes());
+            }
+            Subpacket::EmbeddedSignature(ref signature) => {
+                out.push(SubpacketType::EmbeddedSignature as u8);
+                out.extend(&signature.to_bytes()?);
+            }
+            Subpacket::IssuerFingerprint(key_version, ref fingerprint) => {
+                out.push(SubpacketType::IssuerFingerprint as u8);
+                out.push(key_version);
+                out.extend(fingerprint);
+            }
             Subpacket::Unknown(tag, ref contents) => {
                 out.push(tag);
                 out.extend(contents);
@@ -675,27 +1246,81 @@ impl Subpacket {
             _ => {}
         }
 
-        let mut packet_len = if out.len() < 192 {
-            vec![out.len() as u8]
-        } else {
-            let mut packet_len = vec![255u8];
-            packet_len.write_u32::<BigEndian>(out.len() as u32)?;
-            packet_len
-        };
+        let mut packet_len = Vec::new();
+        write_new_format_length(&mut packet_len, out.len() as u32)?;
 
         packet_len.extend(&out);
         Ok(packet_len)
     }
 }
 
+/// The capabilities of a key, as given by a Key Flags subpacket. See [RFC4880 &sect;5.2.3.21].
+///
+/// [RFC4880 &sect;5.2.3.21]: https://tools.ietf.org/html/rfc4880#section-5.2.3.21
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyFlags {
+    flags: Vec<u8>,
+}
+
+impl KeyFlags {
+    pub const CERTIFY: u8 = 0x01;
+    pub const SIGN: u8 = 0x02;
+    pub const ENCRYPT_COMMUNICATIONS: u8 = 0x04;
+    pub const ENCRYPT_STORAGE: u8 = 0x08;
+    pub const SPLIT: u8 = 0x10;
+    pub const AUTHENTICATE: u8 = 0x20;
+    pub const SHARED: u8 = 0x80;
+
+    /// Create a set of key flags from the flags in the first octet of the subpacket, which are
+    /// given by the constants on this type.
+    pub fn new(flags: u8) -> KeyFlags {
+        KeyFlags { flags: vec![flags] }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> KeyFlags {
+        KeyFlags {
+            flags: Vec::from(bytes),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.flags.clone()
+    }
+
+    /// Whether all of the given flags from the first octet are set.
+    pub fn contains(&self, flags: u8) -> bool {
+        self.flags.first().map_or(0, |first| *first) & flags == flags
+    }
+
+    pub fn can_certify(&self) -> bool {
+        self.contains(KeyFlags::CERTIFY)
+    }
+
+    pub fn can_sign(&self) -> bool {
+        self.contains(KeyFlags::SIGN)
+    }
+
+    /// Whether the key may be used to encrypt either communications or storage.
+    pub fn can_encrypt(&self) -> bool {
+        self.contains(KeyFlags::ENCRYPT_COMMUNICATIONS) || self.contains(KeyFlags::ENCRYPT_STORAGE)
+    }
+
+    pub fn can_authenticate(&self) -> bool {
+        self.contains(KeyFlags::AUTHENTICATE)
+    }
+}
+
 /// Actual multiprecision integer signature contents.
 ///
-/// For RSA signatures, this is the multiprecision integer representing `m^d mod n`. For DSA
-/// signatures, this is two multiprecision integers representing `r` and `s`, respectively.
+/// For RSA signatures, this is the multiprecision integer representing `m^d mod n`. For DSA,
+/// ECDSA and EdDSA signatures, this is two multiprecision integers representing `r` and `s`,
+/// respectively. EdDSA values hold the native encodings of `R` and `S` as integers.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Signature {
     Rsa(BigUint),
     Dsa(BigUint, BigUint),
+    Ecdsa(BigUint, BigUint),
+    EdDsa(BigUint, BigUint),
     Unknown(Vec<u8>),
 }
 
@@ -710,6 +1335,8 @@ pub enum SignatureError {
     Unusable { reason: String },
     #[fail(display = "Malformed MPI payload")]
     MalformedMpi,
+    #[fail(display = "Signature type {} can't be used here", sig_type)]
+    WrongType { sig_type: String },
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -738,6 +1365,7 @@ enum SubpacketType {
     Features = 30,
     SignatureTarget = 31,
     EmbeddedSignature = 32,
+    IssuerFingerprint = 33,
     Reserved,
     Unknown,
 }
@@ -769,7 +1397,102 @@ impl From<u8> for SubpacketType {
             30 => SubpacketType::Features,
             31 => SubpacketType::SignatureTarget,
             32 => SubpacketType::EmbeddedSignature,
+            33 => SubpacketType::IssuerFingerprint,
             _ => SubpacketType::Unknown,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use certificate::Certificate;
+    use packet::Packet;
+
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/message.txt");
+    const TEXT: &[u8] = include_bytes!("../tests/data/text.txt");
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const ED_SIGNATURE: &[u8] = include_bytes!("../tests/data/ed.sig");
+    const ED_TEXT_SIGNATURE: &[u8] = include_bytes!("../tests/data/ed.text.sig");
+
+    fn signature(bytes: &[u8]) -> SignaturePacket {
+        match Packet::from_bytes(bytes).unwrap().0 {
+            Packet::Signature(signature) => signature,
+            p => panic!("expected a signature, found {:?}", p),
+        }
+    }
+
+    fn primary_key() -> Key {
+        Certificate::from_bytes(ED_CERTIFICATE).unwrap().primary
+    }
+
+    #[test]
+    fn hash_in_pieces() {
+        let signature = signature(ED_SIGNATURE);
+
+        let mut hasher = signature.hasher().unwrap();
+        for byte in MESSAGE.chunks(1) {
+            hasher.update(byte);
+        }
+        let digest = hasher.digest().unwrap();
+
+        assert_eq!(digest, signature.digest(MESSAGE).unwrap());
+        signature.verify_digest(&digest, &primary_key()).unwrap();
+    }
+
+    #[test]
+    fn hash_with_write() {
+        let signature = signature(ED_SIGNATURE);
+
+        let mut hasher = signature.hasher().unwrap();
+        io::copy(&mut &MESSAGE[..], &mut hasher).unwrap();
+        let digest = hasher.digest().unwrap();
+
+        signature.verify_digest(&digest, &primary_key()).unwrap();
+    }
+
+    #[test]
+    fn hash_text_line_endings() {
+        let signature = signature(ED_TEXT_SIGNATURE);
+        assert_eq!(signature.sig_type, SignatureType::TextDocument);
+
+        let digest = signature.digest(TEXT).unwrap();
+        signature.verify_digest(&digest, &primary_key()).unwrap();
+
+        // A <CR><LF> split across two updates is still a single line ending.
+        let crlf = String::from_utf8(TEXT.to_vec()).unwrap().replace("\n", "\r\n");
+        let split = crlf.find('\n').unwrap();
+        let mut hasher = signature.hasher().unwrap();
+        hasher.update(&crlf.as_bytes()[..split]);
+        hasher.update(&crlf.as_bytes()[split..]);
+
+        assert_eq!(hasher.digest().unwrap(), digest);
+    }
+
+    #[test]
+    fn hash_binary_line_endings() {
+        let mut signature = signature(ED_SIGNATURE);
+        signature.sig_type = SignatureType::BinaryDocument;
+
+        assert_ne!(
+            signature.digest(b"Hello, world!\r\n").unwrap(),
+            signature.digest(MESSAGE).unwrap()
+        );
+    }
+
+    #[test]
+    fn finish_records_hash_prefix() {
+        let signature = SignaturePacket::new(
+            SignatureType::BinaryDocument,
+            PublicKeyAlgorithm::EdDsa,
+            HashAlgorithm::Sha256,
+        ).unwrap();
+
+        let mut hasher = signature.hasher().unwrap();
+        hasher.update(MESSAGE);
+        let digest = hasher.finish().unwrap();
+
+        assert_eq!(*signature.payload_hash.borrow(), Some([digest[0], digest[1]]));
+        assert_eq!(digest, signature.signable_payload(MESSAGE).unwrap());
+    }
+}
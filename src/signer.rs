@@ -0,0 +1,500 @@
+use std::io;
+use std::io::Read;
+
+use ed25519_dalek;
+use ed25519_dalek::Signer as Ed25519Signer;
+use failure::Error;
+use num::{BigUint, One, Zero};
+
+use key::{DsaPrivateKey, DsaPublicKey, EcdsaPrivateKey, EcdsaPublicKey, EdDsaPrivateKey,
+          EdDsaPublicKey, Key, KeyError, KeyMaterial, RsaPrivateKey, RsaPublicKey};
+use signature::{Signature, SignaturePacket};
+use types::*;
+use util::{fixed_bytes, random_below};
+use verify::{emsa_pkcs1_v1_5, truncate_digest};
+
+/// A source of raw signatures, such as a hardware security module or a remote signing service.
+///
+/// Implementors only need to produce the signature over a digest; building and finishing the
+/// surrounding [`SignaturePacket`] is handled by [`sign`].
+///
+/// [`SignaturePacket`]: struct.SignaturePacket.html
+/// [`sign`]: fn.sign.html
+pub trait Signer {
+    /// The key ID of the signing key.
+    fn key_id(&self) -> Result<u64, Error>;
+
+    /// The fingerprint of the signing key.
+    fn fingerprint(&self) -> Result<Vec<u8>, Error>;
+
+    /// The version of the signing key's packet. The fingerprint is only added to signatures made
+    /// by version 4 keys.
+    ///
+    /// By default, this is worked out from the length of the fingerprint: version 4 fingerprints
+    /// are 20 octets long, and version 3 ones are 16.
+    fn key_version(&self) -> u8 {
+        match self.fingerprint() {
+            Ok(ref fingerprint) if fingerprint.len() != 20 => 3,
+            _ => 4,
+        }
+    }
+
+    /// The public key algorithm of the signing key.
+    fn algorithm(&self) -> PublicKeyAlgorithm;
+
+    /// Sign a digest computed with `hash_algo`.
+    ///
+    /// RSA signers must place the digest in a DigestInfo structure before signing it; see
+    /// [`HashAlgorithm::digest_info`]. EdDSA signers sign the digest itself as the message.
+    ///
+    /// [`HashAlgorithm::digest_info`]: enum.HashAlgorithm.html#method.digest_info
+    fn sign(&self, hash_algo: HashAlgorithm, digest: &[u8]) -> Result<Signature, Error>;
+}
+
+/// Sign some data with a [`Signer`], returning a finished copy of a template signature.
+///
+/// The returned signature has the signer's public key algorithm, Issuer subpacket, Issuer
+/// Fingerprint subpacket for version 4 keys, the hash prefix and the signature MPIs filled in.
+/// All other fields, such as the signature type, hash algorithm and creation time, are taken from
+/// `template`.
+///
+/// [`Signer`]: trait.Signer.html
+pub fn sign<T: AsRef<[u8]>, S: Signer + ?Sized>(
+    template: &SignaturePacket,
+    data: T,
+    signer: &S,
+) -> Result<SignaturePacket, Error> {
+    let signature = prepare_signature(template, signer)?;
+
+    let digest = signature.signable_payload(data)?;
+    finish_signature(signature, &digest, signer)
+}
+
+/// Sign data read from a stream with a [`Signer`], returning a finished copy of a template
+/// signature.
+///
+/// This behaves like [`sign`], except that the data is hashed as it is read rather than being
+/// held in memory, so arbitrarily large inputs can be signed. Text document signatures are made
+/// over the data with its line endings converted to `<CR><LF>`.
+///
+/// [`Signer`]: trait.Signer.html
+/// [`sign`]: fn.sign.html
+pub fn sign_reader<R: Read, S: Signer + ?Sized>(
+    template: &SignaturePacket,
+    mut data: R,
+    signer: &S,
+) -> Result<SignaturePacket, Error> {
+    let signature = prepare_signature(template, signer)?;
+
+    let digest = {
+        let mut hasher = signature.hasher()?;
+        io::copy(&mut data, &mut hasher)?;
+        hasher.finish()?
+    };
+    finish_signature(signature, &digest, signer)
+}
+
+/// Certify a user ID on a key with a [`Signer`], returning a finished copy of a template
+/// signature.
+///
+/// The template's signature type must be one of the certification types, or a certification
+/// revocation. Self-signatures are made by passing a signer for `key` itself.
+///
+/// [`Signer`]: trait.Signer.html
+pub fn sign_certification<S: Signer + ?Sized>(
+    template: &SignaturePacket,
+    key: &Key,
+    user_id: &str,
+    signer: &S,
+) -> Result<SignaturePacket, Error> {
+    let signature = prepare_signature(template, signer)?;
+    let digest = signature.certification_payload(key, user_id)?;
+    finish_signature(signature, &digest, signer)
+}
+
+/// Bind a subkey to a primary key with a [`Signer`], returning a finished copy of a template
+/// signature.
+///
+/// The template's signature type must be a subkey binding or revocation, made with a signer for
+/// the primary key, or a primary key binding, made with a signer for the subkey.
+///
+/// [`Signer`]: trait.Signer.html
+pub fn sign_key_binding<S: Signer + ?Sized>(
+    template: &SignaturePacket,
+    primary: &Key,
+    subkey: &Key,
+    signer: &S,
+) -> Result<SignaturePacket, Error> {
+    let signature = prepare_signature(template, signer)?;
+    let digest = signature.key_binding_payload(primary, subkey)?;
+    finish_signature(signature, &digest, signer)
+}
+
+/// Sign a key directly with a [`Signer`], returning a finished copy of a template signature.
+///
+/// The template's signature type must be a direct-key signature or a key revocation.
+///
+/// [`Signer`]: trait.Signer.html
+pub fn sign_direct_key<S: Signer + ?Sized>(
+    template: &SignaturePacket,
+    key: &Key,
+    signer: &S,
+) -> Result<SignaturePacket, Error> {
+    let signature = prepare_signature(template, signer)?;
+    let digest = signature.direct_key_payload(key)?;
+    finish_signature(signature, &digest, signer)
+}
+
+/// Fill in the signature MPIs over a digest of the signed data.
+fn finish_signature<S: Signer + ?Sized>(
+    mut signature: SignaturePacket,
+    digest: &[u8],
+    signer: &S,
+) -> Result<SignaturePacket, Error> {
+    let contents = signer.sign(signature.hash_algo, digest)?;
+    signature.set_contents(contents)?;
+
+    Ok(signature)
+}
+
+/// Copy a template signature, filling in the fields that identify the signer.
+fn prepare_signature<S: Signer + ?Sized>(
+    template: &SignaturePacket,
+    signer: &S,
+) -> Result<SignaturePacket, Error> {
+    let mut signature = template.clone();
+    // Signatures are always made as version 4, even from a version 3 template.
+    signature.version = 4;
+    signature.pubkey_algo = signer.algorithm();
+    match signer.key_version() {
+        4 => signature.set_issuer_fingerprint(4, signer.fingerprint()?),
+        _ => signature.clear_issuer_fingerprint(),
+    }
+    signature.set_signer(signer.key_id()?);
+
+    Ok(signature)
+}
+
+/// A [`Signer`] backed by an unlocked secret [`Key`] held in memory.
+///
+/// [`Signer`]: trait.Signer.html
+/// [`Key`]: struct.Key.html
+#[derive(Clone, Debug)]
+pub struct KeySigner {
+    key: Key,
+}
+
+impl KeySigner {
+    /// Create a signer from a key with unencrypted secret key material.
+    pub fn new(key: Key) -> Result<KeySigner, Error> {
+        if key.is_locked() {
+            bail!(KeyError::Locked);
+        }
+
+        if key.key_material.private_to_bytes()?.is_empty() {
+            bail!(KeyError::MissingSecretKey);
+        }
+
+        Ok(KeySigner { key })
+    }
+
+    /// Retrieve the key used by this signer.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+}
+
+impl Signer for KeySigner {
+    fn key_id(&self) -> Result<u64, Error> {
+        self.key.id()
+    }
+
+    fn fingerprint(&self) -> Result<Vec<u8>, Error> {
+        self.key.fingerprint()
+    }
+
+    fn key_version(&self) -> u8 {
+        self.key.version()
+    }
+
+    fn algorithm(&self) -> PublicKeyAlgorithm {
+        self.key.pubkey_algorithm
+    }
+
+    fn sign(&self, hash_algo: HashAlgorithm, digest: &[u8]) -> Result<Signature, Error> {
+        match self.key.key_material {
+            KeyMaterial::Rsa(ref public, Some(ref private)) => {
+                sign_rsa(hash_algo, digest, public, private)
+            }
+            KeyMaterial::Dsa(ref public, Some(ref private)) => sign_dsa(digest, public, private),
+            KeyMaterial::Ecdsa(ref public, Some(ref private)) => {
+                sign_ecdsa(digest, public, private)
+            }
+            KeyMaterial::EdDsa(ref public, Some(ref private)) => {
+                sign_eddsa(digest, public, private)
+            }
+            KeyMaterial::Rsa(_, None)
+            | KeyMaterial::Dsa(_, None)
+            | KeyMaterial::Ecdsa(_, None)
+            | KeyMaterial::EdDsa(_, None) => bail!(KeyError::MissingSecretKey),
+            _ => bail!(SignerError::UnsupportedAlgorithm {
+                algorithm: format!("{:?}", self.key.pubkey_algorithm),
+            }),
+        }
+    }
+}
+
+fn sign_rsa(
+    hash_algo: HashAlgorithm,
+    digest: &[u8],
+    public: &RsaPublicKey,
+    private: &RsaPrivateKey,
+) -> Result<Signature, Error> {
+    let message = match emsa_pkcs1_v1_5(hash_algo, digest, public.n.bits().div_ceil(8))? {
+        Some(message) => BigUint::from_bytes_be(&message),
+        None => bail!(SignerError::KeyTooSmall),
+    };
+
+    Ok(Signature::Rsa(message.modpow(&private.d, &public.n)))
+}
+
+fn sign_dsa(
+    digest: &[u8],
+    public: &DsaPublicKey,
+    private: &DsaPrivateKey,
+) -> Result<Signature, Error> {
+    let z = truncate_digest(digest, public.q.bits());
+    let two = BigUint::one() + BigUint::one();
+
+    loop {
+        let k = random_below(&public.q);
+        let r = public.g.modpow(&k, &public.p) % &public.q;
+        if r.is_zero() {
+            continue;
+        }
+
+        // q is prime, so k^-1 mod q is k^(q-2) mod q.
+        let k_inv = k.modpow(&(&public.q - &two), &public.q);
+        let s = (k_inv * (&z + &private.0 * &r)) % &public.q;
+        if s.is_zero() {
+            continue;
+        }
+
+        return Ok(Signature::Dsa(r, s));
+    }
+}
+
+macro_rules! sign_ecdsa {
+    ($curve:ident, $secret:expr, $digest:expr) => {{
+        use $curve::ecdsa::signature::hazmat::PrehashSigner;
+
+        let key = $curve::ecdsa::SigningKey::from_slice($secret)
+            .map_err(|_| KeyError::MalformedMpi)?;
+        let signature: $curve::ecdsa::Signature = key.sign_prehash($digest)
+            .map_err(|_| SignerError::KeyTooSmall)?;
+
+        signature.to_bytes().to_vec()
+    }};
+}
+
+fn sign_ecdsa(
+    digest: &[u8],
+    public: &EcdsaPublicKey,
+    private: &EcdsaPrivateKey,
+) -> Result<Signature, Error> {
+    let field_bytes = match public.curve.field_bytes() {
+        Some(field_bytes) => field_bytes,
+        None => bail!(SignerError::UnsupportedAlgorithm {
+            algorithm: format!("{:?}", public.curve),
+        }),
+    };
+
+    let secret = match fixed_bytes(&private.0, field_bytes) {
+        Some(secret) => secret,
+        None => bail!(KeyError::MalformedMpi),
+    };
+
+    let signature = match public.curve {
+        Curve::NistP256 => sign_ecdsa!(p256, &secret, digest),
+        Curve::NistP384 => sign_ecdsa!(p384, &secret, digest),
+        Curve::NistP521 => sign_ecdsa!(p521, &secret, digest),
+        ref curve => bail!(SignerError::UnsupportedAlgorithm {
+            algorithm: format!("{:?}", curve),
+        }),
+    };
+
+    let (r, s) = signature.split_at(field_bytes);
+    Ok(Signature::Ecdsa(BigUint::from_bytes_be(r), BigUint::from_bytes_be(s)))
+}
+
+fn sign_eddsa(
+    digest: &[u8],
+    public: &EdDsaPublicKey,
+    private: &EdDsaPrivateKey,
+) -> Result<Signature, Error> {
+    if public.curve != Curve::Ed25519 {
+        bail!(SignerError::UnsupportedAlgorithm {
+            algorithm: format!("{:?}", public.curve),
+        });
+    }
+
+    let mut secret = [0u8; 32];
+    match fixed_bytes(&private.0, 32) {
+        Some(bytes) => secret.copy_from_slice(&bytes),
+        None => bail!(KeyError::MalformedMpi),
+    }
+
+    // The message signed by an OpenPGP EdDSA signature is the digest itself.
+    let signature = ed25519_dalek::SigningKey::from_bytes(&secret).sign(digest).to_bytes();
+    let (r, s) = signature.split_at(32);
+
+    Ok(Signature::EdDsa(BigUint::from_bytes_be(r), BigUint::from_bytes_be(s)))
+}
+
+/// Error type for signing failures.
+#[derive(Debug, Fail)]
+pub enum SignerError {
+    #[fail(display = "Unsupported signing algorithm: {}", algorithm)]
+    UnsupportedAlgorithm { algorithm: String },
+    #[fail(display = "Key is too small to sign this digest")]
+    KeyTooSmall,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet::Packet;
+    use signature::SignatureType;
+
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/message.txt");
+    const TEXT: &[u8] = include_bytes!("../tests/data/text.txt");
+    const RSA_SECRET_KEY: &[u8] = include_bytes!("../tests/data/rsa.sec");
+    const DSA_SECRET_KEY: &[u8] = include_bytes!("../tests/data/dsa.sec");
+    const ED_SECRET_KEY: &[u8] = include_bytes!("../tests/data/ed.sec");
+    const P256_SECRET_KEY: &[u8] = include_bytes!("../tests/data/enistp256.sec");
+    const P384_SECRET_KEY: &[u8] = include_bytes!("../tests/data/nistp384.sec");
+    const P521_SECRET_KEY: &[u8] = include_bytes!("../tests/data/nistp521.sec");
+    const PROTECTED_SECRET_KEY: &[u8] = include_bytes!("../tests/data/protected.sec");
+
+    fn secret_key(bytes: &[u8]) -> Key {
+        match Packet::from_bytes(bytes).unwrap().0 {
+            Packet::SecretKey(key) => key,
+            p => panic!("expected a secret key, found {:?}", p),
+        }
+    }
+
+    fn template() -> SignaturePacket {
+        SignaturePacket::new(
+            SignatureType::BinaryDocument,
+            PublicKeyAlgorithm::Rsa,
+            HashAlgorithm::Sha512,
+        ).unwrap()
+    }
+
+    // A signer that only exposes a KeySigner through the Signer trait, as a remote signer would.
+    struct RemoteSigner {
+        signer: KeySigner,
+        key_version: u8,
+    }
+
+    impl Signer for RemoteSigner {
+        fn key_id(&self) -> Result<u64, Error> {
+            self.signer.key_id()
+        }
+
+        fn fingerprint(&self) -> Result<Vec<u8>, Error> {
+            self.signer.fingerprint()
+        }
+
+        fn key_version(&self) -> u8 {
+            self.key_version
+        }
+
+        fn algorithm(&self) -> PublicKeyAlgorithm {
+            self.signer.algorithm()
+        }
+
+        fn sign(&self, hash_algo: HashAlgorithm, digest: &[u8]) -> Result<Signature, Error> {
+            self.signer.sign(hash_algo, digest)
+        }
+    }
+
+    #[test]
+    fn sign_with_each_algorithm() {
+        for &secret in &[
+            RSA_SECRET_KEY,
+            DSA_SECRET_KEY,
+            ED_SECRET_KEY,
+            P256_SECRET_KEY,
+            P384_SECRET_KEY,
+            P521_SECRET_KEY,
+        ] {
+            let key = secret_key(secret);
+            let signer = KeySigner::new(key.clone()).unwrap();
+
+            let signature = sign(&template(), MESSAGE, &signer).unwrap();
+            assert_eq!(signature.pubkey_algo, key.pubkey_algorithm);
+            assert_eq!(signature.sig_type, SignatureType::BinaryDocument);
+            assert_eq!(signature.hash_algo, HashAlgorithm::Sha512);
+            assert_eq!(signature.signer(), Some(key.id().unwrap()));
+            assert_eq!(signature.issuer_fingerprint(), Some(key.fingerprint().unwrap()));
+
+            // Check the signature as it would be read back in.
+            let bytes = Packet::Signature(signature).to_bytes().unwrap();
+            let signature = match Packet::from_bytes(&bytes).unwrap().0 {
+                Packet::Signature(signature) => signature,
+                p => panic!("expected a signature, found {:?}", p),
+            };
+            signature.verify(MESSAGE, &key).unwrap();
+            assert!(signature.verify(b"Hello, world?\n", &key).is_err());
+        }
+    }
+
+    #[test]
+    fn sign_with_remote_signer() {
+        let signer = RemoteSigner {
+            signer: KeySigner::new(secret_key(ED_SECRET_KEY)).unwrap(),
+            key_version: 4,
+        };
+        let signature = sign(&template(), MESSAGE, &signer).unwrap();
+        signature.verify(MESSAGE, signer.signer.key()).unwrap();
+
+        // Issuer Fingerprint subpackets are only defined for version 4 keys.
+        let signer = RemoteSigner {
+            key_version: 3,
+            ..signer
+        };
+        let signature = sign(&signature, MESSAGE, &signer).unwrap();
+        assert_eq!(signature.issuer_fingerprint(), None);
+        assert_eq!(signature.signer(), Some(signer.key_id().unwrap()));
+        signature.verify(MESSAGE, signer.signer.key()).unwrap();
+    }
+
+    #[test]
+    fn locked_key() {
+        let err = KeySigner::new(secret_key(PROTECTED_SECRET_KEY)).unwrap_err();
+        assert_eq!(err.to_string(), KeyError::Locked.to_string());
+    }
+
+    #[test]
+    fn sign_reader_matches_sign() {
+        // Ed25519 signatures are deterministic, so both ways of hashing must give the same bytes.
+        let signer = KeySigner::new(secret_key(ED_SECRET_KEY)).unwrap();
+        let signature = sign(&template(), MESSAGE, &signer).unwrap();
+        let streamed = sign_reader(&template(), MESSAGE, &signer).unwrap();
+
+        assert_eq!(streamed.to_bytes().unwrap(), signature.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn sign_reader_text() {
+        let signer = KeySigner::new(secret_key(ED_SECRET_KEY)).unwrap();
+        let mut template = template();
+        template.sig_type = SignatureType::TextDocument;
+
+        let crlf = String::from_utf8(TEXT.to_vec()).unwrap().replace("\n", "\r\n");
+        let signature = sign_reader(&template, crlf.as_bytes(), &signer).unwrap();
+        signature.verify(TEXT, signer.key()).unwrap();
+        signature.verify(&crlf, signer.key()).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+use failure::Error;
+use nom::{be_u8, rest};
+use nom::{ErrorKind, IResult};
+use nom::Err as NomErr;
+use rand;
+use rand::RngCore;
+
+use s2k::{StringToKey, s2k};
+use seipd::DecryptionError;
+use symmetric::Cfb;
+use types::*;
+
+named!(
+    skesk<SymmetricKeySessionKeyPacket>,
+    do_parse!(
+        version: verify!(be_u8, |version| version == 4) >>
+        sym_algo: map!(be_u8, SymmetricKeyAlgorithm::from) >>
+        s2k: s2k >>
+        encrypted_key: rest >>
+        (SymmetricKeySessionKeyPacket {
+            version,
+            sym_algo,
+            s2k,
+            encrypted_key: if encrypted_key.is_empty() {
+                None
+            } else {
+                Some(Vec::from(encrypted_key))
+            },
+        })
+    )
+);
+
+/// The session key of an encrypted message: the symmetric algorithm the message is encrypted with,
+/// and the key itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionKey {
+    pub algo: SymmetricKeyAlgorithm,
+    pub key: Vec<u8>,
+}
+
+impl SessionKey {
+    /// Generate a random session key for a symmetric algorithm.
+    pub fn random(algo: SymmetricKeyAlgorithm) -> SessionKey {
+        let mut key = vec![0u8; algo.key_bytes()];
+        rand::thread_rng().fill_bytes(&mut key);
+
+        SessionKey { algo, key }
+    }
+
+    /// Decode a session key from its algorithm and key followed by the two-octet checksum of the
+    /// key, as decrypted from a public-key encrypted session key.
+    pub fn from_checksummed_bytes(bytes: &[u8]) -> Result<SessionKey, Error> {
+        if bytes.len() < 3 {
+            bail!(DecryptionError::InvalidSessionKey);
+        }
+
+        let (key, expected) = bytes.split_at(bytes.len() - 2);
+        let (algo, key) = (SymmetricKeyAlgorithm::from(key[0]), &key[1..]);
+        if algo.key_bytes() == 0 || key.len() != algo.key_bytes() || checksum(key) != expected {
+            bail!(DecryptionError::InvalidSessionKey);
+        }
+
+        Ok(SessionKey {
+            algo,
+            key: Vec::from(key),
+        })
+    }
+
+    /// The algorithm and key followed by the two-octet checksum of the key, as encrypted to
+    /// public keys.
+    pub(crate) fn to_checksummed_bytes(&self) -> Vec<u8> {
+        let mut out = vec![self.algo.into()];
+        out.extend(&self.key);
+        out.extend(&checksum(&self.key));
+
+        out
+    }
+}
+
+/// The sum of the key's octets modulo 65536.
+fn checksum(key: &[u8]) -> [u8; 2] {
+    key.iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(u16::from(*byte)))
+        .to_be_bytes()
+}
+
+/// A Symmetric-Key Encrypted Session Key packet as specified in [RFC4880 &sect;5.3], used by
+/// messages encrypted with a passphrase, such as those made by `gpg --symmetric`.
+///
+/// The key derived from the passphrase with the [`StringToKey`] specifier is either used directly
+/// as the session key, or used to decrypt the `encrypted_key`, which holds the actual session key
+/// along with its algorithm.
+///
+/// [RFC4880 &sect;5.3]: https://tools.ietf.org/html/rfc4880#section-5.3
+/// [`StringToKey`]: enum.StringToKey.html
+#[derive(Clone, Debug)]
+pub struct SymmetricKeySessionKeyPacket {
+    pub version: u8,
+    pub sym_algo: SymmetricKeyAlgorithm,
+    pub s2k: StringToKey,
+    pub encrypted_key: Option<Vec<u8>>,
+}
+
+impl SymmetricKeySessionKeyPacket {
+    /// Read in a symmetric-key encrypted session key from some bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SymmetricKeySessionKeyPacket, Error> {
+        match skesk(bytes) {
+            IResult::Done(_, skesk) => Ok(skesk),
+            IResult::Error(NomErr::Code(ErrorKind::Custom(e))) => {
+                let e = NomError::from(e);
+
+                bail!(SymmetricKeySessionKeyError::InvalidFormat {
+                    reason: format!("{:?}", e),
+                })
+            }
+            IResult::Error(e) => bail!(SymmetricKeySessionKeyError::InvalidFormat {
+                reason: format!("{}", e),
+            }),
+            IResult::Incomplete(i) => bail!(SymmetricKeySessionKeyError::InvalidFormat {
+                reason: format!("{:?}", i),
+            }),
+        }
+    }
+
+    /// Create a symmetric-key encrypted session key holding `session_key`, encrypted with a key
+    /// derived from a passphrase.
+    pub fn new<T: AsRef<[u8]>>(
+        session_key: &SessionKey,
+        passphrase: T,
+        s2k: StringToKey,
+    ) -> Result<SymmetricKeySessionKeyPacket, Error> {
+        let key = s2k.derive_key(passphrase, session_key.algo.key_bytes())?;
+
+        let iv = vec![0u8; session_key.algo.block_bytes()];
+        let mut encrypted_key = vec![session_key.algo.into()];
+        encrypted_key.extend(&session_key.key);
+        Cfb::new(session_key.algo, &key, &iv)?.encrypt(&mut encrypted_key);
+
+        Ok(SymmetricKeySessionKeyPacket {
+            version: 4,
+            sym_algo: session_key.algo,
+            s2k,
+            encrypted_key: Some(encrypted_key),
+        })
+    }
+
+    /// Derive the session key from a passphrase.
+    ///
+    /// When there is no encrypted session key, a wrong passphrase can't be detected here, and
+    /// only shows up once the message fails to decrypt.
+    pub fn session_key<T: AsRef<[u8]>>(&self, passphrase: T) -> Result<SessionKey, Error> {
+        let key = self.s2k.derive_key(passphrase, self.sym_algo.key_bytes())?;
+
+        let encrypted_key = match self.encrypted_key {
+            Some(ref encrypted_key) => encrypted_key,
+            None => {
+                return Ok(SessionKey {
+                    algo: self.sym_algo,
+                    key,
+                })
+            }
+        };
+
+        // The session key is encrypted in CFB mode with an IV of all zeros.
+        let iv = vec![0u8; self.sym_algo.block_bytes()];
+        let mut plaintext = encrypted_key.clone();
+        Cfb::new(self.sym_algo, &key, &iv)?.decrypt(&mut plaintext);
+
+        let (algo, key) = match plaintext.split_first() {
+            Some((&algo, key)) => (SymmetricKeyAlgorithm::from(algo), key),
+            None => return Err(SymmetricKeySessionKeyError::WrongPassphrase.into()),
+        };
+        if algo.key_bytes() == 0 || key.len() != algo.key_bytes() {
+            return Err(SymmetricKeySessionKeyError::WrongPassphrase.into());
+        }
+
+        Ok(SessionKey {
+            algo,
+            key: Vec::from(key),
+        })
+    }
+
+    /// Serialize this symmetric-key encrypted session key to bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+
+        out.push(self.version);
+        out.push(self.sym_algo.into());
+        out.extend(&self.s2k.to_bytes());
+        if let Some(ref encrypted_key) = self.encrypted_key {
+            out.extend(encrypted_key);
+        }
+
+        Ok(out)
+    }
+}
+
+/// Error type for [`SymmetricKeySessionKeyPacket`]-level errors.
+///
+/// [`SymmetricKeySessionKeyPacket`]: struct.SymmetricKeySessionKeyPacket.html
+#[derive(Debug, Fail)]
+pub enum SymmetricKeySessionKeyError {
+    #[fail(display = "Invalid symmetric-key encrypted session key format: {}", reason)]
+    InvalidFormat { reason: String },
+    #[fail(display = "Wrong passphrase for symmetric-key encrypted session key")]
+    WrongPassphrase,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::from_hex;
+
+    const PASSPHRASE: &str = "password";
+    const SYMMETRIC_MESSAGE: &[u8] = include_bytes!("../tests/data/symmetric.gpg");
+    const MIXED_MESSAGE: &[u8] = include_bytes!("../tests/data/rsa.symmetric.gpg");
+
+    // The body of a session key packet with a one octet old-format length, at `offset` in a
+    // GnuPG message.
+    fn body(message: &[u8], offset: usize) -> &[u8] {
+        assert_eq!(message[offset], 0x8C);
+        &message[offset + 2..offset + 2 + usize::from(message[offset + 1])]
+    }
+
+    #[test]
+    fn derived_session_key() {
+        // Made with `gpg --symmetric`; the session key is the key derived from the passphrase.
+        let body = body(SYMMETRIC_MESSAGE, 0);
+        let skesk = SymmetricKeySessionKeyPacket::from_bytes(body).unwrap();
+
+        assert_eq!(skesk.sym_algo, SymmetricKeyAlgorithm::Aes256);
+        assert!(skesk.encrypted_key.is_none());
+        match skesk.s2k {
+            StringToKey::IteratedSalted(HashAlgorithm::Sha1, _, 65_011_712) => {}
+            ref s2k => panic!("unexpected S2K specifier {:?}", s2k),
+        }
+        assert_eq!(skesk.to_bytes().unwrap(), body);
+
+        let expected = from_hex("00B4628F54A9B1344B0AB65BD7204CE49A2FBA6C0C1FF117A64E5DDBD33B9F3A");
+        let session_key = skesk.session_key(PASSPHRASE).unwrap();
+        assert_eq!(session_key.algo, SymmetricKeyAlgorithm::Aes256);
+        assert_eq!(session_key.key, expected);
+    }
+
+    #[test]
+    fn encrypted_session_key() {
+        // Made with `gpg --symmetric --encrypt`, so the passphrase encrypts the session key shared
+        // with the public key recipient.
+        let body = body(MIXED_MESSAGE, 271);
+        let skesk = SymmetricKeySessionKeyPacket::from_bytes(body).unwrap();
+
+        assert_eq!(skesk.encrypted_key.as_ref().map(Vec::len), Some(33));
+        assert_eq!(skesk.to_bytes().unwrap(), body);
+
+        let expected = from_hex("10218C61AAA60C9A2248F7B84EAB812C88233D5973167717CBE87B8BA5189682");
+        let session_key = skesk.session_key(PASSPHRASE).unwrap();
+        assert_eq!(session_key.algo, SymmetricKeyAlgorithm::Aes256);
+        assert_eq!(session_key.key, expected);
+
+        let err = skesk.session_key("wrong").unwrap_err();
+        match err.downcast::<SymmetricKeySessionKeyError>() {
+            Ok(SymmetricKeySessionKeyError::WrongPassphrase) => {}
+            err => panic!("expected a wrong passphrase, found {:?}", err),
+        }
+    }
+
+    #[test]
+    fn new_round_trip() {
+        let session_key = SessionKey::random(SymmetricKeyAlgorithm::Aes128);
+        let s2k = StringToKey::iterated(HashAlgorithm::Sha256, 65536);
+        let skesk = SymmetricKeySessionKeyPacket::new(&session_key, PASSPHRASE, s2k).unwrap();
+
+        let bytes = skesk.to_bytes().unwrap();
+        let skesk = SymmetricKeySessionKeyPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(skesk.session_key(PASSPHRASE).unwrap(), session_key);
+    }
+
+    #[test]
+    fn checksummed_session_key() {
+        let session_key = SessionKey {
+            algo: SymmetricKeyAlgorithm::Aes128,
+            key: from_hex("000102030405060708090A0B0C0D0E0F"),
+        };
+
+        let mut bytes = session_key.to_checksummed_bytes();
+        assert_eq!(bytes[0], 7);
+        assert_eq!(bytes[17..], [0x00, 0x78]);
+        assert_eq!(SessionKey::from_checksummed_bytes(&bytes).unwrap(), session_key);
+
+        bytes[18] ^= 0x01;
+        let err = SessionKey::from_checksummed_bytes(&bytes).unwrap_err();
+        assert_eq!(err.to_string(), DecryptionError::InvalidSessionKey.to_string());
+
+        let err = SessionKey::from_checksummed_bytes(&bytes[..10]).unwrap_err();
+        assert_eq!(err.to_string(), DecryptionError::InvalidSessionKey.to_string());
+    }
+
+    #[test]
+    fn reject_unknown_version() {
+        let mut body = body(SYMMETRIC_MESSAGE, 0).to_vec();
+        body[0] = 5;
+        assert!(SymmetricKeySessionKeyPacket::from_bytes(&body).is_err());
+    }
+}
@@ -0,0 +1,436 @@
+use aes::{Aes128, Aes192, Aes256};
+use blowfish::Blowfish;
+use cast5::Cast5;
+use cipher::{Block, BlockDecrypt, BlockEncrypt, KeyInit};
+use des::TdesEde3;
+use failure::Error;
+use idea::Idea;
+use twofish::Twofish;
+
+use types::*;
+
+/// A block cipher keyed for one of the supported [`SymmetricKeyAlgorithm`]s.
+///
+/// OpenPGP's modes are all variants of CFB, which only uses the forward (encryption) direction of
+/// the cipher; the inverse direction is only needed to unwrap ECDH session keys.
+///
+/// [`SymmetricKeyAlgorithm`]: enum.SymmetricKeyAlgorithm.html
+pub(crate) enum BlockCipher {
+    Idea(Idea),
+    TripleDes(TdesEde3),
+    Cast5(Cast5),
+    // Blowfish's key schedule is several kilobytes, so keep it out of line.
+    Blowfish(Box<Blowfish>),
+    Aes128(Aes128),
+    Aes192(Aes192),
+    Aes256(Aes256),
+    Twofish(Twofish),
+}
+
+fn encrypt_block<C: BlockEncrypt>(cipher: &C, block: &mut [u8]) {
+    let mut buf = Block::<C>::default();
+    buf.copy_from_slice(block);
+    cipher.encrypt_block(&mut buf);
+    block.copy_from_slice(&buf);
+}
+
+fn decrypt_block<C: BlockDecrypt>(cipher: &C, block: &mut [u8]) {
+    let mut buf = Block::<C>::default();
+    buf.copy_from_slice(block);
+    cipher.decrypt_block(&mut buf);
+    block.copy_from_slice(&buf);
+}
+
+impl BlockCipher {
+    pub(crate) fn new(algo: SymmetricKeyAlgorithm, key: &[u8]) -> Result<BlockCipher, Error> {
+        let cipher = match algo {
+            SymmetricKeyAlgorithm::Idea => Idea::new_from_slice(key).map(BlockCipher::Idea),
+            SymmetricKeyAlgorithm::TripleDes => {
+                TdesEde3::new_from_slice(key).map(BlockCipher::TripleDes)
+            }
+            SymmetricKeyAlgorithm::Cast5 => Cast5::new_from_slice(key).map(BlockCipher::Cast5),
+            SymmetricKeyAlgorithm::Blowfish => {
+                Blowfish::new_from_slice(key).map(|c| BlockCipher::Blowfish(Box::new(c)))
+            }
+            SymmetricKeyAlgorithm::Aes128 => Aes128::new_from_slice(key).map(BlockCipher::Aes128),
+            SymmetricKeyAlgorithm::Aes192 => Aes192::new_from_slice(key).map(BlockCipher::Aes192),
+            SymmetricKeyAlgorithm::Aes256 => Aes256::new_from_slice(key).map(BlockCipher::Aes256),
+            SymmetricKeyAlgorithm::Twofish => {
+                Twofish::new_from_slice(key).map(BlockCipher::Twofish)
+            }
+            SymmetricKeyAlgorithm::Plaintext
+            | SymmetricKeyAlgorithm::Reserved
+            | SymmetricKeyAlgorithm::Unknown => bail!(AlgorithmError::SymmetricKeyAlgorithmError),
+        };
+
+        let cipher = cipher.map_err(|_| SymmetricError::InvalidKeyLength {
+            expected: algo.key_bytes(),
+            actual: key.len(),
+        })?;
+
+        Ok(cipher)
+    }
+
+    pub(crate) fn block_bytes(&self) -> usize {
+        match *self {
+            BlockCipher::Idea(_)
+            | BlockCipher::TripleDes(_)
+            | BlockCipher::Cast5(_)
+            | BlockCipher::Blowfish(_) => 8,
+            BlockCipher::Aes128(_)
+            | BlockCipher::Aes192(_)
+            | BlockCipher::Aes256(_)
+            | BlockCipher::Twofish(_) => 16,
+        }
+    }
+
+    pub(crate) fn encrypt_block(&self, block: &mut [u8]) {
+        match *self {
+            BlockCipher::Idea(ref c) => encrypt_block(c, block),
+            BlockCipher::TripleDes(ref c) => encrypt_block(c, block),
+            BlockCipher::Cast5(ref c) => encrypt_block(c, block),
+            BlockCipher::Blowfish(ref c) => encrypt_block(&**c, block),
+            BlockCipher::Aes128(ref c) => encrypt_block(c, block),
+            BlockCipher::Aes192(ref c) => encrypt_block(c, block),
+            BlockCipher::Aes256(ref c) => encrypt_block(c, block),
+            BlockCipher::Twofish(ref c) => encrypt_block(c, block),
+        }
+    }
+
+    pub(crate) fn decrypt_block(&self, block: &mut [u8]) {
+        match *self {
+            BlockCipher::Idea(ref c) => decrypt_block(c, block),
+            BlockCipher::TripleDes(ref c) => decrypt_block(c, block),
+            BlockCipher::Cast5(ref c) => decrypt_block(c, block),
+            BlockCipher::Blowfish(ref c) => decrypt_block(&**c, block),
+            BlockCipher::Aes128(ref c) => decrypt_block(c, block),
+            BlockCipher::Aes192(ref c) => decrypt_block(c, block),
+            BlockCipher::Aes256(ref c) => decrypt_block(c, block),
+            BlockCipher::Twofish(ref c) => decrypt_block(c, block),
+        }
+    }
+}
+
+/// Cipher feedback mode, as specified in [RFC4880 &sect;13.9] (without the resynchronization step
+/// used by Symmetrically Encrypted Data packets).
+///
+/// Data may be encrypted or decrypted in pieces of any size; the state carries over between
+/// calls.
+///
+/// [RFC4880 &sect;13.9]: https://tools.ietf.org/html/rfc4880#section-13.9
+pub(crate) struct Cfb {
+    cipher: BlockCipher,
+    keystream: Vec<u8>,
+    feedback: Vec<u8>,
+    pos: usize,
+}
+
+impl Cfb {
+    pub(crate) fn new(algo: SymmetricKeyAlgorithm, key: &[u8], iv: &[u8]) -> Result<Cfb, Error> {
+        let cipher = BlockCipher::new(algo, key)?;
+        if iv.len() != cipher.block_bytes() {
+            bail!(SymmetricError::InvalidIvLength {
+                expected: cipher.block_bytes(),
+                actual: iv.len(),
+            });
+        }
+
+        let mut cfb = Cfb {
+            cipher,
+            keystream: Vec::from(iv),
+            feedback: Vec::from(iv),
+            pos: 0,
+        };
+        cfb.next_block();
+
+        Ok(cfb)
+    }
+
+    fn next_block(&mut self) {
+        self.keystream.copy_from_slice(&self.feedback);
+        self.cipher.encrypt_block(&mut self.keystream);
+        self.pos = 0;
+    }
+
+    pub(crate) fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.pos == self.keystream.len() {
+                self.next_block();
+            }
+
+            *byte ^= self.keystream[self.pos];
+            self.feedback[self.pos] = *byte;
+            self.pos += 1;
+        }
+    }
+
+    pub(crate) fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.pos == self.keystream.len() {
+                self.next_block();
+            }
+
+            self.feedback[self.pos] = *byte;
+            *byte ^= self.keystream[self.pos];
+            self.pos += 1;
+        }
+    }
+}
+
+// The initial value of the AES key wrap algorithm.
+const KEY_WRAP_IV: [u8; 8] = [0xA6; 8];
+
+/// Wrap a key with a key-encryption key, using the AES key wrap algorithm from [RFC3394], as
+/// ECDH session keys are. The key must be a multiple of 8 octets long.
+///
+/// [RFC3394]: https://tools.ietf.org/html/rfc3394
+pub(crate) fn key_wrap(
+    algo: SymmetricKeyAlgorithm,
+    kek: &[u8],
+    key: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let cipher = BlockCipher::new(algo, kek)?;
+    if cipher.block_bytes() != 16 || key.is_empty() || !key.len().is_multiple_of(8) {
+        bail!(SymmetricError::InvalidKeyWrap);
+    }
+
+    let mut a = KEY_WRAP_IV;
+    let mut r: Vec<[u8; 8]> = key
+        .chunks(8)
+        .map(|chunk| {
+            let mut block = [0u8; 8];
+            block.copy_from_slice(chunk);
+            block
+        })
+        .collect();
+
+    let n = r.len();
+    for j in 0..6 {
+        for (i, r) in r.iter_mut().enumerate() {
+            let mut b = [0u8; 16];
+            b[..8].copy_from_slice(&a);
+            b[8..].copy_from_slice(r);
+            cipher.encrypt_block(&mut b);
+
+            let t = (n * j + i + 1) as u64;
+            a.copy_from_slice(&b[..8]);
+            for (a, t) in a.iter_mut().zip(&t.to_be_bytes()) {
+                *a ^= t;
+            }
+            r.copy_from_slice(&b[8..]);
+        }
+    }
+
+    let mut out = Vec::from(&a[..]);
+    for block in r {
+        out.extend(&block);
+    }
+
+    Ok(out)
+}
+
+/// Unwrap a key wrapped with [`key_wrap`], checking its integrity.
+///
+/// [`key_wrap`]: fn.key_wrap.html
+pub(crate) fn key_unwrap(
+    algo: SymmetricKeyAlgorithm,
+    kek: &[u8],
+    wrapped: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let cipher = BlockCipher::new(algo, kek)?;
+    if cipher.block_bytes() != 16 || wrapped.len() < 16 || !wrapped.len().is_multiple_of(8) {
+        bail!(SymmetricError::InvalidKeyWrap);
+    }
+
+    let mut a = [0u8; 8];
+    a.copy_from_slice(&wrapped[..8]);
+    let mut r: Vec<[u8; 8]> = wrapped[8..]
+        .chunks(8)
+        .map(|chunk| {
+            let mut block = [0u8; 8];
+            block.copy_from_slice(chunk);
+            block
+        })
+        .collect();
+
+    let n = r.len();
+    for j in (0..6).rev() {
+        for (i, r) in r.iter_mut().enumerate().rev() {
+            let t = (n * j + i + 1) as u64;
+            for (a, t) in a.iter_mut().zip(&t.to_be_bytes()) {
+                *a ^= t;
+            }
+
+            let mut b = [0u8; 16];
+            b[..8].copy_from_slice(&a);
+            b[8..].copy_from_slice(r);
+            cipher.decrypt_block(&mut b);
+
+            a.copy_from_slice(&b[..8]);
+            r.copy_from_slice(&b[8..]);
+        }
+    }
+
+    if a != KEY_WRAP_IV {
+        bail!(SymmetricError::KeyUnwrapFailed);
+    }
+
+    let mut out = Vec::new();
+    for block in r {
+        out.extend(&block);
+    }
+
+    Ok(out)
+}
+
+/// Error type for symmetric encryption errors.
+#[derive(Debug, Fail)]
+pub enum SymmetricError {
+    #[fail(display = "Invalid key length: expected {}, got {}", expected, actual)]
+    InvalidKeyLength { expected: usize, actual: usize },
+    #[fail(display = "Invalid IV length: expected {}, got {}", expected, actual)]
+    InvalidIvLength { expected: usize, actual: usize },
+    #[fail(display = "Key wrap needs a 128-bit block cipher and a multiple of 8 key octets")]
+    InvalidKeyWrap,
+    #[fail(display = "Unwrapped key failed its integrity check")]
+    KeyUnwrapFailed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::from_hex;
+
+    // The CFB128-AES128 vectors from NIST SP 800-38A, Appendix F.3.13.
+    const CFB_KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const CFB_IV: &str = "000102030405060708090a0b0c0d0e0f";
+    const CFB_PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51\
+                                 30c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710";
+    const CFB_CIPHERTEXT: &str = "3b3fd92eb72dad20333449f8e83cfb4ac8a64537a0b3a93fcde3cdad9f1ce58b\
+                                  26751f67a3cbb140b1808cf187a4f4dfc04b05357c5d1c0eeac4c66f9ff7f2e6";
+
+    // The test vectors from RFC 3394, Section 4: the key-encryption key, the key data and the
+    // wrapped key.
+    const KEY_WRAP_VECTORS: &[(SymmetricKeyAlgorithm, &str, &str, &str)] = &[
+        (
+            SymmetricKeyAlgorithm::Aes128,
+            "000102030405060708090A0B0C0D0E0F",
+            "00112233445566778899AABBCCDDEEFF",
+            "1FA68B0A8112B447AEF34BD8FB5A7B829D3E862371D2CFE5",
+        ),
+        (
+            SymmetricKeyAlgorithm::Aes192,
+            "000102030405060708090A0B0C0D0E0F1011121314151617",
+            "00112233445566778899AABBCCDDEEFF",
+            "96778B25AE6CA435F92B5B97C050AED2468AB8A17AD84E5D",
+        ),
+        (
+            SymmetricKeyAlgorithm::Aes256,
+            "000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F",
+            "00112233445566778899AABBCCDDEEFF",
+            "64E8C3F9CE0F5BA263E9777905818A2A93C8191E7D6E8AE7",
+        ),
+        (
+            SymmetricKeyAlgorithm::Aes192,
+            "000102030405060708090A0B0C0D0E0F1011121314151617",
+            "00112233445566778899AABBCCDDEEFF0001020304050607",
+            "031D33264E15D33268F24EC260743EDCE1C6C7DDEE725A936BA814915C6762D2",
+        ),
+        (
+            SymmetricKeyAlgorithm::Aes256,
+            "000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F",
+            "00112233445566778899AABBCCDDEEFF0001020304050607",
+            "A8F9BC1612C68B3FF6E6F4FBE30E71E4769C8B80A32CB8958CD5D17D6B254DA1",
+        ),
+        (
+            SymmetricKeyAlgorithm::Aes256,
+            "000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F",
+            "00112233445566778899AABBCCDDEEFF000102030405060708090A0B0C0D0E0F",
+            "28C9F404C4B810F4CBCCB35CFB87F8263F5786E2D80ED326CBC7F0E71A99F43BFB988B9B7A02DD21",
+        ),
+    ];
+
+    fn cfb() -> Cfb {
+        Cfb::new(SymmetricKeyAlgorithm::Aes128, &from_hex(CFB_KEY), &from_hex(CFB_IV)).unwrap()
+    }
+
+    #[test]
+    fn cfb_encrypt() {
+        let mut data = from_hex(CFB_PLAINTEXT);
+        cfb().encrypt(&mut data);
+        assert_eq!(data, from_hex(CFB_CIPHERTEXT));
+    }
+
+    #[test]
+    fn cfb_decrypt_in_pieces() {
+        let mut data = from_hex(CFB_CIPHERTEXT);
+
+        let mut cfb = cfb();
+        let (first, rest) = data.split_at_mut(5);
+        let (second, third) = rest.split_at_mut(27);
+        cfb.decrypt(first);
+        cfb.decrypt(second);
+        cfb.decrypt(third);
+
+        assert_eq!(data, from_hex(CFB_PLAINTEXT));
+    }
+
+    #[test]
+    fn cfb_invalid_iv_length() {
+        let key = from_hex(CFB_KEY);
+        let err = Cfb::new(SymmetricKeyAlgorithm::Aes128, &key, &[0u8; 8]).err().unwrap();
+
+        let expected = SymmetricError::InvalidIvLength {
+            expected: 16,
+            actual: 8,
+        };
+        assert_eq!(err.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn key_wrap_vectors() {
+        for &(algo, kek, key, wrapped) in KEY_WRAP_VECTORS {
+            let result = key_wrap(algo, &from_hex(kek), &from_hex(key)).unwrap();
+            assert_eq!(result, from_hex(wrapped));
+        }
+    }
+
+    #[test]
+    fn key_wrap_invalid_input() {
+        let kek = from_hex(KEY_WRAP_VECTORS[0].1);
+        for key in &[&[][..], &[0u8; 12][..]] {
+            let err = key_wrap(SymmetricKeyAlgorithm::Aes128, &kek, key).unwrap_err();
+            assert_eq!(err.to_string(), SymmetricError::InvalidKeyWrap.to_string());
+        }
+
+        // Key wrap is only defined for ciphers with 128-bit blocks.
+        let err = key_wrap(SymmetricKeyAlgorithm::Cast5, &kek, &[0u8; 16]).unwrap_err();
+        assert_eq!(err.to_string(), SymmetricError::InvalidKeyWrap.to_string());
+    }
+
+    #[test]
+    fn key_unwrap_vectors() {
+        for &(algo, kek, key, wrapped) in KEY_WRAP_VECTORS {
+            let result = key_unwrap(algo, &from_hex(kek), &from_hex(wrapped)).unwrap();
+            assert_eq!(result, from_hex(key));
+        }
+    }
+
+    #[test]
+    fn key_unwrap_failed() {
+        let (algo, kek, _, wrapped) = KEY_WRAP_VECTORS[0];
+
+        let mut modified = from_hex(wrapped);
+        modified[12] ^= 0x01;
+        let err = key_unwrap(algo, &from_hex(kek), &modified).unwrap_err();
+        assert_eq!(err.to_string(), SymmetricError::KeyUnwrapFailed.to_string());
+
+        let mut wrong_kek = from_hex(kek);
+        wrong_kek[0] ^= 0x01;
+        let err = key_unwrap(algo, &wrong_kek, &from_hex(wrapped)).unwrap_err();
+        assert_eq!(err.to_string(), SymmetricError::KeyUnwrapFailed.to_string());
+
+        let err = key_unwrap(algo, &from_hex(kek), &from_hex(wrapped)[..12]).unwrap_err();
+        assert_eq!(err.to_string(), SymmetricError::InvalidKeyWrap.to_string());
+    }
+}
@@ -1,5 +1,6 @@
 use digest::Digest;
 use failure::Error;
+use yasna;
 use yasna::models::ObjectIdentifier;
 
 /// Type for public key algorithms supported by OpenPGP.
@@ -15,6 +16,7 @@ pub enum PublicKeyAlgorithm {
     Ecdsa = 19,
     Elgamal = 20,
     DiffieHellman = 21,
+    EdDsa = 22,
     Unknown = 255,
 }
 
@@ -30,6 +32,7 @@ impl From<u8> for PublicKeyAlgorithm {
             19 => PublicKeyAlgorithm::Ecdsa,
             20 => PublicKeyAlgorithm::Elgamal,
             21 => PublicKeyAlgorithm::DiffieHellman,
+            22 => PublicKeyAlgorithm::EdDsa,
             _ => PublicKeyAlgorithm::Unknown,
         }
     }
@@ -47,6 +50,7 @@ impl From<PublicKeyAlgorithm> for u8 {
             PublicKeyAlgorithm::Ecdsa => 19,
             PublicKeyAlgorithm::Elgamal => 20,
             PublicKeyAlgorithm::DiffieHellman => 21,
+            PublicKeyAlgorithm::EdDsa => 22,
             PublicKeyAlgorithm::Unknown => 0xFF,
         }
     }
@@ -124,20 +128,82 @@ impl HashAlgorithm {
         Ok(oid)
     }
 
+    /// Wrap a digest computed with this algorithm in a DER-encoded ASN.1 `DigestInfo` structure,
+    /// as used by PKCS#1 v1.5 signatures.
+    pub fn digest_info<T: AsRef<[u8]>>(&self, digest: T) -> Result<Vec<u8>, Error> {
+        let oid = self.asn1_oid()?;
+
+        Ok(yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_oid(&oid);
+                    writer.next().write_null();
+                });
+                writer.next().write_bytes(digest.as_ref());
+            })
+        }))
+    }
+
     pub fn hash<T: AsRef<[u8]>>(&self, contents: T) -> Result<Vec<u8>, Error> {
-        let contents = contents.as_ref();
-        let hash_result = match *self {
-            HashAlgorithm::Md5 => hash!(::md5::Md5::digest(contents)),
-            HashAlgorithm::Sha1 => hash!(::sha1::Sha1::digest(contents)),
-            HashAlgorithm::Ripemd160 => hash!(::ripemd160::Ripemd160::digest(contents)),
-            HashAlgorithm::Sha256 => hash!(::sha2::Sha256::digest(contents)),
-            HashAlgorithm::Sha384 => hash!(::sha2::Sha384::digest(contents)),
-            HashAlgorithm::Sha512 => hash!(::sha2::Sha512::digest(contents)),
-            HashAlgorithm::Sha224 => hash!(::sha2::Sha224::digest(contents)),
+        let mut hasher = Hasher::new(*self)?;
+        hasher.update(contents.as_ref());
+
+        Ok(hasher.finish())
+    }
+}
+
+/// An incremental hash computation for one of the supported [`HashAlgorithm`]s.
+///
+/// [`HashAlgorithm`]: enum.HashAlgorithm.html
+#[derive(Clone)]
+pub(crate) enum Hasher {
+    Md5(::md5::Md5),
+    Sha1(::sha1::Sha1),
+    Ripemd160(::ripemd160::Ripemd160),
+    Sha256(::sha2::Sha256),
+    Sha384(::sha2::Sha384),
+    Sha512(::sha2::Sha512),
+    Sha224(::sha2::Sha224),
+}
+
+impl Hasher {
+    pub(crate) fn new(algo: HashAlgorithm) -> Result<Hasher, Error> {
+        let hasher = match algo {
+            HashAlgorithm::Md5 => Hasher::Md5(Digest::new()),
+            HashAlgorithm::Sha1 => Hasher::Sha1(Digest::new()),
+            HashAlgorithm::Ripemd160 => Hasher::Ripemd160(Digest::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Digest::new()),
+            HashAlgorithm::Sha384 => Hasher::Sha384(Digest::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Digest::new()),
+            HashAlgorithm::Sha224 => Hasher::Sha224(Digest::new()),
             HashAlgorithm::Unknown => bail!(AlgorithmError::HashAlgorithmError),
         };
 
-        Ok(hash_result)
+        Ok(hasher)
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Md5(ref mut h) => h.input(data),
+            Hasher::Sha1(ref mut h) => h.input(data),
+            Hasher::Ripemd160(ref mut h) => h.input(data),
+            Hasher::Sha256(ref mut h) => h.input(data),
+            Hasher::Sha384(ref mut h) => h.input(data),
+            Hasher::Sha512(ref mut h) => h.input(data),
+            Hasher::Sha224(ref mut h) => h.input(data),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Md5(h) => hash!(h.result()),
+            Hasher::Sha1(h) => hash!(h.result()),
+            Hasher::Ripemd160(h) => hash!(h.result()),
+            Hasher::Sha256(h) => hash!(h.result()),
+            Hasher::Sha384(h) => hash!(h.result()),
+            Hasher::Sha512(h) => hash!(h.result()),
+            Hasher::Sha224(h) => hash!(h.result()),
+        }
     }
 }
 
@@ -172,6 +238,22 @@ impl SymmetricKeyAlgorithm {
             SymmetricKeyAlgorithm::Reserved | SymmetricKeyAlgorithm::Unknown => 0,
         }
     }
+
+    /// The key size of this cipher in bytes.
+    pub fn key_bytes(&self) -> usize {
+        match *self {
+            SymmetricKeyAlgorithm::Plaintext => 0,
+            SymmetricKeyAlgorithm::Idea => 16,
+            SymmetricKeyAlgorithm::TripleDes => 24,
+            SymmetricKeyAlgorithm::Cast5 => 16,
+            SymmetricKeyAlgorithm::Blowfish => 16,
+            SymmetricKeyAlgorithm::Aes128 => 16,
+            SymmetricKeyAlgorithm::Aes192 => 24,
+            SymmetricKeyAlgorithm::Aes256 => 32,
+            SymmetricKeyAlgorithm::Twofish => 32,
+            SymmetricKeyAlgorithm::Reserved | SymmetricKeyAlgorithm::Unknown => 0,
+        }
+    }
 }
 
 impl From<u8> for SymmetricKeyAlgorithm {
@@ -192,6 +274,134 @@ impl From<u8> for SymmetricKeyAlgorithm {
     }
 }
 
+impl From<SymmetricKeyAlgorithm> for u8 {
+    fn from(val: SymmetricKeyAlgorithm) -> u8 {
+        match val {
+            SymmetricKeyAlgorithm::Plaintext => 0,
+            SymmetricKeyAlgorithm::Idea => 1,
+            SymmetricKeyAlgorithm::TripleDes => 2,
+            SymmetricKeyAlgorithm::Cast5 => 3,
+            SymmetricKeyAlgorithm::Blowfish => 4,
+            SymmetricKeyAlgorithm::Aes128 => 7,
+            SymmetricKeyAlgorithm::Aes192 => 8,
+            SymmetricKeyAlgorithm::Aes256 => 9,
+            SymmetricKeyAlgorithm::Twofish => 10,
+            SymmetricKeyAlgorithm::Reserved => 5,
+            SymmetricKeyAlgorithm::Unknown => 0xFF,
+        }
+    }
+}
+
+/// Type for compression algorithms supported by OpenPGP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CompressionAlgorithm {
+    Uncompressed = 0,
+    Zip = 1,
+    Zlib = 2,
+    Bzip2 = 3,
+    Unknown,
+}
+
+impl From<u8> for CompressionAlgorithm {
+    fn from(val: u8) -> CompressionAlgorithm {
+        match val {
+            0 => CompressionAlgorithm::Uncompressed,
+            1 => CompressionAlgorithm::Zip,
+            2 => CompressionAlgorithm::Zlib,
+            3 => CompressionAlgorithm::Bzip2,
+            _ => CompressionAlgorithm::Unknown,
+        }
+    }
+}
+
+impl From<CompressionAlgorithm> for u8 {
+    fn from(val: CompressionAlgorithm) -> u8 {
+        match val {
+            CompressionAlgorithm::Uncompressed => 0,
+            CompressionAlgorithm::Zip => 1,
+            CompressionAlgorithm::Zlib => 2,
+            CompressionAlgorithm::Bzip2 => 3,
+            CompressionAlgorithm::Unknown => 0xFF,
+        }
+    }
+}
+
+static OID_NIST_P256: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+static OID_NIST_P384: &[u8] = &[0x2B, 0x81, 0x04, 0x00, 0x22];
+static OID_NIST_P521: &[u8] = &[0x2B, 0x81, 0x04, 0x00, 0x23];
+static OID_BRAINPOOL_P256R1: &[u8] = &[0x2B, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x07];
+static OID_BRAINPOOL_P384R1: &[u8] = &[0x2B, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0B];
+static OID_BRAINPOOL_P512R1: &[u8] = &[0x2B, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0D];
+static OID_SECP256K1: &[u8] = &[0x2B, 0x81, 0x04, 0x00, 0x0A];
+static OID_ED25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01];
+static OID_CURVE25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x97, 0x55, 0x01, 0x05, 0x01];
+
+/// Type for elliptic curves used by OpenPGP keys, identified by their ASN.1 object identifiers.
+/// See [RFC6637 &sect;11].
+///
+/// [RFC6637 &sect;11]: https://tools.ietf.org/html/rfc6637#section-11
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Curve {
+    NistP256,
+    NistP384,
+    NistP521,
+    BrainpoolP256r1,
+    BrainpoolP384r1,
+    BrainpoolP512r1,
+    Secp256k1,
+    Ed25519,
+    Curve25519,
+    Unknown(Vec<u8>),
+}
+
+impl Curve {
+    /// The DER-encoded object identifier of this curve, without the ASN.1 tag and length.
+    pub fn oid(&self) -> &[u8] {
+        match *self {
+            Curve::NistP256 => OID_NIST_P256,
+            Curve::NistP384 => OID_NIST_P384,
+            Curve::NistP521 => OID_NIST_P521,
+            Curve::BrainpoolP256r1 => OID_BRAINPOOL_P256R1,
+            Curve::BrainpoolP384r1 => OID_BRAINPOOL_P384R1,
+            Curve::BrainpoolP512r1 => OID_BRAINPOOL_P512R1,
+            Curve::Secp256k1 => OID_SECP256K1,
+            Curve::Ed25519 => OID_ED25519,
+            Curve::Curve25519 => OID_CURVE25519,
+            Curve::Unknown(ref oid) => oid,
+        }
+    }
+
+    /// The size in bytes of an element of this curve's underlying field.
+    pub fn field_bytes(&self) -> Option<usize> {
+        match *self {
+            Curve::NistP256 | Curve::BrainpoolP256r1 | Curve::Secp256k1 => Some(32),
+            Curve::NistP384 | Curve::BrainpoolP384r1 => Some(48),
+            Curve::NistP521 => Some(66),
+            Curve::BrainpoolP512r1 => Some(64),
+            Curve::Ed25519 | Curve::Curve25519 => Some(32),
+            Curve::Unknown(_) => None,
+        }
+    }
+}
+
+impl From<&[u8]> for Curve {
+    fn from(oid: &[u8]) -> Curve {
+        match oid {
+            o if o == OID_NIST_P256 => Curve::NistP256,
+            o if o == OID_NIST_P384 => Curve::NistP384,
+            o if o == OID_NIST_P521 => Curve::NistP521,
+            o if o == OID_BRAINPOOL_P256R1 => Curve::BrainpoolP256r1,
+            o if o == OID_BRAINPOOL_P384R1 => Curve::BrainpoolP384r1,
+            o if o == OID_BRAINPOOL_P512R1 => Curve::BrainpoolP512r1,
+            o if o == OID_SECP256K1 => Curve::Secp256k1,
+            o if o == OID_ED25519 => Curve::Ed25519,
+            o if o == OID_CURVE25519 => Curve::Curve25519,
+            o => Curve::Unknown(Vec::from(o)),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u32)]
 pub(crate) enum NomError {
@@ -212,14 +422,21 @@ impl From<u32> for NomError {
     }
 }
 
-/// Error type for [`PublicKeyAlgorithm`] and [`HashAlgorithm`]-related operations.
+/// Error type for [`PublicKeyAlgorithm`], [`HashAlgorithm`], [`SymmetricKeyAlgorithm`] and
+/// [`CompressionAlgorithm`]-related operations.
 ///
 /// [`PublicKeyAlgorithm`]: enum.PublicKeyAlgorithm.html
 /// [`HashAlgorithm`]: enum.HashAlgorithm.html
+/// [`SymmetricKeyAlgorithm`]: enum.SymmetricKeyAlgorithm.html
+/// [`CompressionAlgorithm`]: enum.CompressionAlgorithm.html
 #[derive(Clone, Debug, Fail)]
 pub enum AlgorithmError {
     #[fail(display = "unknown public key algorithm")]
     PublicKeyAlgorithmError,
     #[fail(display = "unknown hash algorithm")]
     HashAlgorithmError,
+    #[fail(display = "unsupported symmetric key algorithm")]
+    SymmetricKeyAlgorithmError,
+    #[fail(display = "unknown compression algorithm")]
+    CompressionAlgorithmError,
 }
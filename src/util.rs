@@ -3,7 +3,9 @@ use std::time::Duration;
 use byteorder::{BigEndian, ReadBytesExt};
 use nom::{ErrorKind, IResult, be_u16};
 use nom::Err as NomErr;
-use num::BigUint;
+use num::{BigUint, One};
+use rand;
+use rand::RngCore;
 
 use types::*;
 
@@ -35,3 +37,34 @@ pub(crate) fn pgp_mpi(inp: &[u8]) -> IResult<&[u8], BigUint> {
 
     IResult::Done(remaining, BigUint::from_bytes_be(mpi_slice))
 }
+
+/// Encode an integer as a big-endian octet string of exactly `len` octets, if it fits.
+pub(crate) fn fixed_bytes(n: &BigUint, len: usize) -> Option<Vec<u8>> {
+    let bytes = n.to_bytes_be();
+    if bytes.len() > len {
+        return None;
+    }
+
+    let mut out = vec![0u8; len - bytes.len()];
+    out.extend(&bytes);
+
+    Some(out)
+}
+
+/// Pick a uniformly random integer in `[1, n)`.
+pub(crate) fn random_below(n: &BigUint) -> BigUint {
+    // Sampling 64 bits more than needed makes the bias of the reduction negligible.
+    let mut bytes = vec![0u8; n.bits().div_ceil(8) + 8];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    BigUint::from_bytes_be(&bytes) % (n - BigUint::one()) + BigUint::one()
+}
+
+/// Decode a hexadecimal test vector.
+#[cfg(test)]
+pub(crate) fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
@@ -0,0 +1,515 @@
+use std::time::Duration;
+
+use certificate::Certificate;
+use key::Key;
+use signature::{KeyFlags, SignaturePacket, SignatureType};
+use types::PublicKeyAlgorithm;
+
+/// The validity of one component of a [`Certificate`] at some reference time.
+///
+/// [`Certificate`]: struct.Certificate.html
+#[derive(Clone, Debug)]
+pub struct ComponentValidity {
+    pub result: Result<(), ValidityError>,
+    /// The most recent valid self-signature binding the component to the certificate, which
+    /// gives its key flags and expiration time.
+    pub binding: Option<SignaturePacket>,
+}
+
+impl ComponentValidity {
+    fn invalid(error: ValidityError, binding: Option<SignaturePacket>) -> ComponentValidity {
+        ComponentValidity {
+            result: Err(error),
+            binding,
+        }
+    }
+
+    /// Whether the component is valid.
+    pub fn is_valid(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// The key flags given by the component's binding signature, if any.
+    pub fn key_flags(&self) -> Option<KeyFlags> {
+        self.binding.as_ref().and_then(|binding| binding.key_flags())
+    }
+}
+
+/// The validity of each component of a [`Certificate`] at some reference time, as returned by
+/// [`Certificate::validate`]. Components are listed in the same order as in the certificate.
+///
+/// [`Certificate`]: struct.Certificate.html
+/// [`Certificate::validate`]: struct.Certificate.html#method.validate
+#[derive(Clone, Debug)]
+pub struct CertificateValidity {
+    pub primary: ComponentValidity,
+    pub user_ids: Vec<ComponentValidity>,
+    pub user_attributes: Vec<ComponentValidity>,
+    pub subkeys: Vec<ComponentValidity>,
+}
+
+impl Certificate {
+    /// Check the validity of each component of this certificate at `time`, measured since the
+    /// Unix epoch.
+    ///
+    /// Only self-signatures are taken into account: each user ID, user attribute and subkey must
+    /// be bound to the primary key by a signature that verifies and is in effect at `time`, and
+    /// the primary key itself by a certification of its primary user ID or a direct-key
+    /// signature. Key expiration times from these signatures are applied, as are revocations.
+    /// Revocations for a key that was superseded or retired only take effect from their creation
+    /// time, while all others apply at any time. Signing subkeys must also carry a valid primary
+    /// key binding signature.
+    pub fn validate(&self, time: Duration) -> CertificateValidity {
+        let primary = &self.primary;
+
+        let user_ids = self.user_ids
+            .iter()
+            .map(|user_id| {
+                certification_validity(&user_id.signatures, time, |signature| {
+                    signature
+                        .verify_certification(primary, &user_id.user_id, primary)
+                        .is_ok()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let user_attributes = self.user_attributes
+            .iter()
+            .map(|attribute| {
+                certification_validity(&attribute.signatures, time, |signature| {
+                    signature
+                        .verify_attribute_certification(primary, &attribute.attribute, primary)
+                        .is_ok()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let primary_validity = self.primary_validity(&user_ids, time);
+
+        let subkeys = self.subkeys
+            .iter()
+            .map(|subkey| subkey_validity(primary, &subkey.key, &subkey.signatures, time))
+            .collect::<Vec<_>>();
+
+        // Nothing bound to an invalid primary key can be valid either.
+        let propagate = |mut component: ComponentValidity| {
+            if component.is_valid() && !primary_validity.is_valid() {
+                component.result = Err(ValidityError::InvalidPrimaryKey);
+            }
+            component
+        };
+
+        CertificateValidity {
+            user_ids: user_ids.into_iter().map(&propagate).collect(),
+            user_attributes: user_attributes.into_iter().map(&propagate).collect(),
+            subkeys: subkeys.into_iter().map(&propagate).collect(),
+            primary: primary_validity,
+        }
+    }
+
+    /// The keys of this certificate that may be used to make signatures at `time`.
+    ///
+    /// Keys without key flags are assumed to be usable for anything their algorithm supports.
+    pub fn signing_keys(&self, time: Duration) -> Vec<&Key> {
+        self.usable_keys(time, |flags| flags.can_sign(), algorithm_can_sign)
+    }
+
+    /// The keys of this certificate that may be used to encrypt messages at `time`.
+    ///
+    /// Keys without key flags are assumed to be usable for anything their algorithm supports.
+    pub fn encryption_keys(&self, time: Duration) -> Vec<&Key> {
+        self.usable_keys(time, |flags| flags.can_encrypt(), algorithm_can_encrypt)
+    }
+
+    fn usable_keys<F, G>(&self, time: Duration, flag: F, algorithm: G) -> Vec<&Key>
+    where
+        F: Fn(&KeyFlags) -> bool,
+        G: Fn(PublicKeyAlgorithm) -> bool,
+    {
+        let validity = self.validate(time);
+        let components = ::std::iter::once((&self.primary, &validity.primary)).chain(
+            self.subkeys
+                .iter()
+                .map(|subkey| &subkey.key)
+                .zip(&validity.subkeys),
+        );
+
+        components
+            .filter(|&(key, component)| {
+                component.is_valid() && match component.key_flags() {
+                    Some(ref flags) => flag(flags),
+                    None => algorithm(key.pubkey_algorithm),
+                }
+            })
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    fn primary_validity(
+        &self,
+        user_ids: &[ComponentValidity],
+        time: Duration,
+    ) -> ComponentValidity {
+        let primary = &self.primary;
+        if primary.creation_time > time {
+            return ComponentValidity::invalid(ValidityError::NotYetCreated, None);
+        }
+
+        let revoked = revocation(
+            &self.signatures,
+            SignatureType::KeyRevocation,
+            time,
+            |signature| signature.verify_direct_key(primary, primary).is_ok(),
+        );
+        if let Some(error) = revoked {
+            return ComponentValidity::invalid(error, None);
+        }
+
+        // The primary key is bound by the self-signature on its primary user ID, or failing
+        // that, the most recent one on any user ID or a direct-key signature.
+        let user_id_bindings = user_ids
+            .iter()
+            .filter(|user_id| user_id.is_valid())
+            .filter_map(|user_id| user_id.binding.as_ref());
+        let direct_bindings = self.signatures.iter().filter(|signature| {
+            signature.sig_type == SignatureType::DirectKey && in_effect(signature, time)
+                && signature.verify_direct_key(primary, primary).is_ok()
+        });
+
+        let binding = latest(user_id_bindings.clone().filter(|s| s.is_primary_user_id()))
+            .or_else(|| latest(user_id_bindings))
+            .or_else(|| latest(direct_bindings))
+            .cloned();
+
+        match binding {
+            Some(binding) => key_expiry(primary, binding, time),
+            None => ComponentValidity::invalid(ValidityError::MissingSelfSignature, None),
+        }
+    }
+}
+
+// Whether a signature was made at or before `time`, and has not expired by then.
+fn in_effect(signature: &SignaturePacket, time: Duration) -> bool {
+    signature.timestamp().is_some_and(|created| created <= time)
+        && !signature.is_expired_at(time)
+}
+
+fn latest<'a, I>(signatures: I) -> Option<&'a SignaturePacket>
+where
+    I: Iterator<Item = &'a SignaturePacket>,
+{
+    signatures.max_by_key(|signature| signature.timestamp())
+}
+
+// Find a revocation of type `sig_type` among `signatures` that is in effect at `time`.
+fn revocation<F>(
+    signatures: &[SignaturePacket],
+    sig_type: SignatureType,
+    time: Duration,
+    verify: F,
+) -> Option<ValidityError>
+where
+    F: Fn(&SignaturePacket) -> bool,
+{
+    signatures
+        .iter()
+        .filter(|signature| signature.sig_type == sig_type)
+        .filter(|signature| {
+            let (code, _) = signature.revocation_reason().unwrap_or((0, String::new()));
+            // Keys that were superseded or retired were fine until the revocation was made.
+            let soft = code == 1 || code == 3;
+            !soft || signature.timestamp().is_some_and(|created| created <= time)
+        })
+        .find(|signature| verify(signature))
+        .map(revoked)
+}
+
+fn revoked(signature: &SignaturePacket) -> ValidityError {
+    let (code, reason) = signature.revocation_reason().unwrap_or((0, String::new()));
+    ValidityError::Revoked { code, reason }
+}
+
+// Apply the key expiration time given by a binding signature.
+fn key_expiry(key: &Key, binding: SignaturePacket, time: Duration) -> ComponentValidity {
+    match binding.key_expiration_time() {
+        Some(expiration) if expiration.as_secs() != 0 && key.creation_time + expiration <= time => {
+            let expired = (key.creation_time + expiration).as_secs();
+            ComponentValidity::invalid(ValidityError::Expired { expired }, Some(binding))
+        }
+        _ => ComponentValidity {
+            result: Ok(()),
+            binding: Some(binding),
+        },
+    }
+}
+
+fn certification_validity<F>(
+    signatures: &[SignaturePacket],
+    time: Duration,
+    verify: F,
+) -> ComponentValidity
+where
+    F: Fn(&SignaturePacket) -> bool,
+{
+    let certifications = signatures.iter().filter(|signature| match signature.sig_type {
+        SignatureType::GenericCertification
+        | SignatureType::PersonaCertification
+        | SignatureType::CasualCertification
+        | SignatureType::PositiveCertification => in_effect(signature, time) && verify(signature),
+        _ => false,
+    });
+
+    let binding = match latest(certifications) {
+        Some(binding) => binding.clone(),
+        None => return ComponentValidity::invalid(ValidityError::MissingSelfSignature, None),
+    };
+
+    // A certification revocation only revokes the certifications made before it.
+    let revocation = signatures.iter().find(|signature| {
+        signature.sig_type == SignatureType::CertificationRevocation
+            && signature.timestamp() >= binding.timestamp() && in_effect(signature, time)
+            && verify(signature)
+    });
+
+    match revocation {
+        Some(revocation) => ComponentValidity::invalid(revoked(revocation), Some(binding)),
+        None => ComponentValidity {
+            result: Ok(()),
+            binding: Some(binding),
+        },
+    }
+}
+
+fn subkey_validity(
+    primary: &Key,
+    subkey: &Key,
+    signatures: &[SignaturePacket],
+    time: Duration,
+) -> ComponentValidity {
+    if subkey.creation_time > time {
+        return ComponentValidity::invalid(ValidityError::NotYetCreated, None);
+    }
+
+    let bindings = signatures.iter().filter(|signature| {
+        signature.sig_type == SignatureType::SubkeyBinding && in_effect(signature, time)
+            && signature.verify_key_binding(primary, subkey, primary).is_ok()
+    });
+    let binding = match latest(bindings) {
+        Some(binding) => binding.clone(),
+        None => return ComponentValidity::invalid(ValidityError::MissingSelfSignature, None),
+    };
+
+    let revoked = revocation(
+        signatures,
+        SignatureType::SubkeyRevocation,
+        time,
+        |signature| signature.verify_key_binding(primary, subkey, primary).is_ok(),
+    );
+    if let Some(error) = revoked {
+        return ComponentValidity::invalid(error, Some(binding));
+    }
+
+    // Signing subkeys must prove that they agree to be bound to the primary key, so that nobody
+    // can claim someone else's signing key as their own.
+    let can_sign = match binding.key_flags() {
+        Some(flags) => flags.can_sign(),
+        None => algorithm_can_sign(subkey.pubkey_algorithm),
+    };
+    if can_sign {
+        let back_signature = binding.embedded_signature().is_some_and(|signature| {
+            signature.sig_type == SignatureType::PrimaryKeyBinding
+                && signature.verify_key_binding(primary, subkey, subkey).is_ok()
+        });
+        if !back_signature {
+            return ComponentValidity::invalid(ValidityError::MissingBackSignature, Some(binding));
+        }
+    }
+
+    key_expiry(subkey, binding, time)
+}
+
+fn algorithm_can_sign(algorithm: PublicKeyAlgorithm) -> bool {
+    matches!(
+        algorithm,
+        PublicKeyAlgorithm::Rsa
+            | PublicKeyAlgorithm::RsaSignOnly
+            | PublicKeyAlgorithm::Dsa
+            | PublicKeyAlgorithm::Ecdsa
+            | PublicKeyAlgorithm::EdDsa
+    )
+}
+
+fn algorithm_can_encrypt(algorithm: PublicKeyAlgorithm) -> bool {
+    matches!(
+        algorithm,
+        PublicKeyAlgorithm::Rsa
+            | PublicKeyAlgorithm::RsaEncryptOnly
+            | PublicKeyAlgorithm::ElgamalEncryptOnly
+            | PublicKeyAlgorithm::Elgamal
+            | PublicKeyAlgorithm::EllipticCurve
+    )
+}
+
+/// The reason a component of a [`Certificate`] is not valid.
+///
+/// [`Certificate`]: struct.Certificate.html
+#[derive(Clone, Debug, Fail, PartialEq, Eq)]
+pub enum ValidityError {
+    #[fail(display = "Key was created after the reference time")]
+    NotYetCreated,
+    #[fail(display = "No valid self-signature")]
+    MissingSelfSignature,
+    #[fail(display = "Expired at {} seconds since the Unix epoch", expired)]
+    Expired { expired: u64 },
+    #[fail(display = "Revoked with reason code {}: {}", code, reason)]
+    Revoked { code: u8, reason: String },
+    #[fail(display = "Signing subkey has no valid primary key binding signature")]
+    MissingBackSignature,
+    #[fail(display = "Primary key is not valid")]
+    InvalidPrimaryKey,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const SUBKEY_CERTIFICATE: &[u8] = include_bytes!("../tests/data/sub.pub");
+    const EXPIRED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/expired.pub");
+    const REVOKED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/revoked.pub");
+    const REVOKED_USER_ID_CERTIFICATE: &[u8] = include_bytes!("../tests/data/multi.pub");
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn ids(keys: Vec<&Key>) -> Vec<u64> {
+        keys.into_iter().map(|key| key.id().unwrap()).collect()
+    }
+
+    #[test]
+    fn valid_certificate() {
+        let certificate = Certificate::from_bytes(ED_CERTIFICATE).unwrap();
+        let time = certificate.primary.creation_time + Duration::from_secs(DAY);
+
+        let validity = certificate.validate(time);
+        assert!(validity.primary.is_valid());
+        assert!(validity.user_ids[0].is_valid());
+        assert!(validity.subkeys[0].is_valid());
+        assert!(validity.primary.key_flags().unwrap().can_sign());
+
+        assert_eq!(ids(certificate.signing_keys(time)), vec![0xFAB9_C2B7_EE84_CBA8]);
+        assert_eq!(ids(certificate.encryption_keys(time)), vec![0xD88B_EE62_C596_6048]);
+    }
+
+    #[test]
+    fn not_yet_created() {
+        let certificate = Certificate::from_bytes(ED_CERTIFICATE).unwrap();
+        let time = certificate.primary.creation_time - Duration::from_secs(1);
+
+        let validity = certificate.validate(time);
+        assert_eq!(validity.primary.result, Err(ValidityError::NotYetCreated));
+        assert_eq!(validity.user_ids[0].result, Err(ValidityError::MissingSelfSignature));
+        assert_eq!(validity.subkeys[0].result, Err(ValidityError::NotYetCreated));
+        assert!(certificate.signing_keys(time).is_empty());
+    }
+
+    #[test]
+    fn expired_certificate() {
+        let certificate = Certificate::from_bytes(EXPIRED_CERTIFICATE).unwrap();
+        let created = certificate.primary.creation_time;
+
+        assert!(certificate.validate(created).primary.is_valid());
+
+        let time = created + Duration::from_secs(DAY);
+        let validity = certificate.validate(time);
+        let expired = time.as_secs();
+        assert_eq!(validity.primary.result, Err(ValidityError::Expired { expired }));
+        assert!(validity.primary.binding.is_some());
+        assert!(certificate.signing_keys(time).is_empty());
+    }
+
+    #[test]
+    fn revoked_certificate() {
+        let certificate = Certificate::from_bytes(REVOKED_CERTIFICATE).unwrap();
+
+        // Revocations without a reason apply even before they were made.
+        let validity = certificate.validate(certificate.primary.creation_time);
+        let revoked = ValidityError::Revoked {
+            code: 0,
+            reason: String::new(),
+        };
+        assert_eq!(validity.primary.result, Err(revoked));
+        assert_eq!(validity.user_ids[0].result, Err(ValidityError::InvalidPrimaryKey));
+    }
+
+    #[test]
+    fn revoked_user_id() {
+        let certificate = Certificate::from_bytes(REVOKED_USER_ID_CERTIFICATE).unwrap();
+        assert_eq!(certificate.user_ids[1].user_id, "Gone <gone@example.com>");
+
+        let time = certificate.primary.creation_time + Duration::from_secs(DAY);
+        let validity = certificate.validate(time);
+        let revoked = ValidityError::Revoked {
+            code: 2,
+            reason: "superseded".to_string(),
+        };
+        assert_eq!(validity.primary.result, Err(revoked));
+        assert_eq!(validity.user_ids[0].result, Err(ValidityError::InvalidPrimaryKey));
+        let revoked = ValidityError::Revoked {
+            code: 0x20,
+            reason: String::new(),
+        };
+        assert_eq!(validity.user_ids[1].result, Err(revoked));
+    }
+
+    #[test]
+    fn subkeys() {
+        let certificate = Certificate::from_bytes(SUBKEY_CERTIFICATE).unwrap();
+        let encryption_created = certificate.subkeys[1].key.creation_time;
+
+        // The primary key may only certify, so signatures are made by the signing subkey.
+        let validity = certificate.validate(encryption_created);
+        assert!(validity.subkeys.iter().all(|subkey| subkey.is_valid()));
+        assert_eq!(
+            ids(certificate.signing_keys(encryption_created)),
+            vec![0x5EEE_DEC5_C770_E472]
+        );
+        assert_eq!(
+            ids(certificate.encryption_keys(encryption_created)),
+            vec![0xC906_027D_6716_981C]
+        );
+
+        let time = encryption_created + Duration::from_secs(DAY);
+        let validity = certificate.validate(time);
+        assert!(validity.subkeys[0].is_valid());
+        let expired = time.as_secs();
+        assert_eq!(validity.subkeys[1].result, Err(ValidityError::Expired { expired }));
+        assert!(certificate.encryption_keys(time).is_empty());
+    }
+
+    #[test]
+    fn missing_back_signature() {
+        let mut certificate = Certificate::from_bytes(SUBKEY_CERTIFICATE).unwrap();
+        let time = certificate.subkeys[1].key.creation_time;
+
+        // The primary key binding signature is embedded in the unhashed area.
+        certificate.subkeys[0].signatures[0].unhashed_subpackets.clear();
+
+        let validity = certificate.validate(time);
+        assert_eq!(validity.subkeys[0].result, Err(ValidityError::MissingBackSignature));
+        assert!(certificate.signing_keys(time).is_empty());
+    }
+
+    #[test]
+    fn bad_self_signature() {
+        let mut certificate = Certificate::from_bytes(ED_CERTIFICATE).unwrap();
+        let time = certificate.primary.creation_time + Duration::from_secs(DAY);
+
+        certificate.user_ids[0].user_id = "Ed Test <ef@example.com>".to_string();
+
+        let validity = certificate.validate(time);
+        assert_eq!(validity.user_ids[0].result, Err(ValidityError::MissingSelfSignature));
+        assert_eq!(validity.primary.result, Err(ValidityError::MissingSelfSignature));
+        assert_eq!(validity.subkeys[0].result, Err(ValidityError::InvalidPrimaryKey));
+        assert!(certificate.encryption_keys(time).is_empty());
+    }
+}
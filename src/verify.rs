@@ -0,0 +1,596 @@
+use ed25519_dalek;
+use ed25519_dalek::Verifier;
+use failure::Error;
+use num::{BigUint, One, Zero};
+
+use key::{DsaPublicKey, EcdsaPublicKey, EdDsaPublicKey, Key, KeyError, KeyMaterial,
+          RsaPublicKey};
+use signature::{Signature, SignaturePacket};
+use types::*;
+use util;
+
+/// Build the PKCS#1 v1.5 encoded message from RFC3447, Section 9.2, for a modulus of `len` octets:
+/// 0x00 || 0x01 || 0xFF... || 0x00 || DigestInfo
+///
+/// Returns `None` if the modulus is too small to hold the digest.
+pub(crate) fn emsa_pkcs1_v1_5(
+    hash_algo: HashAlgorithm,
+    digest: &[u8],
+    len: usize,
+) -> Result<Option<Vec<u8>>, Error> {
+    let digest_info = hash_algo.digest_info(digest)?;
+    if len < digest_info.len() + 11 {
+        return Ok(None);
+    }
+
+    let mut message = vec![0x00u8, 0x01];
+    message.resize(len - digest_info.len() - 1, 0xFF);
+    message.push(0x00);
+    message.extend(&digest_info);
+
+    Ok(Some(message))
+}
+
+fn verify_rsa(
+    hash_algo: HashAlgorithm,
+    digest: &[u8],
+    signature: &BigUint,
+    key: &RsaPublicKey,
+) -> Result<(), Error> {
+    if *signature >= key.n {
+        return Err(VerificationError::BadSignature.into());
+    }
+
+    let modulus_bytes = key.n.bits().div_ceil(8);
+    let expected = match emsa_pkcs1_v1_5(hash_algo, digest, modulus_bytes)? {
+        Some(expected) => BigUint::from_bytes_be(&expected),
+        None => return Err(VerificationError::BadSignature.into()),
+    };
+
+    if signature.modpow(&key.e, &key.n) != expected {
+        return Err(VerificationError::BadSignature.into());
+    }
+
+    Ok(())
+}
+
+/// Convert a digest to an integer, keeping only its leftmost `bits` bits as required by DSA and
+/// ECDSA when the digest is longer than the group order.
+pub(crate) fn truncate_digest(digest: &[u8], bits: usize) -> BigUint {
+    let digest_bits = digest.len() * 8;
+    let z = BigUint::from_bytes_be(digest);
+
+    if digest_bits > bits {
+        z >> (digest_bits - bits)
+    } else {
+        z
+    }
+}
+
+fn verify_dsa(
+    digest: &[u8],
+    r: &BigUint,
+    s: &BigUint,
+    key: &DsaPublicKey,
+) -> Result<(), Error> {
+    if key.p.is_zero() || key.q.is_zero() {
+        bail!(KeyError::InvalidFormat {
+            reason: "DSA group parameters can't be zero".to_string(),
+        });
+    }
+
+    if r.is_zero() || s.is_zero() || *r >= key.q || *s >= key.q {
+        return Err(VerificationError::BadSignature.into());
+    }
+
+    let z = truncate_digest(digest, key.q.bits());
+
+    // q is prime, so s^-1 mod q is s^(q-2) mod q.
+    let two = BigUint::one() + BigUint::one();
+    let w = s.modpow(&(&key.q - &two), &key.q);
+
+    let u1 = (&z * &w) % &key.q;
+    let u2 = (r * &w) % &key.q;
+    let v = ((key.g.modpow(&u1, &key.p) * key.y.modpow(&u2, &key.p)) % &key.p) % &key.q;
+
+    if v != *r {
+        return Err(VerificationError::BadSignature.into());
+    }
+
+    Ok(())
+}
+
+fn fixed_bytes(n: &BigUint, len: usize) -> Result<Vec<u8>, Error> {
+    match util::fixed_bytes(n, len) {
+        Some(bytes) => Ok(bytes),
+        None => Err(VerificationError::BadSignature.into()),
+    }
+}
+
+macro_rules! verify_ecdsa {
+    ($curve:ident, $point:expr, $signature:expr, $digest:expr) => {{
+        use $curve::ecdsa::signature::hazmat::PrehashVerifier;
+
+        let key = $curve::ecdsa::VerifyingKey::from_sec1_bytes($point)
+            .map_err(|_| KeyError::InvalidPoint)?;
+        let signature = $curve::ecdsa::Signature::from_slice($signature)
+            .map_err(|_| VerificationError::BadSignature)?;
+
+        key.verify_prehash($digest, &signature)
+            .map_err(|_| VerificationError::BadSignature)?;
+    }};
+}
+
+fn verify_ecdsa(
+    digest: &[u8],
+    r: &BigUint,
+    s: &BigUint,
+    key: &EcdsaPublicKey,
+) -> Result<(), Error> {
+    let field_bytes = match key.curve.field_bytes() {
+        Some(field_bytes) => field_bytes,
+        None => return Err(VerificationError::UnsupportedAlgorithm {
+            algorithm: format!("{:?}", key.curve),
+        }.into()),
+    };
+
+    let point = key.native_point()?;
+    let mut signature = fixed_bytes(r, field_bytes)?;
+    signature.extend(&fixed_bytes(s, field_bytes)?);
+
+    match key.curve {
+        Curve::NistP256 => verify_ecdsa!(p256, point, &signature, digest),
+        Curve::NistP384 => verify_ecdsa!(p384, point, &signature, digest),
+        Curve::NistP521 => verify_ecdsa!(p521, point, &signature, digest),
+        ref curve => return Err(VerificationError::UnsupportedAlgorithm {
+            algorithm: format!("{:?}", curve),
+        }.into()),
+    }
+
+    Ok(())
+}
+
+fn verify_eddsa(
+    digest: &[u8],
+    r: &BigUint,
+    s: &BigUint,
+    key: &EdDsaPublicKey,
+) -> Result<(), Error> {
+    if key.curve != Curve::Ed25519 {
+        return Err(VerificationError::UnsupportedAlgorithm {
+            algorithm: format!("{:?}", key.curve),
+        }.into());
+    }
+
+    let mut point = [0u8; 32];
+    point.copy_from_slice(key.native_point()?);
+    let key = ed25519_dalek::VerifyingKey::from_bytes(&point).map_err(|_| KeyError::InvalidPoint)?;
+
+    // EdDSA signatures store the native encodings of R and S, which are then the signature.
+    let mut signature = [0u8; 64];
+    signature[..32].copy_from_slice(&fixed_bytes(r, 32)?);
+    signature[32..].copy_from_slice(&fixed_bytes(s, 32)?);
+    let signature = ed25519_dalek::Signature::from_bytes(&signature);
+
+    // The message signed by an OpenPGP EdDSA signature is the digest itself.
+    key.verify(digest, &signature)
+        .map_err(|_| VerificationError::BadSignature)?;
+
+    Ok(())
+}
+
+impl SignaturePacket {
+    /// Verify this signature over some payload, using the public key material of `key`.
+    ///
+    /// On failure, the returned error will be a [`VerificationError`] if the signature could be
+    /// checked but did not verify.
+    ///
+    /// [`VerificationError`]: enum.VerificationError.html
+    pub fn verify<T: AsRef<[u8]>>(&self, payload: T, key: &Key) -> Result<(), Error> {
+        if self.hash_algo == HashAlgorithm::Unknown {
+            return Err(VerificationError::UnsupportedAlgorithm {
+                algorithm: format!("{:?}", self.hash_algo),
+            }.into());
+        }
+
+        let digest = self.digest(payload)?;
+        self.verify_digest(&digest, key)
+    }
+
+    /// Verify this signature as a certification of `user_id` on `key`, or as the revocation of
+    /// one, made by `signer`.
+    pub fn verify_certification(
+        &self,
+        key: &Key,
+        user_id: &str,
+        signer: &Key,
+    ) -> Result<(), Error> {
+        let digest = self.certification_hasher(key, user_id)?.digest()?;
+        self.verify_digest(&digest, signer)
+    }
+
+    /// Verify this signature as a certification of a user attribute on `key`, or as the
+    /// revocation of one, made by `signer`. The attribute is given as the contents of its packet.
+    pub fn verify_attribute_certification(
+        &self,
+        key: &Key,
+        attribute: &[u8],
+        signer: &Key,
+    ) -> Result<(), Error> {
+        let digest = self.attribute_certification_hasher(key, attribute)?.digest()?;
+        self.verify_digest(&digest, signer)
+    }
+
+    /// Verify this signature as a binding or revocation of `subkey` to `primary`, made by
+    /// `signer`. Subkey binding and revocation signatures are made by the primary key, and
+    /// primary key binding signatures by the subkey.
+    pub fn verify_key_binding(
+        &self,
+        primary: &Key,
+        subkey: &Key,
+        signer: &Key,
+    ) -> Result<(), Error> {
+        let digest = self.key_binding_hasher(primary, subkey)?.digest()?;
+        self.verify_digest(&digest, signer)
+    }
+
+    /// Verify this signature as a direct-key or key revocation signature over `key`, made by
+    /// `signer`.
+    pub fn verify_direct_key(&self, key: &Key, signer: &Key) -> Result<(), Error> {
+        let digest = self.direct_key_hasher(key)?.digest()?;
+        self.verify_digest(&digest, signer)
+    }
+
+    /// Verify this signature given the digest of the data it covers, as returned by
+    /// [`signable_payload`].
+    ///
+    /// [`signable_payload`]: #method.signable_payload
+    pub fn verify_digest(&self, digest: &[u8], key: &Key) -> Result<(), Error> {
+        if self.hash_algo == HashAlgorithm::Unknown {
+            return Err(VerificationError::UnsupportedAlgorithm {
+                algorithm: format!("{:?}", self.hash_algo),
+            }.into());
+        }
+
+        match self.pubkey_algo {
+            PublicKeyAlgorithm::Rsa
+            | PublicKeyAlgorithm::RsaSignOnly
+            | PublicKeyAlgorithm::Dsa
+            | PublicKeyAlgorithm::Ecdsa
+            | PublicKeyAlgorithm::EdDsa => {}
+            algo => return Err(VerificationError::UnsupportedAlgorithm {
+                algorithm: format!("{:?}", algo),
+            }.into()),
+        }
+
+        if let Some(signer) = self.signer() {
+            if signer != key.id()? {
+                return Err(VerificationError::WrongKey.into());
+            }
+        }
+
+        match (self.contents()?, &key.key_material) {
+            (Signature::Rsa(s), KeyMaterial::Rsa(public, _)) => {
+                verify_rsa(self.hash_algo, digest, &s, public)
+            }
+            (Signature::Dsa(r, s), KeyMaterial::Dsa(public, _)) => {
+                verify_dsa(digest, &r, &s, public)
+            }
+            (Signature::Ecdsa(r, s), KeyMaterial::Ecdsa(public, _)) => {
+                verify_ecdsa(digest, &r, &s, public)
+            }
+            (Signature::EdDsa(r, s), KeyMaterial::EdDsa(public, _)) => {
+                verify_eddsa(digest, &r, &s, public)
+            }
+            _ => Err(VerificationError::WrongKey.into()),
+        }
+    }
+}
+
+/// Error type for signature verification failures.
+#[derive(Clone, Debug, Fail)]
+pub enum VerificationError {
+    #[fail(display = "Bad signature")]
+    BadSignature,
+    #[fail(display = "Signature was not made by this key")]
+    WrongKey,
+    #[fail(display = "Unsupported signature algorithm: {}", algorithm)]
+    UnsupportedAlgorithm { algorithm: String },
+    #[fail(display = "No key available for signature issuer")]
+    MissingKey,
+    #[fail(display = "Malformed signature: {}", reason)]
+    Malformed { reason: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use certificate::{Certificate, SecretCertificate};
+    use packet::Packet;
+    use signature::SignatureType;
+    use signer::{self, KeySigner, Signer};
+
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/message.txt");
+    const RSA_CERTIFICATE: &[u8] = include_bytes!("../tests/data/rsa.pub");
+    const RSA_SIGNATURE: &[u8] = include_bytes!("../tests/data/rsa.sig");
+    const RSA_SECRET_KEY: &[u8] = include_bytes!("../tests/data/rsa.sec");
+    const DSA_CERTIFICATE: &[u8] = include_bytes!("../tests/data/dsa.pub");
+    const DSA_SIGNATURE: &[u8] = include_bytes!("../tests/data/dsa.sig");
+    const ED_CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const ED_SIGNATURE: &[u8] = include_bytes!("../tests/data/ed.sig");
+    const ED_SECRET_KEY: &[u8] = include_bytes!("../tests/data/ed.sec");
+    const P256_CERTIFICATE: &[u8] = include_bytes!("../tests/data/enistp256.pub");
+    const P256_SIGNATURE: &[u8] = include_bytes!("../tests/data/enistp256.sig");
+    const P384_CERTIFICATE: &[u8] = include_bytes!("../tests/data/nistp384.pub");
+    const P384_SIGNATURE: &[u8] = include_bytes!("../tests/data/nistp384.sig");
+    const P521_CERTIFICATE: &[u8] = include_bytes!("../tests/data/nistp521.pub");
+    const P521_SIGNATURE: &[u8] = include_bytes!("../tests/data/nistp521.sig");
+    const BRAINPOOL_CERTIFICATE: &[u8] = include_bytes!("../tests/data/brainpool.pub");
+    const BRAINPOOL_SIGNATURE: &[u8] = include_bytes!("../tests/data/brainpool.sig");
+
+    fn primary_key(certificate: &[u8]) -> Key {
+        Certificate::from_bytes(certificate).unwrap().primary
+    }
+
+    fn signature(bytes: &[u8]) -> SignaturePacket {
+        match Packet::from_bytes(bytes).unwrap().0 {
+            Packet::Signature(signature) => signature,
+            p => panic!("expected a signature, found {:?}", p),
+        }
+    }
+
+    // Flip the lowest bit of the last signature MPI.
+    fn flip_last_bit(bytes: &[u8]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        *bytes.last_mut().unwrap() ^= 0x01;
+        bytes
+    }
+
+    fn verification_error(result: Result<(), Error>) -> VerificationError {
+        match result.unwrap_err().downcast::<VerificationError>() {
+            Ok(err) => err,
+            Err(err) => panic!("expected a verification error, found {}", err),
+        }
+    }
+
+    fn assert_bad_signature(result: Result<(), Error>) {
+        match verification_error(result) {
+            VerificationError::BadSignature => {}
+            err => panic!("expected a bad signature, found {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rsa_signature() {
+        let key = primary_key(RSA_CERTIFICATE);
+        signature(RSA_SIGNATURE).verify(MESSAGE, &key).unwrap();
+    }
+
+    #[test]
+    fn rsa_signature_flipped_bit() {
+        let key = primary_key(RSA_CERTIFICATE);
+        let signature = signature(&flip_last_bit(RSA_SIGNATURE));
+        assert_bad_signature(signature.verify(MESSAGE, &key));
+    }
+
+    #[test]
+    fn rsa_signature_modified_data() {
+        let key = primary_key(RSA_CERTIFICATE);
+        assert_bad_signature(signature(RSA_SIGNATURE).verify(b"Hello, world?\n", &key));
+    }
+
+    #[test]
+    fn rsa_signature_wrong_key() {
+        let key = primary_key(ED_CERTIFICATE);
+        match verification_error(signature(RSA_SIGNATURE).verify(MESSAGE, &key)) {
+            VerificationError::WrongKey => {}
+            err => panic!("expected the wrong key, found {:?}", err),
+        }
+    }
+
+    #[test]
+    fn unsupported_hash_algorithm() {
+        let key = primary_key(RSA_CERTIFICATE);
+        let mut signature = signature(RSA_SIGNATURE);
+        signature.hash_algo = HashAlgorithm::Unknown;
+
+        match verification_error(signature.verify(MESSAGE, &key)) {
+            VerificationError::UnsupportedAlgorithm { algorithm } => {
+                assert_eq!(algorithm, "Unknown")
+            }
+            err => panic!("expected an unsupported algorithm, found {:?}", err),
+        }
+    }
+
+    #[test]
+    fn v3_rsa_signature() {
+        let secret = SecretCertificate::from_bytes(RSA_SECRET_KEY).unwrap();
+        let key = secret.secret_keys()[0].0.clone();
+        let signer = KeySigner::new(key.clone()).unwrap();
+
+        // A version 3 binary document signature made with SHA256 at 2000-01-01 00:00:00 UTC,
+        // followed by its hash prefix and signature MPI.
+        let mut body = vec![0x03, 0x05, 0x00, 0x38, 0x6D, 0x43, 0x80];
+        body.extend(&key.id().unwrap().to_be_bytes());
+        body.extend(&[0x01, 0x08]);
+
+        let unsigned = SignaturePacket::from_bytes(&[&body[..], &[0x00, 0x00]].concat()).unwrap();
+        let digest = unsigned.signable_payload(MESSAGE).unwrap();
+        let mpi = match signer.sign(HashAlgorithm::Sha256, &digest).unwrap() {
+            Signature::Rsa(mpi) => mpi,
+            s => panic!("expected an RSA signature, found {:?}", s),
+        };
+        body.extend(&digest[..2]);
+        body.extend(&(mpi.bits() as u16).to_be_bytes());
+        body.extend(&mpi.to_bytes_be());
+
+        let signature = SignaturePacket::from_bytes(&body).unwrap();
+        let key = key.to_public();
+        signature.verify(MESSAGE, &key).unwrap();
+        assert_bad_signature(signature.verify(b"Hello, world?\n", &key));
+    }
+
+    #[test]
+    fn dsa_signature() {
+        let key = primary_key(DSA_CERTIFICATE);
+        signature(DSA_SIGNATURE).verify(MESSAGE, &key).unwrap();
+    }
+
+    #[test]
+    fn dsa_signature_flipped_bit() {
+        let key = primary_key(DSA_CERTIFICATE);
+        let signature = signature(&flip_last_bit(DSA_SIGNATURE));
+        assert_bad_signature(signature.verify(MESSAGE, &key));
+    }
+
+    #[test]
+    fn dsa_zero_group_parameters() {
+        let mut key = primary_key(DSA_CERTIFICATE);
+        match key.key_material {
+            KeyMaterial::Dsa(ref mut public, _) => public.q = BigUint::zero(),
+            ref material => panic!("expected a DSA key, found {:?}", material),
+        }
+
+        let mut signature = signature(DSA_SIGNATURE);
+        signature.set_signer(key.id().unwrap());
+
+        let err = signature.verify(MESSAGE, &key).unwrap_err();
+        let expected = KeyError::InvalidFormat {
+            reason: "DSA group parameters can't be zero".to_string(),
+        };
+        assert_eq!(err.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn eddsa_signature() {
+        let key = primary_key(ED_CERTIFICATE);
+        signature(ED_SIGNATURE).verify(MESSAGE, &key).unwrap();
+    }
+
+    #[test]
+    fn eddsa_signature_flipped_bit() {
+        let key = primary_key(ED_CERTIFICATE);
+        let signature = signature(&flip_last_bit(ED_SIGNATURE));
+        assert_bad_signature(signature.verify(MESSAGE, &key));
+    }
+
+    #[test]
+    fn ecdsa_signatures() {
+        for &(certificate, signature_bytes) in &[
+            (P256_CERTIFICATE, P256_SIGNATURE),
+            (P384_CERTIFICATE, P384_SIGNATURE),
+            (P521_CERTIFICATE, P521_SIGNATURE),
+        ] {
+            let key = primary_key(certificate);
+            signature(signature_bytes).verify(MESSAGE, &key).unwrap();
+
+            let flipped = signature(&flip_last_bit(signature_bytes));
+            assert_bad_signature(flipped.verify(MESSAGE, &key));
+            assert_bad_signature(signature(signature_bytes).verify(b"Hello, world?\n", &key));
+        }
+    }
+
+    #[test]
+    fn ecdsa_unsupported_curve() {
+        let key = primary_key(BRAINPOOL_CERTIFICATE);
+
+        match verification_error(signature(BRAINPOOL_SIGNATURE).verify(MESSAGE, &key)) {
+            VerificationError::UnsupportedAlgorithm { algorithm } => {
+                assert_eq!(algorithm, "BrainpoolP256r1")
+            }
+            err => panic!("expected an unsupported algorithm, found {:?}", err),
+        }
+    }
+
+    // The primary key, user ID, certification, subkey and subkey binding of a GnuPG export.
+    fn certificate_packets(bytes: &[u8]) -> (Key, String, SignaturePacket, Key, SignaturePacket) {
+        let mut packets = Packet::all_from_bytes(bytes).unwrap().into_iter();
+        match (
+            packets.next(),
+            packets.next(),
+            packets.next(),
+            packets.next(),
+            packets.next(),
+        ) {
+            (
+                Some(Packet::PublicKey(primary)),
+                Some(Packet::UserId(user_id)),
+                Some(Packet::Signature(certification)),
+                Some(Packet::PublicSubkey(subkey)),
+                Some(Packet::Signature(binding)),
+            ) => (primary, user_id, certification, subkey, binding),
+            p => panic!("unexpected packets {:?}", p),
+        }
+    }
+
+    #[test]
+    fn gnupg_self_signatures() {
+        for &certificate in &[
+            RSA_CERTIFICATE,
+            ED_CERTIFICATE,
+            P256_CERTIFICATE,
+            P384_CERTIFICATE,
+            P521_CERTIFICATE,
+        ] {
+            let (primary, user_id, certification, subkey, binding) =
+                certificate_packets(certificate);
+            assert_eq!(certification.sig_type, SignatureType::PositiveCertification);
+            assert_eq!(binding.sig_type, SignatureType::SubkeyBinding);
+
+            certification.verify_certification(&primary, &user_id, &primary).unwrap();
+            binding.verify_key_binding(&primary, &subkey, &primary).unwrap();
+        }
+    }
+
+    #[test]
+    fn gnupg_self_signatures_wrong_component() {
+        let (primary, user_id, certification, subkey, binding) =
+            certificate_packets(ED_CERTIFICATE);
+
+        assert_bad_signature(certification.verify_certification(
+            &primary,
+            &user_id.replace("ed@", "ef@"),
+            &primary,
+        ));
+        assert_bad_signature(certification.verify_certification(&subkey, &user_id, &primary));
+        assert_bad_signature(binding.verify_key_binding(&subkey, &primary, &primary));
+
+        // A certification doesn't verify as a key binding, nor the other way around.
+        assert!(certification.verify_key_binding(&primary, &subkey, &primary).is_err());
+        assert!(binding.verify_certification(&primary, &user_id, &primary).is_err());
+    }
+
+    #[test]
+    fn certification_round_trip() {
+        let (primary, user_id, certification, subkey, binding) =
+            certificate_packets(ED_CERTIFICATE);
+        let secret = match Packet::from_bytes(ED_SECRET_KEY).unwrap().0 {
+            Packet::SecretKey(key) => key,
+            p => panic!("expected a secret key, found {:?}", p),
+        };
+        let signer = KeySigner::new(secret).unwrap();
+
+        let certification =
+            signer::sign_certification(&certification, &primary, &user_id, &signer).unwrap();
+        certification.verify_certification(&primary, &user_id, &primary).unwrap();
+
+        let binding = signer::sign_key_binding(&binding, &primary, &subkey, &signer).unwrap();
+        binding.verify_key_binding(&primary, &subkey, &primary).unwrap();
+    }
+
+    #[test]
+    fn reject_oversized_key() {
+        let (mut primary, _, certification, _, _) = certificate_packets(RSA_CERTIFICATE);
+        match primary.key_material {
+            KeyMaterial::Rsa(ref mut public, _) => public.n = BigUint::one() << 600_000,
+            ref material => panic!("expected an RSA key, found {:?}", material),
+        }
+
+        // The body is too long for the two-octet length it is hashed with.
+        let err = certification.hasher().unwrap().update_key(&primary).unwrap_err();
+        let expected = KeyError::InvalidFormat {
+            reason: "public key is too long to be hashed".to_string(),
+        };
+        assert_eq!(err.to_string(), expected.to_string());
+    }
+}
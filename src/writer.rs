@@ -0,0 +1,572 @@
+use std::io;
+use std::io::Write;
+
+use byteorder::{BigEndian, WriteBytesExt};
+use failure::Error;
+
+use compression::Compressor;
+use literal::LiteralHeader;
+use packet::{Packet, PacketError};
+use types::CompressionAlgorithm;
+
+// The default size of partial body chunks, matching GnuPG.
+const DEFAULT_CHUNK_SIZE: usize = 8192;
+// The largest chunk a partial body length can describe.
+const MAX_CHUNK_SIZE: usize = 1 << 30;
+// The first chunk of a partial body must be at least this long; see RFC4880, Section 4.2.2.4.
+const MIN_CHUNK_SIZE: usize = 512;
+
+/// Write a new-format body length, using the shortest encoding possible.
+pub(crate) fn write_new_format_length<W: Write>(out: &mut W, length: u32) -> io::Result<()> {
+    if length < 192 {
+        out.write_u8(length as u8)
+    } else if length < 8384 {
+        let length = length - 192;
+        out.write_u8((length >> 8) as u8 + 192)?;
+        out.write_u8(length as u8)
+    } else {
+        out.write_u8(255)?;
+        out.write_u32::<BigEndian>(length)
+    }
+}
+
+/// The format of a packet header. See [RFC4880 &sect;4.2].
+///
+/// Old-format headers can only describe packets with tags up to 15, and are what PGP 2.x expects.
+///
+/// [RFC4880 &sect;4.2]: https://tools.ietf.org/html/rfc4880#section-4.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderFormat {
+    Old,
+    New,
+}
+
+/// Write a packet header using the shortest length encoding possible. A `length` of `None` is
+/// only valid for old-format headers, and marks a body extending to the end of the output.
+pub(crate) fn write_header<W: Write>(
+    out: &mut W,
+    format: HeaderFormat,
+    tag: u8,
+    length: Option<u32>,
+) -> Result<(), Error> {
+    match format {
+        HeaderFormat::Old => {
+            if tag > 15 {
+                bail!(PacketError::OldFormatTag { tag });
+            }
+
+            let packet_tag = 0b1000_0000 | (tag << 2);
+            match length {
+                Some(length) if length <= u32::from(u8::MAX) => {
+                    out.write_u8(packet_tag)?;
+                    out.write_u8(length as u8)?;
+                }
+                Some(length) if length <= u32::from(u16::MAX) => {
+                    out.write_u8(packet_tag | 0x1)?;
+                    out.write_u16::<BigEndian>(length as u16)?;
+                }
+                Some(length) => {
+                    out.write_u8(packet_tag | 0x2)?;
+                    out.write_u32::<BigEndian>(length)?;
+                }
+                None => out.write_u8(packet_tag | 0x3)?,
+            }
+        }
+        HeaderFormat::New => match length {
+            Some(length) => {
+                out.write_u8(0b1100_0000 | tag)?;
+                write_new_format_length(out, length)?;
+            }
+            None => bail!(PacketError::IndeterminateLength),
+        },
+    }
+
+    Ok(())
+}
+
+/// A writer for the body of a packet whose length isn't known in advance, framing it with partial
+/// body lengths as specified in [RFC4880 &sect;4.2.2.4].
+///
+/// The body is written in chunks of a fixed power-of-two size, followed by a final chunk holding
+/// whatever is left. [`finish`] must be called to write that final chunk.
+///
+/// [RFC4880 &sect;4.2.2.4]: https://tools.ietf.org/html/rfc4880#section-4.2.2.4
+/// [`finish`]: #method.finish
+pub struct PartialBodyWriter<W: Write> {
+    inner: W,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> PartialBodyWriter<W> {
+    /// Write a new-format header for a packet with the given tag to `inner`, and start writing its
+    /// body in chunks of `chunk_size` octets.
+    ///
+    /// `chunk_size` must be a power of two between 512 and 2^30. Only literal, compressed and
+    /// encrypted data packets may use partial body lengths.
+    pub fn new(mut inner: W, tag: u8, chunk_size: usize) -> Result<PartialBodyWriter<W>, Error> {
+        let valid_size = (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size);
+        if !chunk_size.is_power_of_two() || !valid_size {
+            bail!(PacketError::InvalidChunkSize { size: chunk_size });
+        }
+
+        match tag {
+            8 | 9 | 11 | 18 => {}
+            _ => bail!(PacketError::PartialLengthNotAllowed { tag }),
+        }
+
+        inner.write_u8(0b1100_0000 | tag)?;
+
+        Ok(PartialBodyWriter {
+            inner,
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+        })
+    }
+
+    fn write_chunks(&mut self) -> io::Result<()> {
+        // Always hold back at least one octet, so that the body ends with a fixed-length chunk.
+        let partial_length = 224 + self.chunk_size.trailing_zeros() as u8;
+        let mut written = 0;
+        while self.buffer.len() - written > self.chunk_size {
+            self.inner.write_u8(partial_length)?;
+            self.inner
+                .write_all(&self.buffer[written..written + self.chunk_size])?;
+            written += self.chunk_size;
+        }
+
+        self.buffer.drain(..written);
+        Ok(())
+    }
+
+    /// Write the final chunk of the body, returning the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_chunks()?;
+
+        write_new_format_length(&mut self.inner, self.buffer.len() as u32)?;
+        self.inner.write_all(&self.buffer)?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for PartialBodyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend(buf);
+        self.write_chunks()?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer for a packet body started by a [`PacketWriter`].
+///
+/// [`finish`] must be called once the whole body has been written.
+///
+/// [`PacketWriter`]: struct.PacketWriter.html
+/// [`finish`]: #method.finish
+pub enum BodyWriter<W: Write> {
+    /// A body whose length was given in its header; exactly that many octets must be written.
+    Fixed(W, u32),
+    Partial(PartialBodyWriter<W>),
+    /// A body extending to the end of the output, written with an old-format header.
+    Indeterminate(W),
+}
+
+impl<W: Write> BodyWriter<W> {
+    /// Finish writing the body, returning the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        match self {
+            BodyWriter::Fixed(inner, 0) | BodyWriter::Indeterminate(inner) => Ok(inner),
+            BodyWriter::Fixed(_, remaining) => bail!(PacketError::BodyLengthMismatch { remaining }),
+            BodyWriter::Partial(inner) => inner.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for BodyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            BodyWriter::Fixed(ref mut inner, ref mut remaining) => {
+                if buf.len() > *remaining as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "data exceeds packet body length",
+                    ));
+                }
+
+                let written = inner.write(buf)?;
+                *remaining -= written as u32;
+                Ok(written)
+            }
+            BodyWriter::Partial(ref mut inner) => inner.write(buf),
+            BodyWriter::Indeterminate(ref mut inner) => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            BodyWriter::Fixed(ref mut inner, _) | BodyWriter::Indeterminate(ref mut inner) => {
+                inner.flush()
+            }
+            BodyWriter::Partial(ref mut inner) => inner.flush(),
+        }
+    }
+}
+
+/// A writer for the body of a Compressed Data packet started by a [`PacketWriter`]. The packets
+/// to compress can be written to it with another `PacketWriter`.
+///
+/// [`finish`] must be called once all of the packets have been written.
+///
+/// [`PacketWriter`]: struct.PacketWriter.html
+/// [`finish`]: #method.finish
+pub struct CompressedWriter<W: Write>(Compressor<BodyWriter<W>>);
+
+impl<W: Write> CompressedWriter<W> {
+    /// Finish compressing and writing the body, returning the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        self.0.finish()?.finish()
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// A writer emitting packets to any [`Write`] sink, with control over header formats.
+///
+/// Headers always use the shortest correct length encoding for their format. Bodies that are too
+/// large to build in memory can be streamed with [`literal`], [`compressed`] or [`body`]; when
+/// their length isn't known in advance, new-format headers use partial body lengths and
+/// old-format headers use an indeterminate length, which is only valid for the last packet of the
+/// output.
+///
+/// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+/// [`literal`]: #method.literal
+/// [`compressed`]: #method.compressed
+/// [`body`]: #method.body
+pub struct PacketWriter<W: Write> {
+    inner: W,
+    format: HeaderFormat,
+    chunk_size: usize,
+}
+
+impl<W: Write> PacketWriter<W> {
+    /// Create a packet writer using headers of the given format.
+    pub fn new(inner: W, format: HeaderFormat) -> PacketWriter<W> {
+        PacketWriter {
+            inner,
+            format,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the size of the chunks used for partial body lengths. See [`PartialBodyWriter::new`].
+    ///
+    /// [`PartialBodyWriter::new`]: struct.PartialBodyWriter.html#method.new
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size;
+    }
+
+    /// Write a whole packet.
+    pub fn write_packet(&mut self, packet: &Packet) -> Result<(), Error> {
+        let body = packet.body_to_bytes()?;
+        if body.len() > u32::MAX as usize {
+            bail!(PacketError::BodyTooLong);
+        }
+
+        write_header(&mut self.inner, self.format, packet.packet_tag(), Some(body.len() as u32))?;
+        self.inner.write_all(&body)?;
+
+        Ok(())
+    }
+
+    /// Start streaming the body of a packet with the given tag. If `length` is `None`, the body
+    /// can be of any length.
+    pub fn body(&mut self, tag: u8, length: Option<u32>) -> Result<BodyWriter<&mut W>, Error> {
+        let writer = match (length, self.format) {
+            (Some(length), format) => {
+                write_header(&mut self.inner, format, tag, Some(length))?;
+                BodyWriter::Fixed(&mut self.inner, length)
+            }
+            (None, HeaderFormat::Old) => {
+                write_header(&mut self.inner, HeaderFormat::Old, tag, None)?;
+                BodyWriter::Indeterminate(&mut self.inner)
+            }
+            (None, HeaderFormat::New) => {
+                BodyWriter::Partial(PartialBodyWriter::new(&mut self.inner, tag, self.chunk_size)?)
+            }
+        };
+
+        Ok(writer)
+    }
+
+    /// Start streaming a Literal Data packet. `length` is the length of the literal contents, if
+    /// known.
+    pub fn literal(
+        &mut self,
+        header: &LiteralHeader,
+        length: Option<u32>,
+    ) -> Result<BodyWriter<&mut W>, Error> {
+        let header = header.to_bytes()?;
+        let length = match length {
+            Some(length) => match length.checked_add(header.len() as u32) {
+                Some(length) => Some(length),
+                None => bail!(PacketError::BodyTooLong),
+            },
+            None => None,
+        };
+
+        let mut writer = self.body(11, length)?;
+        writer.write_all(&header)?;
+
+        Ok(writer)
+    }
+
+    /// Start streaming a Compressed Data packet.
+    pub fn compressed(
+        &mut self,
+        algo: CompressionAlgorithm,
+    ) -> Result<CompressedWriter<&mut W>, Error> {
+        let mut writer = self.body(8, None)?;
+        writer.write_u8(algo.into())?;
+
+        Ok(CompressedWriter(Compressor::new(algo, writer)?))
+    }
+
+    /// Retrieve the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Duration;
+
+    use literal::{LiteralData, LiteralFormat, LiteralPacket};
+    use reader::{PacketReader, StreamedPacket};
+
+    const CERTIFICATE: &[u8] = include_bytes!("../tests/data/ed.pub");
+    const MESSAGE: &[u8] = include_bytes!("../tests/data/message.txt");
+
+    const CHUNK_SIZE: usize = 512;
+
+    fn literal_header() -> LiteralHeader {
+        LiteralHeader {
+            format: LiteralFormat::Binary,
+            filename: "message.txt".to_string(),
+            timestamp: Duration::from_secs(1_500_000_000),
+        }
+    }
+
+    // Read the contents of the only literal data packet in some output, descending into
+    // compressed data.
+    fn read_literal(bytes: &[u8]) -> Vec<u8> {
+        let mut reader = PacketReader::new(bytes);
+        let mut contents = Vec::new();
+        match reader.next().unwrap().unwrap() {
+            StreamedPacket::LiteralData(_, mut body) => {
+                body.read_to_end(&mut contents).unwrap();
+            }
+            StreamedPacket::CompressedData(algo, body) => {
+                let mut inner = PacketReader::decompress(algo, body).unwrap();
+                match inner.next().unwrap().unwrap() {
+                    StreamedPacket::LiteralData(_, mut body) => {
+                        body.read_to_end(&mut contents).unwrap();
+                    }
+                    _ => panic!("expected literal data"),
+                }
+                assert!(inner.next().is_none());
+            }
+            _ => panic!("expected literal or compressed data"),
+        }
+        assert!(reader.next().is_none());
+
+        contents
+    }
+
+    // Frame a body the way PartialBodyWriter should: full chunks while more than a chunk remains,
+    // then a fixed-length final chunk.
+    fn expected_partial_body(tag: u8, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0b1100_0000 | tag];
+        let mut remaining = data;
+        while remaining.len() > CHUNK_SIZE {
+            out.push(224 + 9);
+            out.extend(&remaining[..CHUNK_SIZE]);
+            remaining = &remaining[CHUNK_SIZE..];
+        }
+        write_new_format_length(&mut out, remaining.len() as u32).unwrap();
+        out.extend(remaining);
+
+        out
+    }
+
+    #[test]
+    fn partial_body_chunk_boundaries() {
+        for &len in &[0, 1, 511, 512, 513, 1023, 1024, 1025, 1536, 10_000] {
+            let data = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+
+            // Write in pieces that don't line up with the chunks.
+            let mut writer = PartialBodyWriter::new(Vec::new(), 18, CHUNK_SIZE).unwrap();
+            for piece in data.chunks(100) {
+                writer.write_all(piece).unwrap();
+            }
+            let written = writer.finish().unwrap();
+            assert_eq!(written, expected_partial_body(18, &data), "length {}", len);
+
+            let mut reader = PacketReader::new(&written[..]);
+            match reader.next().unwrap().unwrap() {
+                StreamedPacket::SymmetricEncryptedIntegrityProtectedData(mut body) => {
+                    let mut read = Vec::new();
+                    body.read_to_end(&mut read).unwrap();
+                    assert_eq!(read, data, "length {}", len);
+                }
+                _ => panic!("expected integrity protected data"),
+            }
+            assert!(reader.next().is_none());
+        }
+    }
+
+    #[test]
+    fn partial_packet_round_trip() {
+        let literal = LiteralPacket {
+            filename: "data.bin".to_string(),
+            timestamp: Duration::from_secs(1_500_000_000),
+            contents: LiteralData::Binary(vec![0x5A; 2 * CHUNK_SIZE - 14]),
+        };
+        let packet = Packet::LiteralData(literal.clone());
+
+        // The literal header makes the body exactly two chunks long.
+        let bytes = packet.to_partial_bytes(CHUNK_SIZE).unwrap();
+        assert_eq!(bytes[1], 224 + 9);
+        assert_eq!(bytes.len(), 1 + 1 + CHUNK_SIZE + 2 + CHUNK_SIZE);
+
+        match Packet::from_bytes(&bytes).unwrap() {
+            (Packet::LiteralData(read), remaining) => {
+                assert_eq!(read.to_bytes().unwrap(), literal.to_bytes().unwrap());
+                assert!(remaining.is_empty());
+            }
+            p => panic!("expected literal data, found {:?}", p),
+        }
+    }
+
+    #[test]
+    fn reject_invalid_chunk_sizes() {
+        for &size in &[0, 256, 1000, 1 << 31] {
+            let err = PartialBodyWriter::new(Vec::new(), 11, size).err().unwrap();
+            let expected = PacketError::InvalidChunkSize { size };
+            assert_eq!(err.to_string(), expected.to_string());
+        }
+    }
+
+    #[test]
+    fn reject_partial_length_tags() {
+        let err = PartialBodyWriter::new(Vec::new(), 2, CHUNK_SIZE).err().unwrap();
+        let expected = PacketError::PartialLengthNotAllowed { tag: 2 };
+        assert_eq!(err.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn write_old_format_like_gnupg() {
+        let mut writer = PacketWriter::new(Vec::new(), HeaderFormat::Old);
+        for packet in Packet::all_from_bytes(CERTIFICATE).unwrap() {
+            writer.write_packet(&packet).unwrap();
+        }
+
+        assert_eq!(writer.into_inner(), CERTIFICATE);
+    }
+
+    #[test]
+    fn write_new_format() {
+        let packets = Packet::all_from_bytes(CERTIFICATE).unwrap();
+        let mut writer = PacketWriter::new(Vec::new(), HeaderFormat::New);
+        for packet in &packets {
+            writer.write_packet(packet).unwrap();
+        }
+
+        let written = writer.into_inner();
+        assert_eq!(written[0], 0b1100_0110);
+        assert_eq!(written.len(), CERTIFICATE.len());
+
+        let read = Packet::all_from_bytes(&written).unwrap();
+        assert_eq!(read.len(), packets.len());
+        for (read, packet) in read.iter().zip(&packets) {
+            assert_eq!(read.to_bytes().unwrap(), packet.to_bytes().unwrap());
+        }
+    }
+
+    #[test]
+    fn reject_old_format_tag() {
+        let mut writer = PacketWriter::new(Vec::new(), HeaderFormat::Old);
+        let err = writer.body(18, Some(0)).err().unwrap();
+        assert_eq!(err.to_string(), PacketError::OldFormatTag { tag: 18 }.to_string());
+    }
+
+    #[test]
+    fn stream_literal() {
+        for &(format, length) in &[
+            (HeaderFormat::Old, Some(MESSAGE.len() as u32)),
+            (HeaderFormat::Old, None),
+            (HeaderFormat::New, Some(MESSAGE.len() as u32)),
+            (HeaderFormat::New, None),
+        ] {
+            let mut writer = PacketWriter::new(Vec::new(), format);
+            {
+                let mut body = writer.literal(&literal_header(), length).unwrap();
+                body.write_all(MESSAGE).unwrap();
+                body.finish().unwrap();
+            }
+
+            assert_eq!(read_literal(&writer.into_inner()), MESSAGE);
+        }
+    }
+
+    #[test]
+    fn stream_compressed_literal() {
+        let data = MESSAGE.repeat(1000);
+
+        let mut writer = PacketWriter::new(Vec::new(), HeaderFormat::New);
+        writer.set_chunk_size(CHUNK_SIZE);
+        {
+            let compressed = writer.compressed(CompressionAlgorithm::Zlib).unwrap();
+            let mut inner = PacketWriter::new(compressed, HeaderFormat::New);
+            {
+                let mut body = inner.literal(&literal_header(), None).unwrap();
+                body.write_all(&data).unwrap();
+                body.finish().unwrap();
+            }
+            inner.into_inner().finish().unwrap();
+        }
+
+        let written = writer.into_inner();
+        assert!(written.len() < data.len() / 10);
+        assert_eq!(read_literal(&written), data);
+    }
+
+    #[test]
+    fn fixed_length_mismatch() {
+        let mut writer = PacketWriter::new(Vec::new(), HeaderFormat::New);
+        let mut body = writer.literal(&literal_header(), Some(4)).unwrap();
+        assert!(body.write_all(MESSAGE).is_err());
+
+        let mut writer = PacketWriter::new(Vec::new(), HeaderFormat::New);
+        let mut body = writer.literal(&literal_header(), Some(20)).unwrap();
+        body.write_all(MESSAGE).unwrap();
+        let err = body.finish().err().unwrap();
+        assert_eq!(err.to_string(), PacketError::BodyLengthMismatch { remaining: 6 }.to_string());
+    }
+}